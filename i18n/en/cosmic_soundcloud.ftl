@@ -14,3 +14,15 @@ no-results = No results found.
 load-more = Load More
 loading = Loading...
 queue = Queue
+settings = Settings
+crossfade = Crossfade
+normalization = Loudness Normalization
+equalizer = Equalizer
+now-playing = Now Playing
+sleep-timer = Sleep Timer
+stream-quality = Stream Quality
+output-device = Output Device
+volume-control = Volume Control
+audio-cache = Audio Cache
+stream-sources = Stream Sources
+export-folder = Export Folder
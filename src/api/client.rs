@@ -3,7 +3,10 @@
 use reqwest::Client;
 use std::fmt;
 
-use super::types::{Album, AlbumsResponse, LikesResponse, Playlist, StreamUrlResponse, Track, TracksResponse, User, UsersSearchResponse};
+use super::types::{
+    Album, AlbumsResponse, LikesResponse, Playlist, StreamUrlResponse, Track, Transcoding, TracksResponse, User,
+    UsersSearchResponse,
+};
 
 const SOUNDCLOUD_API_V2: &str = "https://api-v2.soundcloud.com";
 const DEFAULT_CLIENT_ID: &str = "FPh1fGfGpygQyivIKoNCi4d6d490BOvt";
@@ -412,32 +415,8 @@ impl SoundCloudClient {
         Ok(playlists)
     }
 
-    /// Get the actual stream URL for a track
-    pub async fn get_stream_url(&self, track: &Track) -> Result<String, ApiError> {
-        // Debug: print all available transcodings
-        if let Some(media) = &track.media {
-            eprintln!("Available transcodings for '{}':", track.title);
-            for t in &media.transcodings {
-                eprintln!("  - protocol: {}, mime: {}, url: {}...",
-                    t.format.protocol,
-                    t.format.mime_type,
-                    &t.url[..t.url.len().min(80)]);
-            }
-        }
-
-        // Use encrypted HLS (only working option since Dec 2025)
-        let transcoding = track
-            .best_transcoding()
-            .ok_or(ApiError::NoStreamUrl)?;
-
-        eprintln!("Selected transcoding: {}", &transcoding.url[..transcoding.url.len().min(100)]);
-
-        // Get track authorization token
-        let track_auth = track
-            .track_authorization
-            .as_ref()
-            .ok_or(ApiError::NoStreamUrl)?;
-
+    /// Ask SoundCloud for the stream URL behind one transcoding
+    pub async fn resolve_transcoding(&self, transcoding: &Transcoding, track_auth: &str) -> Result<String, ApiError> {
         // The transcoding URL returns a redirect to the actual stream
         let url = format!(
             "{}?client_id={}&track_authorization={}",
@@ -451,6 +430,11 @@ impl SoundCloudClient {
             .send()
             .await?;
 
+        if response.status() == 401 {
+            return Err(ApiError::Unauthorized);
+        }
+        let response = response.error_for_status()?;
+
         let text = response.text().await?;
         let stream_response: StreamUrlResponse = serde_json::from_str(&text)
             .map_err(|e| ApiError::Json(e.to_string()))?;
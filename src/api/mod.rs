@@ -2,6 +2,9 @@
 
 mod client;
 mod types;
+pub mod waveform;
 
-pub use client::SoundCloudClient;
-pub use types::{Album, Playlist, Track, User};
+pub use client::{ApiError, SoundCloudClient};
+pub use types::{
+    Album, AudioCodec, Playlist, StreamFormat, StreamPreference, Track, Transcoding, TranscodingFormat, User,
+};
@@ -43,6 +43,150 @@ pub struct Transcoding {
     pub format: TranscodingFormat,
     #[serde(default)]
     pub quality: Option<String>,
+    /// Encoding preset like `aac_160k` or `mp3_1_0`
+    #[serde(default)]
+    pub preset: Option<String>,
+}
+
+impl Transcoding {
+    /// Encrypted HLS, which only plays through the yt-dlp fallback
+    pub fn is_encrypted(&self) -> bool {
+        self.url.contains("encrypted-hls")
+    }
+
+    pub fn codec(&self) -> Option<AudioCodec> {
+        AudioCodec::from_mime_type(&self.format.mime_type)
+    }
+
+    /// SoundCloud's high quality tier (Go+)
+    pub fn is_high_quality(&self) -> bool {
+        self.quality.as_deref() == Some("hq")
+    }
+
+    /// Bitrate in kbps, from the preset where it says, otherwise what
+    /// SoundCloud serves for the codec and tier
+    pub fn bitrate(&self) -> u32 {
+        let from_preset = self.preset.as_deref().and_then(|preset| {
+            preset
+                .split('_')
+                .find_map(|part| part.strip_suffix('k')?.parse().ok())
+        });
+        from_preset.unwrap_or(match self.codec() {
+            Some(AudioCodec::Mp3) => 128,
+            Some(AudioCodec::Opus) => 64,
+            _ if self.is_high_quality() => 256,
+            _ => 160,
+        })
+    }
+}
+
+/// Audio codec of a stream, as far as the player cares
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AudioCodec {
+    Opus,
+    Aac,
+    Mp3,
+}
+
+impl AudioCodec {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Opus => "Opus",
+            Self::Aac => "AAC",
+            Self::Mp3 => "MP3",
+        }
+    }
+
+    /// Detect the codec from an HLS `CODECS` attribute (e.g. `mp4a.40.2`)
+    pub fn from_codecs(codecs: &str) -> Option<Self> {
+        let codecs = codecs.to_ascii_lowercase();
+        if codecs.contains("opus") {
+            Some(Self::Opus)
+        } else if codecs.contains("mp4a.40.34") || codecs.contains("mp4a.6b") || codecs.contains("mp3") {
+            Some(Self::Mp3)
+        } else if codecs.contains("mp4a") {
+            Some(Self::Aac)
+        } else {
+            None
+        }
+    }
+
+    /// Detect the codec from a transcoding MIME type (e.g. `audio/ogg; codecs="opus"`)
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        let mime_type = mime_type.to_ascii_lowercase();
+        if mime_type.contains("opus") {
+            Some(Self::Opus)
+        } else if mime_type.starts_with("audio/mpeg") {
+            Some(Self::Mp3)
+        } else if mime_type.starts_with("audio/mp4") || mime_type.contains("aac") {
+            Some(Self::Aac)
+        } else {
+            None
+        }
+    }
+}
+
+/// Which stream variant the player picks when several are offered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum StreamPreference {
+    /// Highest bitrate available
+    #[default]
+    HighestQuality,
+    /// Lowest bitrate available
+    DataSaver,
+    /// Highest bitrate in this codec, falling back to other codecs. Opus
+    /// isn't offered, as it can't be played.
+    Codec(AudioCodec),
+}
+
+impl StreamPreference {
+    pub fn all() -> &'static [StreamPreference] {
+        &[
+            Self::HighestQuality,
+            Self::DataSaver,
+            Self::Codec(AudioCodec::Aac),
+            Self::Codec(AudioCodec::Mp3),
+        ]
+    }
+
+    pub fn all_labels() -> &'static [&'static str] {
+        &["Highest quality", "Data saver", "Prefer AAC", "Prefer MP3"]
+    }
+}
+
+/// Codec and quality of the stream that's playing, for the player bar
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamFormat {
+    pub codec: Option<AudioCodec>,
+    /// SoundCloud's quality tier (`sq` or `hq`)
+    pub quality: Option<String>,
+    /// Bandwidth of the HLS variant in bits per second
+    pub bitrate: Option<u64>,
+}
+
+impl StreamFormat {
+    pub fn of(transcoding: &Transcoding) -> Self {
+        Self {
+            codec: transcoding.codec(),
+            quality: transcoding.quality.clone(),
+            bitrate: None,
+        }
+    }
+
+    /// Short description like "AAC · 160 kbps", empty if nothing is known
+    pub fn label(&self) -> String {
+        let quality = match (self.bitrate, self.quality.as_deref()) {
+            (Some(bitrate), _) => Some(format!("{} kbps", bitrate / 1000)),
+            (None, Some(quality)) => Some(quality.to_ascii_uppercase()),
+            (None, None) => None,
+        };
+        self.codec
+            .map(|codec| codec.name().to_string())
+            .into_iter()
+            .chain(quality)
+            .collect::<Vec<_>>()
+            .join(" · ")
+    }
 }
 
 /// Media container with transcoding options
@@ -63,6 +207,8 @@ pub struct Track {
     #[serde(default)]
     pub user: TrackUser,
     pub artwork_url: Option<String>,
+    /// JSON peak heights for drawing the track's waveform
+    pub waveform_url: Option<String>,
     /// Duration in milliseconds
     #[serde(default)]
     pub duration: u64,
@@ -91,57 +237,35 @@ impl Track {
         !self.title.is_empty() && !self.user.username.is_empty()
     }
 
-    /// Find progressive (direct) stream transcoding, preferring MP3 over MP4
-    pub fn progressive_transcoding(&self) -> Option<&Transcoding> {
-        let transcodings = &self.media.as_ref()?.transcodings;
-        // Prefer MP3 (audio/mpeg) as it's simpler to decode
-        transcodings
-            .iter()
-            .find(|t| t.format.protocol == "progressive" && t.format.mime_type.contains("mpeg"))
-            .or_else(|| {
-                transcodings
-                    .iter()
-                    .find(|t| t.format.protocol == "progressive")
-            })
-    }
-
-    /// Find HLS stream transcoding (plain, non-encrypted), preferring MP3 over MP4/fMP4
-    pub fn hls_transcoding(&self) -> Option<&Transcoding> {
-        let transcodings = &self.media.as_ref()?.transcodings;
-        // Prefer MP3 (audio/mpeg) as fMP4 (audio/mp4) has decoding issues
-        transcodings
-            .iter()
-            .find(|t| t.format.protocol == "hls" && t.format.mime_type.contains("mpeg"))
-            .or_else(|| {
-                transcodings
-                    .iter()
-                    .find(|t| t.format.protocol == "hls" && !t.url.contains("encrypted"))
-            })
-    }
-
-    /// Find encrypted HLS stream (ctr-encrypted-hls or cbc-encrypted-hls)
-    /// These are the only working streams as of 2026
-    pub fn encrypted_hls_transcoding(&self) -> Option<&Transcoding> {
-        self.media
-            .as_ref()?
+    /// Transcodings to try in order, best match for `preference` first.
+    /// Plain HLS and progressive streams come before encrypted HLS, which
+    /// needs yt-dlp, and HLS before progressive as it's more reliable. Opus
+    /// transcodings are left out since there's no decoder for them.
+    pub fn ranked_transcodings(&self, preference: StreamPreference) -> Vec<&Transcoding> {
+        let Some(media) = &self.media else {
+            return Vec::new();
+        };
+        let mut transcodings: Vec<&Transcoding> = media
             .transcodings
             .iter()
-            .find(|t| t.url.contains("ctr-encrypted-hls") || t.url.contains("cbc-encrypted-hls"))
-    }
+            .filter(|t| t.codec() != Some(AudioCodec::Opus))
+            .collect();
 
-    /// Get best available transcoding
-    /// Prefers: hls (pre-buffered) > progressive > encrypted hls
-    pub fn best_transcoding(&self) -> Option<&Transcoding> {
-        // Try plain HLS first (downloads segments then plays from memory - most reliable)
-        if let Some(t) = self.hls_transcoding() {
-            return Some(t);
-        }
-        // Then progressive (direct streaming - can have buffering issues)
-        if let Some(t) = self.progressive_transcoding() {
-            return Some(t);
-        }
-        // Fall back to encrypted HLS (requires yt-dlp fallback)
-        self.encrypted_hls_transcoding()
+        // AAC is the better sounding codec at SoundCloud's bitrates
+        let codec_rank = |t: &Transcoding| match t.codec() {
+            Some(AudioCodec::Aac) => 0,
+            Some(AudioCodec::Mp3) => 1,
+            _ => 2,
+        };
+        transcodings.sort_by_key(|t| {
+            let preference_rank = match preference {
+                StreamPreference::HighestQuality => (u32::from(!t.is_high_quality()), codec_rank(t)),
+                StreamPreference::DataSaver => (t.bitrate(), codec_rank(t)),
+                StreamPreference::Codec(codec) => (u32::from(t.codec() != Some(codec)), codec_rank(t)),
+            };
+            (t.is_encrypted(), preference_rank, t.format.protocol != "hls")
+        });
+        transcodings
     }
 }
 
@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Track waveforms, as drawn in SoundCloud's player.
+//!
+//! A track's `waveform_url` points at JSON with one peak height per column of
+//! SoundCloud's own drawing. Waveforms never change, so the JSON is kept in
+//! `~/.cache/cosmic-soundcloud/waveforms/` once fetched.
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Number of bars a waveform is reduced to
+pub const BAR_COUNT: usize = 120;
+
+/// Bar heights of a track's waveform, from 0.0 to 1.0
+pub type Waveform = Vec<f32>;
+
+#[derive(Deserialize)]
+struct WaveformData {
+    samples: Vec<u32>,
+}
+
+fn cache_path(track_id: u64) -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| {
+        d.join("cosmic-soundcloud")
+            .join("waveforms")
+            .join(format!("{track_id}.json"))
+    })
+}
+
+/// Load a track's waveform from the cache, or fetch it from `url`
+pub async fn load(track_id: u64, url: &str) -> Result<Waveform, String> {
+    let path = cache_path(track_id);
+    if let Some(path) = &path
+        && let Ok(json) = tokio::fs::read(path).await
+        && let Ok(waveform) = parse(&json)
+    {
+        return Ok(waveform);
+    }
+
+    let json = reqwest::get(json_url(url))
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Failed to fetch waveform: {e}"))?
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to fetch waveform: {e}"))?;
+    let waveform = parse(&json)?;
+
+    if let Some(path) = path {
+        let written = match path.parent() {
+            Some(dir) => tokio::fs::create_dir_all(dir).await,
+            None => Ok(()),
+        };
+        if let Err(e) = written.and(tokio::fs::write(&path, &json).await) {
+            eprintln!("[waveform] Failed to cache {}: {e}", path.display());
+        }
+    }
+    Ok(waveform)
+}
+
+/// Older tracks link a PNG drawing; the same waveform is served as JSON
+fn json_url(url: &str) -> String {
+    match url.strip_suffix(".png") {
+        Some(stem) => format!("{}.json", stem.replacen("://w1.", "://wave.", 1)),
+        None => url.to_string(),
+    }
+}
+
+/// Reduce the waveform to `BAR_COUNT` bars, each the peak of its columns,
+/// scaled so the loudest bar is full height
+fn parse(json: &[u8]) -> Result<Waveform, String> {
+    let data: WaveformData =
+        serde_json::from_slice(json).map_err(|e| format!("Invalid waveform: {e}"))?;
+    if data.samples.is_empty() {
+        return Err("Empty waveform".into());
+    }
+
+    let bars: Vec<u32> = (0..BAR_COUNT)
+        .map(|bar| {
+            let start = bar * data.samples.len() / BAR_COUNT;
+            let end = ((bar + 1) * data.samples.len() / BAR_COUNT).max(start + 1);
+            data.samples[start..end]
+                .iter()
+                .copied()
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let peak = bars.iter().copied().max().unwrap_or(0).max(1);
+    Ok(bars.into_iter().map(|bar| bar as f32 / peak as f32).collect())
+}
@@ -1,8 +1,14 @@
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::api::{Album, Playlist, SoundCloudClient, Track, User};
-use crate::audio::{open_in_browser, system_volume, AudioCommand, AudioEvent, AudioPlayer};
-use crate::config::{Config, RecentArtist};
+use crate::api::waveform::{self, Waveform};
+use crate::api::{Album, ApiError, Playlist, SoundCloudClient, StreamFormat, StreamPreference, Track, User};
+use crate::audio::export;
+use crate::audio::offline::{self, OfflineStore, Pin};
+use crate::audio::{
+    open_in_browser, system_volume, AudioCommand, AudioEvent, AudioPlayer, Crossfade, Decline, Declined,
+    Normalization, Resolved, ResolverChain, ResolverKind, YtDlp, YtDlpError, BAND_FREQUENCIES,
+};
+use crate::config::{Config, EqualizerPreset, NormalizationMode, RecentArtist, VolumeMode};
 use crate::fl;
 use crate::keyring;
 use cosmic::app::context_drawer;
@@ -14,9 +20,17 @@ use cosmic::iced::{Alignment, Length, Subscription};
 use cosmic::widget::{self, about::About, icon, image, menu, nav_bar, segmented_button};
 use cosmic::{iced_futures, prelude::*, Element};
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
 const REPOSITORY: &str = env!("CARGO_PKG_REPOSITORY");
+/// Sleep timer lengths offered as presets (minutes)
+const SLEEP_TIMER_PRESETS: [u64; 3] = [15, 30, 60];
+/// Longest sleep timer that can be set (minutes)
+const SLEEP_TIMER_MAX_MINUTES: u64 = 24 * 60;
+/// How long playback fades out when the sleep timer runs out (seconds)
+const SLEEP_FADE_SECS: f32 = 8.0;
 const APP_ICON: &[u8] = include_bytes!("../resources/icons/hicolor/scalable/apps/com.github.orta.cosmic-soundcloud.svg");
 
 /// Format a number with comma separators (e.g., 1234567 -> "1,234,567")
@@ -122,6 +136,14 @@ pub enum PlaybackStatus {
     Buffering,
 }
 
+/// When the sleep timer fades out and pauses playback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepTimer {
+    At(Instant),
+    /// As the current track ends, before the next one starts
+    EndOfTrack,
+}
+
 /// Library tab selection
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum LibraryTab {
@@ -133,6 +155,7 @@ pub enum LibraryTab {
     Stations,
     Following,
     History,
+    Offline,
 }
 
 impl LibraryTab {
@@ -145,6 +168,7 @@ impl LibraryTab {
             Self::Stations => "Stations",
             Self::Following => "Following",
             Self::History => "History",
+            Self::Offline => "Offline",
         }
     }
 
@@ -157,6 +181,7 @@ impl LibraryTab {
             Self::Stations,
             Self::Following,
             Self::History,
+            Self::Offline,
         ]
     }
 }
@@ -277,15 +302,64 @@ pub struct AppModel {
     volume: f32,
     /// Current playback position in seconds
     playback_position: f32,
+    /// Position the user is dragging the seek slider to (applied on release)
+    seek_preview: Option<f32>,
+    /// Fraction of the waveform the pointer is over
+    waveform_cursor: Option<f32>,
+    /// Fraction of the current track's HLS segments downloaded, while in progress
+    download_progress: Option<f32>,
+    /// Codec and quality the player reported for the current track
+    stream_format: Option<StreamFormat>,
+    /// Chain position and kind of the resolver the current track plays from
+    stream_resolver: Option<(usize, ResolverKind)>,
+    /// Resolver run for the current track, aborted when another track starts
+    stream_resolving: Option<cosmic::iced::task::Handle>,
+    /// Why resolvers passed on the current track so far
+    stream_declined: Vec<Declined>,
+    /// Why the current track can't play, for the player bar
+    stream_error: Option<String>,
+    /// Latest spectrum frame, while the visualizer is shown
+    spectrum: Vec<f32>,
+    /// Running sleep timer
+    sleep_timer: Option<SleepTimer>,
+    /// Custom sleep timer length being typed (minutes)
+    sleep_timer_input: String,
+    /// yt-dlp path being typed in settings
+    ytdlp_path_input: String,
+    /// Version of the configured yt-dlp, or why it won't run (None while checking)
+    ytdlp_version: Option<Result<String, YtDlpError>>,
+    /// Tracks being exported to files
+    exporting: HashSet<u64>,
+    /// Export folder being typed in settings
+    export_dir_input: String,
     /// Whether preloading has been triggered for the current track
     preload_triggered: bool,
     /// Track ID that has been preloaded into the disk cache
     preloaded_track_id: Option<u64>,
+    /// Current playlist was loaded with `PlayAlbum` (crossfade stays off)
+    album_playback: bool,
+    /// Output device choices: "System default", then the available devices
+    output_devices: Vec<String>,
+    /// Device the player is currently using
+    current_output_device: Option<String>,
+
+    // === Offline Store ===
+    offline: OfflineStore,
+    /// Pinned track being downloaded
+    offline_downloading: Option<u64>,
+    /// Pinned tracks whose download failed this session, skipped until restart
+    offline_failed: HashSet<u64>,
+    /// All likes are being fetched to pin them
+    offline_likes_syncing: bool,
 
     // === Artwork Cache ===
     artwork_cache: HashMap<String, image::Handle>,
     artwork_loading: HashSet<String>,
 
+    // === Waveform Cache ===
+    waveforms: HashMap<u64, Waveform>,
+    waveform_loading: HashSet<u64>,
+
     // === Artist Page State ===
     current_page: Page,
     artist_user: Option<User>,
@@ -317,6 +391,8 @@ pub enum Message {
     SubmitToken,
     Logout,
     UserLoaded(Result<User, String>),
+    /// The API couldn't be reached at startup
+    NetworkUnavailable(String),
 
     // Library Navigation
     SwitchTab(segmented_button::Entity),
@@ -338,20 +414,54 @@ pub enum Message {
     // Audio Player
     AudioReady(mpsc::Sender<AudioCommand>),
     AudioEvent(AudioEvent),
-    StreamUrlLoaded(Result<String, String>),
+    /// Where the stream resolvers found a track, or why none of them did
+    StreamResolved(u64, Result<Resolved, Vec<Declined>>),
     TogglePlayPause,
     NextTrack,
     PreviousTrack,
     SetVolume(f32),
+    /// A settings slider was released: save what it changed
+    SaveConfig,
+    SeekPreview(f32),
+    SeekCommit,
+    /// Pointer moved over the waveform, to this fraction of its width
+    WaveformCursor(f32),
+    /// Waveform pressed, starting a seek drag at the pointer
+    WaveformPressed,
+    /// Start the sleep timer for this many minutes
+    StartSleepTimer(u64),
+    StartCustomSleepTimer,
+    SleepTimerInput(String),
+    SleepAtEndOfTrack,
+    CancelSleepTimer,
+    SleepTimerTick,
+    SetCrossfade(f32),
+    SetNormalization(usize),
+    SetEqualizerPreset(usize),
+    SetStreamPreference(usize),
+    SetOutputDevice(usize),
+    SetVolumeMode(usize),
+    SetCacheLimit(f32),
+    /// Cache slider released: save the limit and evict down to it
+    CommitCacheLimit,
+    /// Move a stream source up (`true`) or down the resolver order
+    MoveStreamSource(usize, bool),
+    YtDlpPathInput(String),
+    SetYtDlpPath,
+    YtDlpChecked(Result<String, YtDlpError>),
+    SetEqualizerBand(usize, f32),
 
     // Preloading
     PreloadNextTrack,
-    PreloadStreamUrlLoaded(u64, Result<String, String>),
+    PreloadStreamResolved(u64, Result<Resolved, Vec<Declined>>),
 
     // Artwork
     LoadArtwork(String),
     ArtworkLoaded(String, Vec<u8>),
 
+    // Waveforms
+    WaveformLoaded(u64, Result<Waveform, String>),
+
     // Artist Navigation
     NavigateToArtist(u64, String, Option<String>), // id, username, avatar_url
     NavigateToLibrary,
@@ -366,6 +476,21 @@ pub enum Message {
     PlayAlbum(u64),                              // album_id - load tracks and play
     AlbumTracksLoaded(Result<Vec<Track>, String>),
 
+    // Offline Store
+    ToggleOfflineTrack(Track),
+    ToggleOfflineAlbum(u64),
+    OfflineAlbumTracksLoaded(u64, Result<Vec<Track>, String>),
+    ToggleOfflineLikes,
+    SyncOfflineLikes,
+    OfflineLikesLoaded(Result<Vec<Track>, String>),
+    OfflineTrackDownloaded(u64, Result<u64, String>),
+
+    // Export
+    ExportTrack(Track),
+    TrackExported(u64, Result<PathBuf, String>),
+    ExportDirInput(String),
+    SetExportDir,
+
     // Search
     SearchQueryInput(String),
     SubmitSearch,
@@ -430,8 +555,11 @@ impl cosmic::Application for AppModel {
             })
             .unwrap_or_default();
 
-        // Get system volume, fallback to config volume if unavailable
-        let volume = system_volume::get_volume().unwrap_or(config.volume);
+        // In system mode, start from the system volume if it can be read
+        let volume = match config.volume_mode {
+            VolumeMode::App => config.volume,
+            VolumeMode::System => system_volume::get_volume().unwrap_or(config.volume),
+        };
 
         // Check if we have a saved token in keyring
         // Also migrate any token from old config storage to keyring
@@ -475,6 +603,9 @@ impl cosmic::Application for AppModel {
             }
         };
 
+        let ytdlp_path_input = config.ytdlp_path.clone().unwrap_or_default();
+        let export_dir_input = config.export_dir.clone().unwrap_or_default();
+
         let mut app = AppModel {
             core,
             context_page: ContextPage::default(),
@@ -496,10 +627,34 @@ impl cosmic::Application for AppModel {
             playlist_index: 0,
             volume,
             playback_position: 0.0,
+            seek_preview: None,
+            waveform_cursor: None,
+            download_progress: None,
+            stream_format: None,
+            stream_resolver: None,
+            stream_resolving: None,
+            stream_declined: Vec::new(),
+            stream_error: None,
+            spectrum: Vec::new(),
+            sleep_timer: None,
+            sleep_timer_input: String::new(),
+            ytdlp_path_input,
+            ytdlp_version: None,
+            exporting: HashSet::new(),
+            export_dir_input,
             preload_triggered: false,
+            album_playback: false,
+            output_devices: vec!["System default".to_string()],
+            current_output_device: None,
+            offline: OfflineStore::load(),
+            offline_downloading: None,
+            offline_failed: HashSet::new(),
+            offline_likes_syncing: false,
             preloaded_track_id: None,
             artwork_cache: HashMap::new(),
             artwork_loading: HashSet::new(),
+            waveforms: HashMap::new(),
+            waveform_loading: HashSet::new(),
             // Artist page state
             current_page: Page::Library,
             artist_user: None,
@@ -526,12 +681,22 @@ impl cosmic::Application for AppModel {
         // Rebuild nav to include recent artists from config
         app.rebuild_nav();
 
+        // Pinned tracks show their artwork without the network
+        for (url, path) in app.offline.artwork() {
+            app.artwork_cache
+                .entry(url)
+                .or_insert_with(|| image::Handle::from_path(path));
+        }
+
         // If we have a token, fetch user info
         let command = if app.api_client.is_some() {
             let client = app.api_client.clone().unwrap();
             cosmic::task::future(async move {
                 match client.get_me().await {
                     Ok(user) => Message::UserLoaded(Ok(user)),
+                    Err(ApiError::Http(e)) if e.is_connect() || e.is_timeout() => {
+                        Message::NetworkUnavailable(e.to_string())
+                    }
                     Err(e) => Message::UserLoaded(Err(e.to_string())),
                 }
             })
@@ -539,6 +704,7 @@ impl cosmic::Application for AppModel {
         } else {
             app.update_title()
         };
+        let command = cosmic::task::batch([command, app.check_ytdlp()]);
 
         (app, command)
     }
@@ -550,6 +716,12 @@ impl cosmic::Application for AppModel {
     fn header_end(&self) -> Vec<Element<'_, Self::Message>> {
         let mut elements = Vec::new();
 
+        elements.push(
+            widget::button::icon(widget::icon::from_name("preferences-system-symbolic"))
+                .on_press(Message::ToggleContextPage(ContextPage::Settings))
+                .into(),
+        );
+
         if let Some(user) = &self.current_user {
             // Show user's avatar if loaded, otherwise default icon
             let avatar: Element<_> = if let Some(avatar_url) = &user.avatar_url {
@@ -602,6 +774,26 @@ impl cosmic::Application for AppModel {
                 Message::ToggleContextPage(ContextPage::Queue),
             )
             .title(fl!("queue")),
+            ContextPage::Settings => context_drawer::context_drawer(
+                self.view_settings(),
+                Message::ToggleContextPage(ContextPage::Settings),
+            )
+            .title(fl!("settings")),
+            ContextPage::Equalizer => context_drawer::context_drawer(
+                self.view_equalizer(),
+                Message::ToggleContextPage(ContextPage::Equalizer),
+            )
+            .title(fl!("equalizer")),
+            ContextPage::NowPlaying => context_drawer::context_drawer(
+                self.view_now_playing(),
+                Message::ToggleContextPage(ContextPage::NowPlaying),
+            )
+            .title(fl!("now-playing")),
+            ContextPage::SleepTimer => context_drawer::context_drawer(
+                self.view_sleep_timer(),
+                Message::ToggleContextPage(ContextPage::SleepTimer),
+            )
+            .title(fl!("sleep-timer")),
         })
     }
 
@@ -644,6 +836,13 @@ impl cosmic::Application for AppModel {
             })
         }));
 
+        // Count down a running sleep timer
+        if matches!(self.sleep_timer, Some(SleepTimer::At(_))) {
+            subscriptions.push(
+                cosmic::iced::time::every(Duration::from_secs(1)).map(|_| Message::SleepTimerTick),
+            );
+        }
+
         // Keyboard shortcuts for media controls
         subscriptions.push(keyboard::on_key_press(|key, _modifiers| {
             match key.as_ref() {
@@ -665,16 +864,61 @@ impl cosmic::Application for AppModel {
     fn update(&mut self, message: Self::Message) -> Task<cosmic::Action<Self::Message>> {
         match message {
             Message::ToggleContextPage(context_page) => {
+                let visualizer_was_visible = self.visualizer_visible();
                 if self.context_page == context_page {
                     self.core.window.show_context = !self.core.window.show_context;
                 } else {
                     self.context_page = context_page;
                     self.core.window.show_context = true;
                 }
+                // Refresh the device list whenever settings are shown
+                if context_page == ContextPage::Settings
+                    && self.core.window.show_context
+                    && let Some(tx) = &self.audio_cmd_tx
+                {
+                    let _ = tx.blocking_send(AudioCommand::ListOutputDevices);
+                }
+                // Only analyse the audio while someone is looking at it
+                if self.visualizer_visible() != visualizer_was_visible {
+                    self.send_visualizer();
+                }
             }
 
             Message::UpdateConfig(config) => {
+                let normalization_changed = config.normalization != self.config.normalization;
+                let equalizer_changed = config.equalizer_gains() != self.config.equalizer_gains();
+                let stream_preference_changed =
+                    config.stream_preference != self.config.stream_preference;
+                let output_device_changed = config.output_device != self.config.output_device;
+                let volume_mode_changed = config.volume_mode != self.config.volume_mode;
+                let cache_limit_changed = config.cache_limit_mb != self.config.cache_limit_mb;
+                let ytdlp_path_changed = config.ytdlp_path != self.config.ytdlp_path;
+                if config.export_dir != self.config.export_dir {
+                    self.export_dir_input = config.export_dir.clone().unwrap_or_default();
+                }
                 self.config = config;
+                if normalization_changed {
+                    self.send_normalization();
+                }
+                if equalizer_changed {
+                    self.send_equalizer();
+                }
+                if stream_preference_changed {
+                    self.send_stream_preference();
+                }
+                if output_device_changed {
+                    self.send_output_device();
+                }
+                if volume_mode_changed {
+                    self.apply_volume_mode();
+                }
+                if cache_limit_changed {
+                    self.send_cache_limit();
+                }
+                if ytdlp_path_changed {
+                    self.ytdlp_path_input = self.config.ytdlp_path.clone().unwrap_or_default();
+                    return self.check_ytdlp();
+                }
             }
 
             Message::LaunchUrl(url) => {
@@ -746,13 +990,22 @@ impl cosmic::Application for AppModel {
                 if let Some(tx) = &self.audio_cmd_tx {
                     let _ = tx.blocking_send(AudioCommand::Stop);
                 }
+                if let Some(resolving) = self.stream_resolving.take() {
+                    resolving.abort();
+                }
                 self.playback_status = PlaybackStatus::Stopped;
                 self.current_track = None;
                 self.preload_triggered = false;
                 self.preloaded_track_id = None;
 
-                // Clear preloaded audio cache
-                crate::audio::cache::clear_cache();
+                // Clear preloaded audio cache and offline downloads
+                if let Some(tx) = &self.audio_cmd_tx {
+                    let _ = tx.blocking_send(AudioCommand::ClearCache);
+                }
+                self.offline.clear();
+                self.offline_downloading = None;
+                self.offline_failed.clear();
+                self.offline_likes_syncing = false;
 
                 // Delete token from keyring (if available)
                 let _ = keyring::delete_token();
@@ -784,6 +1037,15 @@ impl cosmic::Application for AppModel {
                         }
                         self.current_user = Some(user);
                         self.auth_state = AuthState::Authenticated;
+
+                        // Pick up likes added since the last sync, then resume
+                        // downloads of pinned tracks
+                        if self.offline.are_likes_pinned() {
+                            tasks.push(cosmic::task::message(cosmic::Action::App(
+                                Message::SyncOfflineLikes,
+                            )));
+                        }
+                        tasks.push(self.download_next_offline());
                         return cosmic::task::batch(tasks);
                     }
                     Err(err) => {
@@ -794,6 +1056,22 @@ impl cosmic::Application for AppModel {
                 }
             }
 
+            Message::NetworkUnavailable(err) => {
+                eprintln!("[login] Network unavailable: {err}");
+                if self.offline.is_empty() {
+                    self.auth_state = AuthState::Failed(err);
+                } else {
+                    // Pinned tracks play without the API, so go straight to them
+                    self.auth_state = AuthState::Authenticated;
+                    self.current_tab = LibraryTab::Offline;
+                    if let Some(position) =
+                        LibraryTab::all().iter().position(|t| *t == LibraryTab::Offline)
+                    {
+                        self.tab_model.activate_position(position as u16);
+                    }
+                }
+            }
+
             // === Library Navigation ===
             Message::SwitchTab(entity) => {
                 self.tab_model.activate(entity);
@@ -938,6 +1216,7 @@ impl cosmic::Application for AppModel {
                 let playlist = match self.current_tab {
                     LibraryTab::Likes => self.likes.items.clone(),
                     LibraryTab::History => self.history.items.clone(),
+                    LibraryTab::Offline => self.offline.tracks(),
                     _ => vec![track.clone()],
                 };
                 let index = playlist.iter().position(|t| t.id == track.id).unwrap_or(0);
@@ -948,11 +1227,21 @@ impl cosmic::Application for AppModel {
             }
 
             Message::PlayTrackInPlaylist(track, playlist, index) => {
+                // Leaving the album's track list turns crossfade back on
+                if self.album_playback
+                    && !playlist.iter().map(|t| t.id).eq(self.current_playlist.iter().map(|t| t.id))
+                {
+                    self.album_playback = false;
+                    self.send_normalization();
+                }
                 self.current_track = Some(track.clone());
                 self.current_playlist = playlist;
                 self.playlist_index = index;
                 self.playback_status = PlaybackStatus::Buffering;
                 self.playback_position = 0.0;
+                self.seek_preview = None;
+                self.download_progress = None;
+                self.stream_format = None;
                 // Reset preload state for the new track
                 self.preload_triggered = false;
 
@@ -964,49 +1253,53 @@ impl cosmic::Application for AppModel {
                 {
                     tasks.push(cosmic::task::message(cosmic::Action::App(Message::LoadArtwork(artwork_url.clone()))));
                 }
+                tasks.push(self.load_waveform(&track));
 
-                // Fetch stream URL and play
-                if let Some(client) = &self.api_client {
-                    let client = client.clone();
-                    tasks.push(cosmic::task::future(async move {
-                        match client.get_stream_url(&track).await {
-                            Ok(url) => Message::StreamUrlLoaded(Ok(url)),
-                            Err(e) => Message::StreamUrlLoaded(Err(e.to_string())),
-                        }
-                    })
-                    .map(cosmic::Action::App));
-                    return cosmic::task::batch(tasks);
-                }
+                self.stream_resolver = None;
+                self.stream_declined.clear();
+                self.stream_error = None;
+                tasks.push(self.resolve_current_stream(track, 0));
+                return cosmic::task::batch(tasks);
             }
 
-            Message::StreamUrlLoaded(result) => match result {
-                Ok(url) => {
-                    if let Some(tx) = &self.audio_cmd_tx {
-                        let track_id = self.current_track.as_ref().map(|t| t.id);
-                        let permalink_url = self
-                            .current_track
-                            .as_ref()
-                            .and_then(|t| t.permalink_url.clone());
-                        // Play at full volume - system volume controls actual output
-                        let _ = tx.blocking_send(AudioCommand::SetVolume(1.0));
-                        let _ = tx.blocking_send(AudioCommand::Play {
-                            track_id,
-                            stream_url: url,
-                            permalink_url,
-                        });
-                    }
+            Message::StreamResolved(track_id, result) => {
+                // A later track was picked while this one resolved
+                if self.current_track.as_ref().map(|t| t.id) != Some(track_id) {
+                    return Task::none();
                 }
-                Err(err) => {
-                    eprintln!("Failed to get stream URL: {err}");
-                    self.playback_status = PlaybackStatus::Stopped;
+                self.stream_resolving = None;
+                match result {
+                    Ok(resolved) => {
+                        self.stream_resolver = Some((resolved.position, resolved.kind));
+                        if let Some(tx) = &self.audio_cmd_tx {
+                            let permalink_url = self
+                                .current_track
+                                .as_ref()
+                                .and_then(|t| t.permalink_url.clone());
+                            let _ = tx.blocking_send(AudioCommand::Play {
+                                track_id: Some(track_id),
+                                source: resolved.source,
+                                permalink_url,
+                            });
+                        }
+                    }
+                    Err(declined) => {
+                        self.stream_declined.extend(declined);
+                        self.stream_unavailable();
+                    }
                 }
-            },
+            }
 
             // === Audio Player ===
             Message::AudioReady(tx) => {
-                // Play at full volume - system volume controls actual output
-                let _ = tx.blocking_send(AudioCommand::SetVolume(1.0));
                 self.audio_cmd_tx = Some(tx);
+                self.send_volume();
+                self.send_normalization();
+                self.send_equalizer();
+                self.send_stream_preference();
+                self.send_output_device();
+                self.send_cache_limit();
+                self.send_visualizer();
             }
 
             Message::AudioEvent(event) => match event {
@@ -1019,6 +1312,7 @@ impl cosmic::Application for AppModel {
                 AudioEvent::Stopped => {
                     self.playback_status = PlaybackStatus::Stopped;
                     self.playback_position = 0.0;
+                    self.spectrum.clear();
                 }
                 AudioEvent::Buffering(buffering) => {
                     if buffering {
@@ -1026,6 +1320,13 @@ impl cosmic::Application for AppModel {
                     }
                 }
                 AudioEvent::Finished => {
+                    // The track ended before the sleep timer could fade it out
+                    if self.sleep_timer == Some(SleepTimer::EndOfTrack) {
+                        eprintln!("[sleep] Track finished, not advancing");
+                        self.sleep_timer = None;
+                        self.playback_status = PlaybackStatus::Stopped;
+                        return Task::none();
+                    }
                     // Auto-play next track
                     eprintln!("[auto-advance] AudioEvent::Finished received, dispatching NextTrack");
                     return cosmic::task::message(cosmic::Action::App(Message::NextTrack));
@@ -1034,19 +1335,37 @@ impl cosmic::Application for AppModel {
                     eprintln!("Audio error: {err}");
                     self.playback_status = PlaybackStatus::Stopped;
                 }
-                AudioEvent::DrmProtected { drm_type, track_url } => {
-                    eprintln!("DRM-protected content ({drm_type}) - opening in browser");
-                    self.playback_status = PlaybackStatus::Stopped;
-                    if !track_url.is_empty()
-                        && let Err(e) = open_in_browser(&track_url)
-                    {
-                        eprintln!("Failed to open browser: {e}");
+                AudioEvent::DrmProtected { drm_type, .. } => {
+                    // Carry on with the resolvers after the one that found it
+                    eprintln!("DRM-protected content ({drm_type}) - trying the next stream source");
+                    let Some((position, kind)) = self.stream_resolver.take() else {
+                        self.playback_status = PlaybackStatus::Stopped;
+                        return Task::none();
+                    };
+                    self.stream_declined.push(Declined {
+                        kind,
+                        reason: Decline::Drm(drm_type),
+                    });
+                    if let Some(track) = self.current_track.clone() {
+                        return self.resolve_current_stream(track, position + 1);
                     }
                 }
                 AudioEvent::Ready => {}
                 AudioEvent::Position(pos) => {
                     self.playback_position = pos;
 
+                    // Fade out in time to pause before the next track starts
+                    if self.sleep_timer == Some(SleepTimer::EndOfTrack)
+                        && let Some(track) = &self.current_track
+                        && track.duration > 0
+                    {
+                        let crossfade = if self.album_playback { 0.0 } else { self.config.crossfade_secs };
+                        let fade_at = track.duration as f32 / 1000.0 - crossfade - SLEEP_FADE_SECS - 1.0;
+                        if pos >= fade_at {
+                            self.sleep();
+                        }
+                    }
+
                     // Trigger preloading of the next track at ~25% through
                     if !self.preload_triggered {
                         if let Some(track) = &self.current_track {
@@ -1063,13 +1382,104 @@ impl cosmic::Application for AppModel {
                 AudioEvent::PreloadComplete(track_id) => {
                     eprintln!("[preload] Preload complete for track {track_id}");
                     self.preloaded_track_id = Some(track_id);
+
+                    // Queue the next track behind the current one for a gapless
+                    // transition, or a crossfade outside of albums
+                    if let Some(next_index) = self.next_playlist_index()
+                        && self.current_playlist[next_index].id == track_id
+                        && self.sleep_timer != Some(SleepTimer::EndOfTrack)
+                        && let Some(tx) = &self.audio_cmd_tx
+                    {
+                        let track_duration = self
+                            .current_track
+                            .as_ref()
+                            .map_or(0.0, |t| t.duration as f32 / 1000.0);
+                        let crossfade = (self.config.crossfade_secs > 0.0
+                            && !self.album_playback
+                            && track_duration > 0.0)
+                            .then_some(Crossfade {
+                                duration: self.config.crossfade_secs,
+                                track_duration,
+                            });
+                        let _ = tx.blocking_send(AudioCommand::Enqueue { track_id, crossfade });
+                    }
+                }
+                AudioEvent::Duration(secs) => {
+                    // Fill in tracks the API returned without a duration
+                    if let Some(track) = &mut self.current_track
+                        && track.duration == 0
+                    {
+                        track.duration = (secs * 1000.0) as u64;
+                    }
+                }
+                AudioEvent::OutputDevices { devices, current } => {
+                    self.output_devices = std::iter::once("System default".to_string())
+                        .chain(devices)
+                        .collect();
+                    // The chosen device stays selectable while it's unplugged
+                    if let Some(device) = &self.config.output_device
+                        && !self.output_devices.contains(device)
+                    {
+                        self.output_devices.push(device.clone());
+                    }
+                    self.current_output_device = Some(current);
+                }
+                AudioEvent::DownloadProgress {
+                    track_id,
+                    downloaded,
+                    total,
+                } => {
+                    let is_current = self.current_track.as_ref().map(|t| t.id) == track_id;
+                    if is_current && total > 0 {
+                        self.download_progress =
+                            (downloaded < total).then(|| downloaded as f32 / total as f32);
+                    }
+                }
+                AudioEvent::Format(format) => {
+                    self.stream_format = Some(format);
+                }
+                AudioEvent::Spectrum(bars) => {
+                    self.spectrum = bars;
+                }
+                AudioEvent::TrackChanged(track_id) => {
+                    // Queued before the sleep timer was set to the end of the track
+                    if self.sleep_timer == Some(SleepTimer::EndOfTrack) {
+                        self.sleep();
+                    }
+                    // The player moved on to the enqueued track by itself
+                    if let Some(next_index) = self.next_playlist_index()
+                        && self.current_playlist[next_index].id == track_id
+                    {
+                        let track = self.current_playlist[next_index].clone();
+                        eprintln!("[gapless] Now playing '{}'", track.title);
+                        self.playlist_index = next_index;
+                        self.playback_status = PlaybackStatus::Playing;
+                        self.playback_position = 0.0;
+                        self.seek_preview = None;
+                        self.download_progress = None;
+                        self.stream_format = None;
+                        self.preload_triggered = false;
+
+                        let mut tasks = vec![self.load_waveform(&track)];
+                        if let Some(artwork_url) = &track.artwork_url
+                            && !self.artwork_cache.contains_key(artwork_url)
+                            && !self.artwork_loading.contains(artwork_url)
+                        {
+                            tasks.push(cosmic::task::message(cosmic::Action::App(
+                                Message::LoadArtwork(artwork_url.clone()),
+                            )));
+                        }
+                        self.current_track = Some(track);
+                        return cosmic::task::batch(tasks);
+                    }
                 }
             },
 
             Message::TogglePlayPause => {
                 if let Some(tx) = &self.audio_cmd_tx {
                     match self.playback_status {
-                        PlaybackStatus::Playing => {
+                        // Pausing while buffering starts the track paused
+                        PlaybackStatus::Playing | PlaybackStatus::Buffering => {
                             let _ = tx.blocking_send(AudioCommand::Pause);
                         }
                         PlaybackStatus::Paused => {
@@ -1083,7 +1493,6 @@ impl cosmic::Application for AppModel {
                                 ));
                             }
                         }
-                        PlaybackStatus::Buffering => {}
                     }
                 }
             }
@@ -1130,64 +1539,228 @@ impl cosmic::Application for AppModel {
 
             Message::SetVolume(vol) => {
                 self.volume = vol.clamp(0.0, 1.0);
-                // Set system volume instead of internal player volume
-                system_volume::set_volume(self.volume);
+                match self.config.volume_mode {
+                    // Saved once the slider is released
+                    VolumeMode::App => {
+                        self.config.volume = self.volume;
+                        self.send_volume();
+                    }
+                    VolumeMode::System => {
+                        system_volume::set_volume(self.volume);
+                    }
+                }
+            }
+
+            Message::SaveConfig => {
+                self.save_config();
+            }
+
+            // Saved once the slider is released
+            Message::SetCrossfade(secs) => {
+                self.config.crossfade_secs = secs.clamp(0.0, 12.0);
+            }
+
+            Message::SetNormalization(index) => {
+                if let Some(mode) = NormalizationMode::all().get(index) {
+                    self.config.normalization = *mode;
+                    self.save_config();
+                    self.send_normalization();
+                }
+            }
+
+            Message::SetStreamPreference(index) => {
+                if let Some(preference) = StreamPreference::all().get(index) {
+                    self.config.stream_preference = *preference;
+                    self.save_config();
+                    self.send_stream_preference();
+                }
+            }
+
+            // Evicting is permanent, so the player only gets the limit the
+            // slider is released at
+            Message::SetCacheLimit(mb) => {
+                self.config.cache_limit_mb = mb.clamp(256.0, 8192.0) as u32;
+            }
+
+            Message::CommitCacheLimit => {
+                self.save_config();
+                self.send_cache_limit();
+            }
+
+            Message::MoveStreamSource(index, up) => {
+                let sources = &mut self.config.stream_sources;
+                let target = if up { index.checked_sub(1) } else { Some(index + 1) };
+                if let Some(target) = target
+                    && index < sources.len()
+                    && target < sources.len()
+                {
+                    sources.swap(index, target);
+                    self.save_config();
+                }
+            }
+
+            Message::YtDlpPathInput(path) => {
+                self.ytdlp_path_input = path;
+            }
+
+            Message::SetYtDlpPath => {
+                let path = self.ytdlp_path_input.trim();
+                self.config.ytdlp_path = (!path.is_empty()).then(|| path.to_string());
+                self.save_config();
+                return self.check_ytdlp();
+            }
+
+            Message::YtDlpChecked(result) => {
+                match &result {
+                    Ok(version) => eprintln!("[yt-dlp] Found version {version}"),
+                    Err(e) => eprintln!("[yt-dlp] {e}"),
+                }
+                self.ytdlp_version = Some(result);
+            }
+
+            Message::SetVolumeMode(index) => {
+                if let Some(mode) = VolumeMode::all().get(index)
+                    && *mode != self.config.volume_mode
+                {
+                    self.config.volume_mode = *mode;
+                    self.save_config();
+                    self.apply_volume_mode();
+                }
+            }
+
+            Message::SetOutputDevice(index) => {
+                // Index 0 is the system default
+                if index < self.output_devices.len() {
+                    self.config.output_device =
+                        (index > 0).then(|| self.output_devices[index].clone());
+                    self.save_config();
+                    self.send_output_device();
+                }
+            }
+
+            Message::SetEqualizerPreset(index) => {
+                if let Some(preset) = EqualizerPreset::all().get(index) {
+                    self.config.equalizer_preset = *preset;
+                    self.save_config();
+                    self.send_equalizer();
+                }
+            }
+
+            Message::SetEqualizerBand(band, gain) => {
+                // Editing a band turns the current curve into the custom one,
+                // saved once the slider is released
+                let mut gains = self.config.equalizer_gains();
+                if let Some(value) = gains.get_mut(band) {
+                    *value = gain.clamp(-12.0, 12.0);
+                    self.config.equalizer_custom = gains;
+                    self.config.equalizer_preset = EqualizerPreset::Custom;
+                    self.send_equalizer();
+                }
+            }
+
+            Message::SeekPreview(position) => {
+                self.seek_preview = Some(position);
+            }
+
+            Message::SeekCommit => {
+                if let Some(position) = self.seek_preview.take() {
+                    self.playback_position = position;
+                    if let Some(tx) = &self.audio_cmd_tx {
+                        let _ = tx.blocking_send(AudioCommand::Seek(position));
+                    }
+                }
+            }
+
+            Message::WaveformCursor(fraction) => {
+                self.waveform_cursor = Some(fraction);
+                // Dragging: follow the pointer until the button is released
+                if self.seek_preview.is_some()
+                    && let Some(track) = &self.current_track
+                {
+                    self.seek_preview = Some(fraction * track.duration as f32 / 1000.0);
+                }
+            }
+
+            Message::WaveformPressed => {
+                if let (Some(fraction), Some(track)) = (self.waveform_cursor, &self.current_track) {
+                    self.seek_preview = Some(fraction * track.duration as f32 / 1000.0);
+                }
+            }
+
+            // === Sleep Timer ===
+            Message::StartSleepTimer(minutes) => {
+                let minutes = minutes.clamp(1, SLEEP_TIMER_MAX_MINUTES);
+                let deadline = minutes
+                    .checked_mul(60)
+                    .and_then(|secs| Instant::now().checked_add(Duration::from_secs(secs)));
+                if let Some(deadline) = deadline {
+                    eprintln!("[sleep] Pausing in {minutes} min");
+                    self.sleep_timer = Some(SleepTimer::At(deadline));
+                }
+            }
+
+            Message::StartCustomSleepTimer => {
+                if let Some(minutes) = self.custom_sleep_minutes() {
+                    return cosmic::task::message(cosmic::Action::App(Message::StartSleepTimer(
+                        minutes,
+                    )));
+                }
+            }
+
+            Message::SleepTimerInput(input) => {
+                self.sleep_timer_input = input;
+            }
+
+            Message::SleepAtEndOfTrack => {
+                eprintln!("[sleep] Pausing at the end of the current track");
+                self.sleep_timer = Some(SleepTimer::EndOfTrack);
+            }
+
+            Message::CancelSleepTimer => {
+                eprintln!("[sleep] Timer cancelled");
+                self.sleep_timer = None;
+            }
+
+            Message::SleepTimerTick => {
+                if let Some(SleepTimer::At(deadline)) = self.sleep_timer
+                    && Instant::now() >= deadline
+                {
+                    self.sleep();
+                }
             }
 
             // === Preloading ===
             Message::PreloadNextTrack => {
-                if !self.current_playlist.is_empty() {
-                    let next_index = (self.playlist_index + 1) % self.current_playlist.len();
-                    // Only preload if there's actually a next track to play
-                    if next_index != 0
-                        || self.config.repeat_mode == crate::config::RepeatMode::All
-                    {
-                        let next_track = &self.current_playlist[next_index];
-                        let track_id = next_track.id;
-
-                        // Skip if already preloaded
-                        if self.preloaded_track_id == Some(track_id) {
-                            eprintln!("[preload] Track {track_id} already preloaded, skipping");
-                        } else if let Some(client) = &self.api_client {
-                            eprintln!("[preload] Resolving stream URL for next track {track_id}");
-                            let client = client.clone();
-                            let track = next_track.clone();
-                            return cosmic::task::future(async move {
-                                match client.get_stream_url(&track).await {
-                                    Ok(url) => Message::PreloadStreamUrlLoaded(
-                                        track_id,
-                                        Ok(url),
-                                    ),
-                                    Err(e) => Message::PreloadStreamUrlLoaded(
-                                        track_id,
-                                        Err(e.to_string()),
-                                    ),
-                                }
-                            })
-                            .map(cosmic::Action::App);
-                        }
+                // Only preload if there's actually a next track to play
+                if let Some(next_index) = self.next_playlist_index() {
+                    let next_track = &self.current_playlist[next_index];
+                    let track_id = next_track.id;
+
+                    // Skip if already preloaded
+                    if self.preloaded_track_id == Some(track_id) {
+                        eprintln!("[preload] Track {track_id} already preloaded, skipping");
+                    } else {
+                        eprintln!("[preload] Resolving stream for next track {track_id}");
+                        let track = next_track.clone();
+                        return self.resolve_stream(track, 0, Message::PreloadStreamResolved);
                     }
                 }
             }
 
-            Message::PreloadStreamUrlLoaded(track_id, result) => match result {
-                Ok(stream_url) => {
+            Message::PreloadStreamResolved(track_id, result) => match result {
+                Ok(resolved) => {
                     if let Some(tx) = &self.audio_cmd_tx {
-                        let permalink_url = self
-                            .current_playlist
-                            .iter()
-                            .find(|t| t.id == track_id)
-                            .and_then(|t| t.permalink_url.clone());
                         eprintln!("[preload] Sending preload command for track {track_id}");
                         let _ = tx.blocking_send(AudioCommand::Preload {
                             track_id,
-                            stream_url,
-                            permalink_url,
+                            source: resolved.source,
                         });
                     }
                 }
-                Err(err) => {
-                    eprintln!("[preload] Failed to get stream URL for track {track_id}: {err}");
+                Err(declined) => {
+                    for declined in declined {
+                        eprintln!("[preload] Track {track_id} unavailable from {declined}");
+                    }
                 }
             },
 
@@ -1216,6 +1789,17 @@ impl cosmic::Application for AppModel {
                 }
             }
 
+            // === Waveforms ===
+            Message::WaveformLoaded(track_id, result) => {
+                self.waveform_loading.remove(&track_id);
+                match result {
+                    Ok(waveform) => {
+                        self.waveforms.insert(track_id, waveform);
+                    }
+                    Err(e) => eprintln!("[waveform] Track {track_id}: {e}"),
+                }
+            }
+
             // === Artist Navigation ===
             Message::NavigateToArtist(user_id, username, avatar_url) => {
                 // Update recent artists list
@@ -1424,6 +2008,12 @@ impl cosmic::Application for AppModel {
 
             // === Album Playback ===
             Message::PlayAlbum(album_id) => {
+                // A pinned album's track list comes from the offline store
+                if let Some(tracks) = self.offline.album_tracks(album_id) {
+                    return cosmic::task::message(cosmic::Action::App(Message::AlbumTracksLoaded(
+                        Ok(tracks),
+                    )));
+                }
                 if let Some(client) = &self.api_client {
                     let client = client.clone();
                     return cosmic::task::future(async move {
@@ -1442,15 +2032,187 @@ impl cosmic::Application for AppModel {
                         // Set as playlist and play first track
                         let first_track = tracks[0].clone();
                         let playlist = tracks;
-                        return cosmic::task::message(cosmic::Action::App(
-                            Message::PlayTrackInPlaylist(first_track, playlist, 0),
-                        ));
+                        let task = self.update(Message::PlayTrackInPlaylist(first_track, playlist, 0));
+                        // Albums play back to back without crossfading
+                        self.album_playback = true;
+                        self.send_normalization();
+                        return task;
                     }
                 } else if let Err(e) = result {
                     eprintln!("Failed to load album tracks: {e}");
                 }
             }
 
+            // === Offline Store ===
+            Message::ToggleOfflineTrack(track) => {
+                if self.offline.is_track_pinned(track.id) {
+                    self.offline.unpin_track(track.id);
+                } else {
+                    self.offline.pin(Pin::Track(track.id), vec![track]);
+                    return self.download_next_offline();
+                }
+            }
+
+            Message::ToggleOfflineAlbum(album_id) => {
+                if self.offline.is_album_pinned(album_id) {
+                    self.offline.unpin_album(album_id);
+                } else if let Some(client) = &self.api_client {
+                    let client = client.clone();
+                    return cosmic::task::future(async move {
+                        let result = client
+                            .get_playlist_tracks(album_id)
+                            .await
+                            .map_err(|e| e.to_string());
+                        Message::OfflineAlbumTracksLoaded(album_id, result)
+                    })
+                    .map(cosmic::Action::App);
+                }
+            }
+
+            Message::OfflineAlbumTracksLoaded(album_id, result) => match result {
+                Ok(tracks) => {
+                    let title = self
+                        .artist_albums
+                        .iter()
+                        .find(|a| a.id == album_id)
+                        .map(|a| a.title.clone())
+                        .or_else(|| {
+                            self.recommendations
+                                .iter()
+                                .find(|p| p.id == album_id)
+                                .map(|p| p.title.clone())
+                        })
+                        .unwrap_or_else(|| "Album".to_string());
+                    let track_ids = tracks.iter().map(|t| t.id).collect();
+                    self.offline.pin(
+                        Pin::Album {
+                            id: album_id,
+                            title,
+                            track_ids,
+                        },
+                        tracks,
+                    );
+                    return self.download_next_offline();
+                }
+                Err(e) => eprintln!("[offline] Failed to load album {album_id}: {e}"),
+            },
+
+            Message::ToggleOfflineLikes => {
+                if self.offline.are_likes_pinned() || self.offline_likes_syncing {
+                    self.offline_likes_syncing = false;
+                    self.offline.unpin_likes();
+                } else {
+                    return self.update(Message::SyncOfflineLikes);
+                }
+            }
+
+            Message::SyncOfflineLikes => {
+                if let (Some(client), Some(user)) = (&self.api_client, &self.current_user) {
+                    self.offline_likes_syncing = true;
+                    let client = client.clone();
+                    let user_id = user.id;
+                    return cosmic::task::future(async move {
+                        // Every page, not just the ones the Likes tab has shown
+                        let mut tracks = Vec::new();
+                        let mut next_href = None;
+                        loop {
+                            match client.get_user_likes(user_id, next_href.as_deref()).await {
+                                Ok((page, next)) => {
+                                    tracks.extend(page);
+                                    match next {
+                                        Some(next) => next_href = Some(next),
+                                        None => break,
+                                    }
+                                }
+                                Err(e) => return Message::OfflineLikesLoaded(Err(e.to_string())),
+                            }
+                        }
+                        Message::OfflineLikesLoaded(Ok(tracks))
+                    })
+                    .map(cosmic::Action::App);
+                }
+            }
+
+            Message::OfflineLikesLoaded(result) => {
+                // Unpinned while the likes were loading
+                if !self.offline_likes_syncing {
+                    return Task::none();
+                }
+                self.offline_likes_syncing = false;
+                match result {
+                    Ok(tracks) => {
+                        eprintln!("[offline] Pinning {} liked tracks", tracks.len());
+                        let track_ids = tracks.iter().map(|t| t.id).collect();
+                        self.offline.pin(Pin::Likes { track_ids }, tracks);
+                        return self.download_next_offline();
+                    }
+                    Err(e) => eprintln!("[offline] Failed to load likes: {e}"),
+                }
+            }
+
+            Message::OfflineTrackDownloaded(track_id, result) => {
+                self.offline_downloading = None;
+                match result {
+                    Ok(size) => {
+                        if self.offline.mark_downloaded(track_id, size) {
+                            eprintln!("[offline] Track {track_id} downloaded ({size} bytes)");
+                            for (url, path) in self.offline.artwork() {
+                                self.artwork_cache
+                                    .entry(url)
+                                    .or_insert_with(|| image::Handle::from_path(path));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("[offline] Failed to download track {track_id}: {e}");
+                        self.offline_failed.insert(track_id);
+                    }
+                }
+                return self.download_next_offline();
+            }
+
+            // === Export ===
+            Message::ExportTrack(track) => {
+                let Some(dir) = self.export_dir() else {
+                    eprintln!("[export] No folder to export to");
+                    return Task::none();
+                };
+                if !self.exporting.insert(track.id) {
+                    return Task::none();
+                }
+                let track_id = track.id;
+                let album = self.album_title(track_id);
+                let sources = self.config.stream_sources.clone();
+                let client = self.api_client.clone();
+                let preference = self.config.stream_preference;
+                let ytdlp = self.ytdlp();
+                eprintln!("[export] Exporting '{}' to {}", track.title, dir.display());
+                return cosmic::task::future(async move {
+                    let chain = ResolverChain::new(&sources, client, preference, ytdlp);
+                    let result = export::export_track(chain, track, album, dir, preference).await;
+                    Message::TrackExported(track_id, result)
+                })
+                .map(cosmic::Action::App);
+            }
+
+            Message::TrackExported(track_id, result) => {
+                self.exporting.remove(&track_id);
+                match result {
+                    Ok(path) => eprintln!("[export] Saved {}", path.display()),
+                    Err(e) => eprintln!("[export] Failed to export track {track_id}: {e}"),
+                }
+            }
+
+            Message::ExportDirInput(dir) => {
+                self.export_dir_input = dir;
+            }
+
+            Message::SetExportDir => {
+                let dir = self.export_dir_input.trim();
+                self.config.export_dir = (!dir.is_empty()).then(|| dir.to_string());
+                self.save_config();
+            }
+
             // === Search ===
             Message::NavigateToSearch => {
                 self.current_page = Page::Search;
@@ -1656,12 +2418,282 @@ impl AppModel {
         }
     }
 
-    /// Rebuild the navigation model with Library, Search, Recommendations, and recent artists
-    fn rebuild_nav(&mut self) {
-        self.nav.clear();
+    /// Persist the current configuration
+    fn save_config(&self) {
+        if let Ok(config_context) = cosmic_config::Config::new(Self::APP_ID, Config::VERSION) {
+            let _ = self.config.write_entry(&config_context);
+        }
+    }
 
-        // Clone data we need to avoid borrow issues
-        let recent_artists: Vec<_> = self
+    /// Start downloading the next pinned track, if none is downloading.
+    /// Downloads run one at a time so they don't compete with playback.
+    fn download_next_offline(&mut self) -> Task<cosmic::Action<Message>> {
+        // Only once the API has been reached, otherwise every download fails
+        if self.offline_downloading.is_some() || self.current_user.is_none() {
+            return Task::none();
+        }
+        let Some(client) = self.api_client.clone() else {
+            return Task::none();
+        };
+        let Some(track) = self
+            .offline
+            .pending()
+            .into_iter()
+            .find(|t| !self.offline_failed.contains(&t.id))
+        else {
+            return Task::none();
+        };
+
+        let track_id = track.id;
+        let sources = self.config.stream_sources.clone();
+        let preference = self.config.stream_preference;
+        let ytdlp = self.ytdlp();
+        self.offline_downloading = Some(track_id);
+        eprintln!("[offline] Downloading '{}'", track.title);
+        cosmic::task::future(async move {
+            let result = offline::download_track(client, track, sources, preference, ytdlp).await;
+            Message::OfflineTrackDownloaded(track_id, result)
+        })
+        .map(cosmic::Action::App)
+    }
+
+    /// Ask the stream resolvers from chain position `start` on where to play
+    /// `track` from, in the configured order
+    fn resolve_stream(
+        &self,
+        track: Track,
+        start: usize,
+        message: fn(u64, Result<Resolved, Vec<Declined>>) -> Message,
+    ) -> Task<cosmic::Action<Message>> {
+        let sources = self.config.stream_sources.clone();
+        let client = self.api_client.clone();
+        let preference = self.config.stream_preference;
+        let ytdlp = self.ytdlp();
+        cosmic::task::future(async move {
+            let chain = ResolverChain::new(&sources, client, preference, ytdlp);
+            message(track.id, chain.resolve(&track, start).await)
+        })
+        .map(cosmic::Action::App)
+    }
+
+    /// Resolve the current track's stream, abandoning any earlier resolve so
+    /// a slow resolver (like a yt-dlp process, killed on drop) stops working
+    /// for a track that's no longer wanted
+    fn resolve_current_stream(&mut self, track: Track, start: usize) -> Task<cosmic::Action<Message>> {
+        let (task, handle) = self.resolve_stream(track, start, Message::StreamResolved).abortable();
+        if let Some(previous) = self.stream_resolving.replace(handle) {
+            previous.abort();
+        }
+        task
+    }
+
+    /// Every stream resolver passed on the current track. DRM-protected
+    /// tracks can still be played on the website; if yt-dlp just isn't
+    /// installed, the message also points at that fix.
+    fn stream_unavailable(&mut self) {
+        eprintln!("No stream source can play this track:");
+        for declined in &self.stream_declined {
+            eprintln!("  - {declined}");
+        }
+        self.playback_status = PlaybackStatus::Stopped;
+
+        let drm = self
+            .stream_declined
+            .iter()
+            .any(|declined| matches!(declined.reason, Decline::Drm(_)));
+        let ytdlp_missing = self
+            .stream_declined
+            .iter()
+            .any(|declined| matches!(declined.reason, Decline::YtDlp(YtDlpError::Missing(_))));
+        self.stream_error = Some(
+            match (drm, ytdlp_missing) {
+                (true, true) => {
+                    "DRM-protected, opened in your browser. Install yt-dlp or set its path in Settings to play it here."
+                }
+                (true, false) => "DRM-protected, opened in your browser",
+                (false, _) => "No stream source can play this track",
+            }
+            .to_string(),
+        );
+        if drm
+            && let Some(track_url) = self.current_track.as_ref().and_then(|t| t.permalink_url.as_deref())
+            && !track_url.is_empty()
+        {
+            eprintln!("Opening DRM-protected track in browser");
+            if let Err(e) = open_in_browser(track_url) {
+                eprintln!("Failed to open browser: {e}");
+            }
+        }
+    }
+
+    /// Folder to export tracks to
+    fn export_dir(&self) -> Option<PathBuf> {
+        self.config
+            .export_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(export::default_dir)
+    }
+
+    /// Album a track is on, if a pinned album or the artist page lists it
+    fn album_title(&self, track_id: u64) -> Option<String> {
+        self.offline.album_title(track_id).or_else(|| {
+            self.artist_albums
+                .iter()
+                .find(|album| album.track_stubs.iter().any(|t| t.id == track_id))
+                .map(|album| album.title.clone())
+        })
+    }
+
+    /// The configured yt-dlp binary
+    fn ytdlp(&self) -> YtDlp {
+        YtDlp::new(self.config.ytdlp_path.as_deref())
+    }
+
+    /// Detect the configured yt-dlp's version, or that it's missing
+    fn check_ytdlp(&mut self) -> Task<cosmic::Action<Message>> {
+        self.ytdlp_version = None;
+        let ytdlp = self.ytdlp();
+        cosmic::task::future(async move { Message::YtDlpChecked(ytdlp.version().await) })
+            .map(cosmic::Action::App)
+    }
+
+    /// Send the stream variant preference to the audio player
+    fn send_stream_preference(&self) {
+        if let Some(tx) = &self.audio_cmd_tx {
+            let _ = tx.blocking_send(AudioCommand::SetStreamPreference(self.config.stream_preference));
+        }
+    }
+
+    /// Set the player's own volume: the slider in app mode, full volume when
+    /// the slider drives the system volume instead
+    fn send_volume(&self) {
+        let volume = match self.config.volume_mode {
+            VolumeMode::App => self.volume,
+            VolumeMode::System => 1.0,
+        };
+        if let Some(tx) = &self.audio_cmd_tx {
+            let _ = tx.blocking_send(AudioCommand::SetVolume(volume));
+        }
+    }
+
+    /// Point the volume slider at what the current volume mode controls
+    fn apply_volume_mode(&mut self) {
+        self.volume = match self.config.volume_mode {
+            VolumeMode::App => self.config.volume,
+            VolumeMode::System => system_volume::get_volume().unwrap_or(self.volume),
+        };
+        self.send_volume();
+    }
+
+    fn send_cache_limit(&self) {
+        if let Some(tx) = &self.audio_cmd_tx {
+            let bytes = u64::from(self.config.cache_limit_mb) * 1024 * 1024;
+            let _ = tx.blocking_send(AudioCommand::SetCacheBudget(bytes));
+        }
+    }
+
+    fn send_output_device(&self) {
+        if let Some(tx) = &self.audio_cmd_tx {
+            let _ = tx.blocking_send(AudioCommand::SetOutputDevice(self.config.output_device.clone()));
+        }
+    }
+
+    /// Send the selected equalizer curve to the audio player
+    fn send_equalizer(&self) {
+        if let Some(tx) = &self.audio_cmd_tx {
+            let _ = tx.blocking_send(AudioCommand::SetEqualizer(self.config.equalizer_gains()));
+        }
+    }
+
+    /// Fetch a track's waveform for the seek bar, unless it's loaded or
+    /// loading already
+    fn load_waveform(&mut self, track: &Track) -> Task<cosmic::Action<Message>> {
+        let Some(url) = track.waveform_url.clone() else {
+            return Task::none();
+        };
+        let track_id = track.id;
+        if self.waveforms.contains_key(&track_id) || !self.waveform_loading.insert(track_id) {
+            return Task::none();
+        }
+        cosmic::task::future(async move {
+            Message::WaveformLoaded(track_id, waveform::load(track_id, &url).await)
+        })
+        .map(cosmic::Action::App)
+    }
+
+    /// The sleep timer ran out: fade playback out and pause
+    fn sleep(&mut self) {
+        eprintln!("[sleep] Timer expired, fading out");
+        self.sleep_timer = None;
+        if let Some(tx) = &self.audio_cmd_tx {
+            let _ = tx.blocking_send(AudioCommand::FadeOutAndPause(SLEEP_FADE_SECS));
+        }
+    }
+
+    /// Sleep timer length typed in the custom field, if it's in range
+    fn custom_sleep_minutes(&self) -> Option<u64> {
+        self.sleep_timer_input
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|minutes| (1..=SLEEP_TIMER_MAX_MINUTES).contains(minutes))
+    }
+
+    /// Time left on the sleep timer, for the player bar
+    fn sleep_timer_label(&self) -> Option<String> {
+        match self.sleep_timer? {
+            SleepTimer::At(deadline) => {
+                let secs = deadline.saturating_duration_since(Instant::now()).as_secs();
+                Some(format!("Sleep in {}:{:02}", secs / 60, secs % 60))
+            }
+            SleepTimer::EndOfTrack => Some("Sleep after this track".to_string()),
+        }
+    }
+
+    /// Whether the Now Playing page with the spectrum visualizer is open
+    fn visualizer_visible(&self) -> bool {
+        self.core.window.show_context && self.context_page == ContextPage::NowPlaying
+    }
+
+    fn send_visualizer(&self) {
+        if let Some(tx) = &self.audio_cmd_tx {
+            let _ = tx.blocking_send(AudioCommand::SetVisualizer(self.visualizer_visible()));
+        }
+    }
+
+    /// Tell the audio player how to normalize loudness. Album mode only applies
+    /// to playlists loaded with `PlayAlbum`; everything else uses track gain.
+    fn send_normalization(&self) {
+        let normalization = match self.config.normalization {
+            NormalizationMode::Off => Normalization::Off,
+            NormalizationMode::Album if self.album_playback => {
+                Normalization::Album(self.current_playlist.iter().map(|t| t.id).collect())
+            }
+            NormalizationMode::Track | NormalizationMode::Album => Normalization::Track,
+        };
+        if let Some(tx) = &self.audio_cmd_tx {
+            let _ = tx.blocking_send(AudioCommand::SetNormalization(normalization));
+        }
+    }
+
+    /// Index of the track after the current one in the playlist, honouring repeat mode.
+    /// Returns `None` at the end of a non-repeating playlist.
+    fn next_playlist_index(&self) -> Option<usize> {
+        if self.current_playlist.is_empty() {
+            return None;
+        }
+        let next_index = (self.playlist_index + 1) % self.current_playlist.len();
+        (next_index != 0 || self.config.repeat_mode == crate::config::RepeatMode::All)
+            .then_some(next_index)
+    }
+
+    /// Rebuild the navigation model with Library, Search, Recommendations, and recent artists
+    fn rebuild_nav(&mut self) {
+        self.nav.clear();
+
+        // Clone data we need to avoid borrow issues
+        let recent_artists: Vec<_> = self
             .config
             .recent_artists
             .iter()
@@ -1787,13 +2819,21 @@ impl AppModel {
                     .apply(Element::from)
             };
 
+            let mut details = widget::column::with_capacity(3)
+                .push(widget::text::body(&track.title))
+                .push(widget::text::caption(&track.user.username));
+            if let Some(error) = &self.stream_error {
+                details = details.push(widget::text::caption(error));
+            } else if let Some(format) = &self.stream_format {
+                let label = format.label();
+                if !label.is_empty() {
+                    details = details.push(widget::text::caption(label));
+                }
+            }
+
             widget::row::with_capacity(2)
                 .push(artwork)
-                .push(
-                    widget::column::with_capacity(2)
-                        .push(widget::text::body(&track.title))
-                        .push(widget::text::caption(&track.user.username)),
-                )
+                .push(details)
                 .spacing(space_s)
                 .align_y(Alignment::Center)
                 .into()
@@ -1828,7 +2868,8 @@ impl AppModel {
         // Progress display
         let progress_display: Element<_> = if let Some(track) = &self.current_track {
             let duration_secs = track.duration as f32 / 1000.0;
-            let position_secs = self.playback_position;
+            // Show where the user is dragging to until the seek is committed
+            let position_secs = self.seek_preview.unwrap_or(self.playback_position);
 
             // Format time as MM:SS
             let format_time = |secs: f32| -> String {
@@ -1838,24 +2879,42 @@ impl AppModel {
                 format!("{mins}:{secs:02}")
             };
 
-            let progress_ratio = if duration_secs > 0.0 {
-                (position_secs / duration_secs).clamp(0.0, 1.0)
-            } else {
-                0.0
-            };
-
-            let time_text = format!(
+            let mut time_text = format!(
                 "{} / {}",
                 format_time(position_secs),
                 format_time(duration_secs)
             );
+            if let Some(downloaded) = self.download_progress {
+                time_text.push_str(&format!(" · {:.0}%", downloaded * 100.0));
+            }
+            if let Some(sleep) = self.sleep_timer_label() {
+                time_text.push_str(&format!(" · {sleep}"));
+            }
 
-            widget::column::with_capacity(2)
-                .push(
-                    widget::progress_bar(0.0..=1.0, progress_ratio)
-                        .width(Length::Fixed(200.0))
-                        .height(Length::Fixed(4.0)),
+            // Waveform or seek slider - only seekable once the track duration is known
+            let progress: Element<_> = if duration_secs > 0.0
+                && let Some(waveform) = self.waveforms.get(&track.id)
+            {
+                Self::view_waveform(waveform, position_secs, duration_secs)
+            } else if duration_secs > 0.0 {
+                widget::slider(
+                    0.0..=duration_secs,
+                    position_secs.clamp(0.0, duration_secs),
+                    Message::SeekPreview,
                 )
+                .on_release(Message::SeekCommit)
+                .step(1.0)
+                .width(Length::Fixed(200.0))
+                .into()
+            } else {
+                widget::progress_bar(0.0..=1.0, 0.0)
+                    .width(Length::Fixed(200.0))
+                    .height(Length::Fixed(4.0))
+                    .into()
+            };
+
+            widget::column::with_capacity(2)
+                .push(progress)
                 .push(widget::text::caption(time_text))
                 .spacing(space_s / 2)
                 .align_x(Alignment::Center)
@@ -1876,8 +2935,8 @@ impl AppModel {
             .spacing(space_s / 2)
             .align_x(Alignment::Center);
 
-        // Right: Volume and Queue toggle
-        let volume_control = widget::row::with_capacity(4)
+        // Right: Volume, Now Playing, Sleep Timer, Equalizer and Queue toggles
+        let volume_control = widget::row::with_capacity(7)
             .push(
                 widget::icon::from_name("audio-volume-high-symbolic")
                     .size(16)
@@ -1885,10 +2944,42 @@ impl AppModel {
             )
             .push(
                 widget::slider(0.0..=1.0, self.volume, Message::SetVolume)
+                    .on_release(Message::SaveConfig)
                     .step(0.1)
                     .width(Length::Fixed(100.0)),
             )
             .push(widget::horizontal_space().width(Length::Fixed(space_s as f32)))
+            .push(
+                widget::button::icon(widget::icon::from_name("audio-x-generic-symbolic"))
+                    .on_press(Message::ToggleContextPage(ContextPage::NowPlaying))
+                    .class(if self.visualizer_visible() {
+                        cosmic::theme::Button::Suggested
+                    } else {
+                        cosmic::theme::Button::Standard
+                    }),
+            )
+            .push(
+                widget::button::icon(widget::icon::from_name("alarm-symbolic"))
+                    .on_press(Message::ToggleContextPage(ContextPage::SleepTimer))
+                    .class(
+                        if self.sleep_timer.is_some()
+                            || (self.context_page == ContextPage::SleepTimer && self.core.window.show_context)
+                        {
+                            cosmic::theme::Button::Suggested
+                        } else {
+                            cosmic::theme::Button::Standard
+                        },
+                    ),
+            )
+            .push(
+                widget::button::icon(widget::icon::from_name("multimedia-equalizer-symbolic"))
+                    .on_press(Message::ToggleContextPage(ContextPage::Equalizer))
+                    .class(if self.context_page == ContextPage::Equalizer && self.core.window.show_context {
+                        cosmic::theme::Button::Suggested
+                    } else {
+                        cosmic::theme::Button::Standard
+                    }),
+            )
             .push(
                 widget::button::icon(widget::icon::from_name("view-list-symbolic"))
                     .on_press(Message::ToggleContextPage(ContextPage::Queue))
@@ -1931,6 +3022,54 @@ impl AppModel {
             .into()
     }
 
+    /// SoundCloud-style waveform used as the seek bar: bars up to the playback
+    /// position are drawn in the accent colour. Pressing and dragging anywhere
+    /// on it previews the position under the pointer, which is sought to on
+    /// release or when the pointer leaves the waveform.
+    fn view_waveform(waveform: &Waveform, position_secs: f32, duration_secs: f32) -> Element<'_, Message> {
+        const WIDTH: f32 = 360.0;
+        const HEIGHT: f32 = 24.0;
+        let played = (position_secs / duration_secs).clamp(0.0, 1.0);
+        let mut bars = widget::row::with_capacity(waveform.len())
+            .spacing(1.0)
+            .width(Length::Fixed(WIDTH))
+            .height(Length::Fixed(HEIGHT));
+        for (index, &level) in waveform.iter().enumerate() {
+            let is_played = (index as f32 / waveform.len() as f32) < played;
+            let bar = widget::container(widget::vertical_space())
+                .width(Length::Fill)
+                .height(Length::Fixed((level * HEIGHT).max(1.0)))
+                .class(cosmic::theme::Container::custom(move |theme| {
+                    let cosmic = theme.cosmic();
+                    let color: cosmic::iced::Color = if is_played {
+                        cosmic.accent_color().into()
+                    } else {
+                        let mut color: cosmic::iced::Color = cosmic.on_bg_color().into();
+                        color.a = 0.3;
+                        color
+                    };
+                    cosmic::iced_widget::container::Style {
+                        background: Some(cosmic::iced::Background::Color(color)),
+                        ..Default::default()
+                    }
+                }));
+            bars = bars.push(
+                widget::container(bar)
+                    .width(Length::Fill)
+                    .height(Length::Fill)
+                    .align_y(Vertical::Bottom),
+            );
+        }
+        // Positions are relative to the waveform, so the whole width maps
+        // onto the track
+        cosmic::iced::widget::mouse_area(bars)
+            .on_move(|point| Message::WaveformCursor((point.x / WIDTH).clamp(0.0, 1.0)))
+            .on_press(Message::WaveformPressed)
+            .on_release(Message::SeekCommit)
+            .on_exit(Message::SeekCommit)
+            .into()
+    }
+
     fn view_login(&self) -> Element<'_, Message> {
         let space_m = cosmic::theme::spacing().space_m;
         let space_l = cosmic::theme::spacing().space_l;
@@ -2199,10 +3338,22 @@ impl AppModel {
             .spacing(space_s)
             .width(Length::Fixed(120.0));
 
-        widget::button::custom(card_content)
+        let card = widget::button::custom(card_content)
             .on_press(Message::PlayAlbum(album_id))
             .class(cosmic::theme::Button::Text)
-            .padding(space_s)
+            .padding(space_s);
+
+        let offline_label = if self.offline.is_album_pinned(album_id) {
+            "Remove offline"
+        } else {
+            "Save offline"
+        };
+        let offline_button = widget::button::text(offline_label)
+            .on_press(Message::ToggleOfflineAlbum(album_id));
+
+        widget::column::with_capacity(2)
+            .push(card)
+            .push(offline_button)
             .into()
     }
 
@@ -2266,6 +3417,7 @@ impl AppModel {
             LibraryTab::Overview => self.view_overview(),
             LibraryTab::Likes => self.view_likes(),
             LibraryTab::History => self.view_history(),
+            LibraryTab::Offline => self.view_offline(),
             _ => self.view_coming_soon(),
         };
 
@@ -2335,7 +3487,21 @@ impl AppModel {
             .map(|(idx, track)| self.view_track_item_in_playlist(track, playlist.clone(), idx))
             .collect();
 
-        let mut content = widget::column::with_children(tracks).spacing(space_s);
+        let offline_label = if self.offline_likes_syncing {
+            "Loading likes for offline..."
+        } else if self.offline.are_likes_pinned() {
+            "Remove from offline"
+        } else {
+            "Make available offline"
+        };
+        let offline_button = widget::row::with_capacity(2)
+            .push(widget::horizontal_space())
+            .push(widget::button::standard(offline_label).on_press(Message::ToggleOfflineLikes));
+
+        let mut children: Vec<Element<_>> = Vec::with_capacity(tracks.len() + 1);
+        children.push(offline_button.into());
+        children.extend(tracks);
+        let mut content = widget::column::with_children(children).spacing(space_s);
 
         // Load more button
         if self.likes.next_href.is_some() {
@@ -2394,6 +3560,60 @@ impl AppModel {
         widget::scrollable(padded_content).into()
     }
 
+    fn view_offline(&self) -> Element<'_, Message> {
+        let space_s = cosmic::theme::spacing().space_s;
+        let space_m = cosmic::theme::spacing().space_m;
+
+        if self.offline.is_empty() && !self.offline_likes_syncing {
+            return widget::text::body(
+                "Nothing is available offline yet. Pin tracks, albums or your likes to listen without a connection.",
+            )
+            .into();
+        }
+
+        let tracks = self.offline.tracks();
+        let mut summary = format!(
+            "{} of {} tracks downloaded · {:.0} MB",
+            tracks.len(),
+            self.offline.len(),
+            self.offline.size() as f64 / (1024.0 * 1024.0)
+        );
+        if self.offline_downloading.is_some() {
+            summary.push_str(" · Downloading...");
+        }
+
+        let mut content = widget::column::with_capacity(tracks.len() + 4)
+            .push(widget::text::caption(summary))
+            .spacing(space_s);
+
+        // Albums and likes are unpinned as a whole, single tracks from their row
+        for pin in self.offline.pins() {
+            let (title, message) = match pin {
+                Pin::Album { id, title, .. } => (title.clone(), Message::ToggleOfflineAlbum(*id)),
+                Pin::Likes { .. } => ("Liked tracks".to_string(), Message::ToggleOfflineLikes),
+                Pin::Track(_) => continue,
+            };
+            content = content.push(
+                widget::row::with_capacity(3)
+                    .push(widget::text::body(title))
+                    .push(widget::horizontal_space())
+                    .push(widget::button::text("Remove").on_press(message))
+                    .align_y(Alignment::Center),
+            );
+        }
+
+        content = content.push(widget::vertical_space().height(Length::Fixed(space_s as f32)));
+        for (idx, track) in tracks.iter().enumerate() {
+            content = content.push(self.view_track_item_in_playlist(track, tracks.clone(), idx));
+        }
+
+        // Add bottom padding for player bar clearance and right padding for scrollbar
+        let padded_content = widget::container(content)
+            .padding([0, space_m as u16, 120, 0]);
+
+        widget::scrollable(padded_content).into()
+    }
+
     /// Render a track item. If playlist_context is Some, clicking plays in playlist context.
     fn view_track_item_in_playlist(
         &self,
@@ -2510,11 +3730,35 @@ impl AppModel {
             .class(cosmic::theme::Button::Text)
             .padding(0);
 
+        // Pin or unpin the track on its own; tracks downloaded as part of an
+        // album or the likes show as available
+        let offline_icon = if self.offline.is_track_pinned(track.id) {
+            "user-trash-symbolic"
+        } else if self.offline.is_available(track.id) {
+            "emblem-ok-symbolic"
+        } else {
+            "folder-download-symbolic"
+        };
+        let offline_button = widget::button::icon(widget::icon::from_name(offline_icon))
+            .on_press(Message::ToggleOfflineTrack(track.clone()));
+
+        // Export to file, spinning while it's being written
+        let exporting = self.exporting.contains(&track.id);
+        let export_icon = if exporting {
+            "content-loading-symbolic"
+        } else {
+            "document-save-as-symbolic"
+        };
+        let export_button = widget::button::icon(widget::icon::from_name(export_icon))
+            .on_press_maybe((!exporting).then(|| Message::ExportTrack(track.clone())));
+
         widget::container(
-            widget::row::with_capacity(4)
+            widget::row::with_capacity(6)
                 .push(play_button)
                 .push(info)
                 .push(widget::horizontal_space())
+                .push(export_button)
+                .push(offline_button)
                 .push(duration)
                 .spacing(space_s)
                 .align_y(Alignment::Center),
@@ -2767,6 +4011,402 @@ impl AppModel {
             .into()
     }
 
+    /// View for the settings sidebar
+    fn view_settings(&self) -> Element<'_, Message> {
+        let space_s = cosmic::theme::spacing().space_s;
+        let space_m = cosmic::theme::spacing().space_m;
+
+        let crossfade_secs = self.config.crossfade_secs;
+        let crossfade_label = if crossfade_secs > 0.0 {
+            format!("{crossfade_secs:.0} s")
+        } else {
+            "Off".to_string()
+        };
+
+        let crossfade = widget::column::with_capacity(3)
+            .push(
+                widget::row::with_capacity(2)
+                    .push(widget::text::heading(fl!("crossfade")).width(Length::Fill))
+                    .push(widget::text::body(crossfade_label)),
+            )
+            .push(
+                widget::slider(0.0..=12.0, crossfade_secs, Message::SetCrossfade)
+                    .on_release(Message::SaveConfig)
+                    .step(1.0)
+                    .width(Length::Fill),
+            )
+            .push(widget::text::caption(
+                "Fade between tracks. Albums always play without crossfading.",
+            ))
+            .spacing(space_s);
+
+        let selected_normalization = NormalizationMode::all()
+            .iter()
+            .position(|m| *m == self.config.normalization);
+        let normalization = widget::column::with_capacity(2)
+            .push(
+                widget::row::with_capacity(2)
+                    .push(widget::text::heading(fl!("normalization")).width(Length::Fill))
+                    .push(widget::dropdown(
+                        NormalizationMode::all_labels(),
+                        selected_normalization,
+                        Message::SetNormalization,
+                    ))
+                    .align_y(Alignment::Center),
+            )
+            .push(widget::text::caption(
+                "Play tracks at a consistent loudness. Album mode keeps the level differences within an album.",
+            ))
+            .spacing(space_s);
+
+        let selected_preference = StreamPreference::all()
+            .iter()
+            .position(|p| *p == self.config.stream_preference);
+        let stream_quality = widget::column::with_capacity(2)
+            .push(
+                widget::row::with_capacity(2)
+                    .push(widget::text::heading(fl!("stream-quality")).width(Length::Fill))
+                    .push(widget::dropdown(
+                        StreamPreference::all_labels(),
+                        selected_preference,
+                        Message::SetStreamPreference,
+                    ))
+                    .align_y(Alignment::Center),
+            )
+            .push(widget::text::caption(
+                "Which stream format to play. Others are tried in turn if the preferred one fails.",
+            ))
+            .spacing(space_s);
+
+        let selected_device = match &self.config.output_device {
+            Some(device) => self.output_devices.iter().position(|d| d == device),
+            None => Some(0),
+        };
+        let mut device_caption = String::new();
+        if let Some(device) = &self.current_output_device {
+            device_caption.push_str(&format!("Playing on {device}. "));
+        }
+        device_caption.push_str("Falls back to the system default while the chosen device is unplugged.");
+        let output_device = widget::column::with_capacity(2)
+            .push(
+                widget::row::with_capacity(2)
+                    .push(widget::text::heading(fl!("output-device")).width(Length::Fill))
+                    .push(widget::dropdown(
+                        &self.output_devices,
+                        selected_device,
+                        Message::SetOutputDevice,
+                    ))
+                    .align_y(Alignment::Center),
+            )
+            .push(widget::text::caption(device_caption))
+            .spacing(space_s);
+
+        let selected_volume_mode = VolumeMode::all()
+            .iter()
+            .position(|m| *m == self.config.volume_mode);
+        let volume_mode = widget::column::with_capacity(2)
+            .push(
+                widget::row::with_capacity(2)
+                    .push(widget::text::heading(fl!("volume-control")).width(Length::Fill))
+                    .push(widget::dropdown(
+                        VolumeMode::all_labels(),
+                        selected_volume_mode,
+                        Message::SetVolumeMode,
+                    ))
+                    .align_y(Alignment::Center),
+            )
+            .push(widget::text::caption(
+                "Whether the volume slider changes only this app or the whole desktop.",
+            ))
+            .spacing(space_s);
+
+        let cache_limit_mb = self.config.cache_limit_mb;
+        let cache_limit_label = if cache_limit_mb >= 1024 {
+            format!("{:.1} GB", cache_limit_mb as f32 / 1024.0)
+        } else {
+            format!("{cache_limit_mb} MB")
+        };
+        let cache_limit = widget::column::with_capacity(3)
+            .push(
+                widget::row::with_capacity(2)
+                    .push(widget::text::heading(fl!("audio-cache")).width(Length::Fill))
+                    .push(widget::text::body(cache_limit_label)),
+            )
+            .push(
+                widget::slider(256.0..=8192.0, cache_limit_mb as f32, Message::SetCacheLimit)
+                    .on_release(Message::CommitCacheLimit)
+                    .step(256.0)
+                    .width(Length::Fill),
+            )
+            .push(widget::text::caption(
+                "Played and preloaded tracks are kept on disk so replays don't download them again. The least recently played are removed first.",
+            ))
+            .spacing(space_s);
+
+        let source_count = self.config.stream_sources.len();
+        let mut stream_sources = widget::column::with_capacity(source_count + 2)
+            .push(widget::text::heading(fl!("stream-sources")))
+            .spacing(space_s);
+        for (index, kind) in self.config.stream_sources.iter().enumerate() {
+            stream_sources = stream_sources.push(
+                widget::row::with_capacity(3)
+                    .push(widget::text::body(format!("{}. {}", index + 1, kind.name())).width(Length::Fill))
+                    .push(
+                        widget::button::icon(widget::icon::from_name("go-up-symbolic"))
+                            .on_press_maybe((index > 0).then_some(Message::MoveStreamSource(index, true))),
+                    )
+                    .push(
+                        widget::button::icon(widget::icon::from_name("go-down-symbolic")).on_press_maybe(
+                            (index + 1 < source_count).then_some(Message::MoveStreamSource(index, false)),
+                        ),
+                    )
+                    .align_y(Alignment::Center),
+            );
+        }
+        stream_sources = stream_sources.push(widget::text::caption(
+            "Where to look for a track's audio, from the top. Each source that can't play a track passes it on to the next.",
+        ));
+
+        let ytdlp_status = match &self.ytdlp_version {
+            None => "Checking…".to_string(),
+            Some(Ok(version)) => format!("Version {version}. Used for DRM-protected tracks."),
+            Some(Err(YtDlpError::Missing(_))) => {
+                "Not found. Install yt-dlp or enter the path to its binary to play DRM-protected tracks.".to_string()
+            }
+            Some(Err(e)) => format!("Doesn't work: {e}"),
+        };
+        let ytdlp = widget::column::with_capacity(3)
+            .push(widget::text::heading("yt-dlp"))
+            .push(
+                widget::text_input("yt-dlp", &self.ytdlp_path_input)
+                    .on_input(Message::YtDlpPathInput)
+                    .on_submit(|_| Message::SetYtDlpPath)
+                    .width(Length::Fill),
+            )
+            .push(widget::text::caption(ytdlp_status))
+            .spacing(space_s);
+
+        let export_placeholder = export::default_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+        let export_dir = widget::column::with_capacity(3)
+            .push(widget::text::heading(fl!("export-folder")))
+            .push(
+                widget::text_input(export_placeholder, &self.export_dir_input)
+                    .on_input(Message::ExportDirInput)
+                    .on_submit(|_| Message::SetExportDir)
+                    .width(Length::Fill),
+            )
+            .push(widget::text::caption(
+                "Exported tracks are saved here as MP3 or M4A, tagged with their title, artist, album and artwork.",
+            ))
+            .spacing(space_s);
+
+        let settings = widget::column::with_capacity(9)
+            .push(crossfade)
+            .push(normalization)
+            .push(stream_quality)
+            .push(stream_sources)
+            .push(ytdlp)
+            .push(output_device)
+            .push(volume_mode)
+            .push(cache_limit)
+            .push(export_dir)
+            .spacing(space_m);
+
+        widget::container(settings)
+            .width(Length::Fill)
+            .padding([0, space_m])
+            .into()
+    }
+
+    /// View for the equalizer sidebar: preset picker and one slider per band
+    fn view_equalizer(&self) -> Element<'_, Message> {
+        let space_s = cosmic::theme::spacing().space_s;
+        let space_m = cosmic::theme::spacing().space_m;
+
+        let selected_preset = EqualizerPreset::all()
+            .iter()
+            .position(|p| *p == self.config.equalizer_preset);
+        let preset_row = widget::row::with_capacity(2)
+            .push(widget::text::heading("Preset").width(Length::Fill))
+            .push(widget::dropdown(
+                EqualizerPreset::all_labels(),
+                selected_preset,
+                Message::SetEqualizerPreset,
+            ))
+            .align_y(Alignment::Center);
+
+        let gains = self.config.equalizer_gains();
+        let mut bands = widget::row::with_capacity(gains.len()).spacing(space_s / 2);
+        for (band, (&frequency, &gain)) in BAND_FREQUENCIES.iter().zip(&gains).enumerate() {
+            let label = if frequency >= 1000.0 {
+                format!("{}k", frequency / 1000.0)
+            } else {
+                format!("{frequency}")
+            };
+            let column = widget::column::with_capacity(3)
+                .push(widget::text::caption(format!("{gain:+.0}")))
+                .push(
+                    cosmic::iced::widget::vertical_slider(-12.0..=12.0, gain, move |gain| {
+                        Message::SetEqualizerBand(band, gain)
+                    })
+                    .on_release(Message::SaveConfig)
+                    .step(0.5)
+                    .height(Length::Fixed(160.0)),
+                )
+                .push(widget::text::caption(label))
+                .spacing(space_s / 2)
+                .align_x(Alignment::Center)
+                .width(Length::Fill);
+            bands = bands.push(column);
+        }
+
+        let content = widget::column::with_capacity(3)
+            .push(preset_row)
+            .push(bands)
+            .push(widget::text::caption("Gains in dB. Moving a band switches to the custom curve."))
+            .spacing(space_m);
+
+        widget::container(content)
+            .width(Length::Fill)
+            .padding([0, space_m])
+            .into()
+    }
+
+    /// View for the Now Playing sidebar: the current track over a spectrum
+    /// of what's playing
+    fn view_now_playing(&self) -> Element<'_, Message> {
+        let space_s = cosmic::theme::spacing().space_s;
+        let space_m = cosmic::theme::spacing().space_m;
+
+        let Some(track) = &self.current_track else {
+            return widget::container(widget::text::body("No track playing"))
+                .width(Length::Fill)
+                .padding(space_m)
+                .into();
+        };
+
+        let artwork: Element<_> = match track
+            .artwork_url
+            .as_ref()
+            .and_then(|url| self.artwork_cache.get(url))
+        {
+            Some(handle) => widget::image(handle.clone())
+                .width(Length::Fixed(240.0))
+                .height(Length::Fixed(240.0))
+                .content_fit(cosmic::iced::ContentFit::Cover)
+                .into(),
+            None => widget::icon::from_name("audio-x-generic-symbolic")
+                .size(240)
+                .apply(Element::from),
+        };
+
+        let mut details = widget::column::with_capacity(3)
+            .push(widget::text::title3(&track.title))
+            .push(widget::text::body(&track.user.username))
+            .spacing(space_s / 2)
+            .align_x(Alignment::Center);
+        if let Some(format) = &self.stream_format {
+            let label = format.label();
+            if !label.is_empty() {
+                details = details.push(widget::text::caption(label));
+            }
+        }
+
+        // One accent-coloured bar per frequency band, growing from the bottom
+        const SPECTRUM_HEIGHT: f32 = 160.0;
+        let mut bars = widget::row::with_capacity(self.spectrum.len())
+            .spacing(space_s / 4)
+            .height(Length::Fixed(SPECTRUM_HEIGHT))
+            .align_y(Alignment::End);
+        for &level in &self.spectrum {
+            let bar = widget::container(widget::vertical_space())
+                .width(Length::Fill)
+                .height(Length::Fixed((level * SPECTRUM_HEIGHT).max(1.0)))
+                .class(cosmic::theme::Container::custom(|theme| {
+                    let cosmic = theme.cosmic();
+                    cosmic::iced_widget::container::Style {
+                        background: Some(cosmic::iced::Background::Color(
+                            cosmic.accent_color().into(),
+                        )),
+                        border: cosmic::iced::Border {
+                            radius: cosmic.corner_radii.radius_xs.into(),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    }
+                }));
+            bars = bars.push(bar);
+        }
+
+        let content = widget::column::with_capacity(3)
+            .push(artwork)
+            .push(details)
+            .push(bars)
+            .spacing(space_m)
+            .align_x(Alignment::Center);
+
+        widget::container(content)
+            .width(Length::Fill)
+            .padding([0, space_m])
+            .into()
+    }
+
+    /// View for the sleep timer sidebar: presets, a custom length and the
+    /// running timer
+    fn view_sleep_timer(&self) -> Element<'_, Message> {
+        let space_s = cosmic::theme::spacing().space_s;
+        let space_m = cosmic::theme::spacing().space_m;
+
+        let status = match self.sleep_timer_label() {
+            Some(label) => format!("{label}. Playback fades out over a few seconds and pauses."),
+            None => "Fade out and pause playback after a while.".to_string(),
+        };
+
+        let mut presets = widget::row::with_capacity(SLEEP_TIMER_PRESETS.len() + 1).spacing(space_s);
+        for minutes in SLEEP_TIMER_PRESETS {
+            presets = presets.push(
+                widget::button::standard(format!("{minutes} min"))
+                    .on_press(Message::StartSleepTimer(minutes)),
+            );
+        }
+
+        let custom_minutes = self.custom_sleep_minutes();
+        let custom = widget::row::with_capacity(2)
+            .push(
+                widget::text_input("Minutes (1–1440)", &self.sleep_timer_input)
+                    .on_input(Message::SleepTimerInput)
+                    .on_submit(|_| Message::StartCustomSleepTimer)
+                    .width(Length::Fill),
+            )
+            .push(
+                widget::button::standard("Start")
+                    .on_press_maybe(custom_minutes.map(|_| Message::StartCustomSleepTimer)),
+            )
+            .spacing(space_s)
+            .align_y(Alignment::Center);
+
+        let content = widget::column::with_capacity(5)
+            .push(widget::text::caption(status))
+            .push(presets)
+            .push(custom)
+            .push(
+                widget::button::standard("End of current track")
+                    .on_press(Message::SleepAtEndOfTrack),
+            )
+            .push(
+                widget::button::destructive("Cancel timer")
+                    .on_press_maybe(self.sleep_timer.is_some().then_some(Message::CancelSleepTimer)),
+            )
+            .spacing(space_m);
+
+        widget::container(content)
+            .width(Length::Fill)
+            .padding([0, space_m])
+            .into()
+    }
+
     /// View for the queue sidebar showing upcoming tracks
     fn view_queue(&self) -> Element<'_, Message> {
         let space_s = cosmic::theme::spacing().space_s;
@@ -2848,6 +4488,14 @@ pub enum ContextPage {
     About,
     /// Shows upcoming tracks in the current playlist
     Queue,
+    /// Playback settings
+    Settings,
+    /// Equalizer bands and presets
+    Equalizer,
+    /// The current track with a spectrum visualizer
+    NowPlaying,
+    /// Sleep timer presets and countdown
+    SleepTimer,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Second-order IIR filter shared by the loudness meter and the equalizer.
+
+/// Second-order IIR filter (transposed direct form II)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// Create a filter from normalized coefficients (`a0` already divided out)
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, z: [0.0; 2] }
+    }
+
+    /// Peaking EQ filter (Audio EQ Cookbook) boosting or cutting `gain_db`
+    /// around `frequency`
+    pub fn peaking(frequency: f64, q: f64, gain_db: f64, sample_rate: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * std::f64::consts::PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha / a;
+        Self::new(
+            [
+                (1.0 + alpha * a) / a0,
+                -2.0 * cos_w0 / a0,
+                (1.0 - alpha * a) / a0,
+            ],
+            [-2.0 * cos_w0 / a0, (1.0 - alpha / a) / a0],
+        )
+    }
+
+    /// Replace the coefficients, keeping the filter state so a running signal
+    /// doesn't click
+    pub fn set_coefficients(&mut self, other: &Biquad) {
+        self.b = other.b;
+        self.a = other.a;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
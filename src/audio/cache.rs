@@ -1,12 +1,26 @@
 // SPDX-License-Identifier: MPL-2.0
 
-//! Disk-based audio cache for preloaded tracks.
+//! Disk-based audio cache for preloaded and fully streamed tracks.
 //!
 //! Stores downloaded audio data in `~/.cache/cosmic-soundcloud/audio/`
-//! using the track ID as the filename. This allows preloaded next-track
-//! data to persist briefly without consuming application memory.
+//! using the track ID as the filename, next to an `index.json` recording
+//! each file's codec, source transcoding, size and last access. Tracks stay
+//! cached across plays; once the cache grows past its byte budget, the
+//! least recently played tracks are evicted.
 
+use crate::api::{AudioCodec, StreamFormat, TranscodingFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
 use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Default cache budget in bytes
+pub const DEFAULT_BUDGET: u64 = 1024 * 1024 * 1024;
+
+/// How long access times may wait in memory before the index is saved
+const SAVE_DELAY: Duration = Duration::from_secs(10);
 
 /// Return the audio cache directory (`~/.cache/cosmic-soundcloud/audio/`).
 fn cache_dir() -> Option<PathBuf> {
@@ -18,35 +32,280 @@ fn cache_path(track_id: u64) -> Option<PathBuf> {
     cache_dir().map(|d| d.join(format!("{track_id}.audio")))
 }
 
-/// Check whether audio data is cached for the given track.
-pub fn has_cached(track_id: u64) -> bool {
-    cache_path(track_id).is_some_and(|p| p.exists())
+/// Return the path of the cache index.
+fn index_path() -> Option<PathBuf> {
+    cache_dir().map(|d| d.join("index.json"))
 }
 
-/// Read cached audio data for a track. Returns `None` if not cached.
-pub fn read_cached(track_id: u64) -> Option<Vec<u8>> {
-    let path = cache_path(track_id)?;
-    std::fs::read(path).ok()
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
 }
 
-/// Write audio data to the cache for a track.
-pub fn write_cached(track_id: u64, data: &[u8]) -> Result<(), String> {
-    let dir = cache_dir().ok_or("No cache directory available")?;
-    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create cache dir: {e}"))?;
-    let path = dir.join(format!("{track_id}.audio"));
-    std::fs::write(path, data).map_err(|e| format!("Failed to write cache file: {e}"))
+/// Index record of one cached track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub codec: Option<AudioCodec>,
+    /// Transcoding the audio was downloaded from
+    pub transcoding: Option<TranscodingFormat>,
+    /// Quality tier of that transcoding
+    #[serde(default)]
+    pub quality: Option<String>,
+    /// Size of the cached file in bytes
+    pub size: u64,
+    /// When the track was last cached or played (Unix seconds)
+    pub last_access: u64,
 }
 
-/// Remove a single track from the cache.
-pub fn remove_cached(track_id: u64) {
-    if let Some(path) = cache_path(track_id) {
-        let _ = std::fs::remove_file(path);
+/// The cache index, with LRU eviction down to a byte budget
+pub struct AudioCache {
+    entries: HashMap<u64, CacheEntry>,
+    budget: u64,
+    /// When the index first changed without being saved
+    unsaved_since: Option<Instant>,
+}
+
+impl AudioCache {
+    /// Load the index from disk. Files without an index entry (from before the
+    /// index existed) are adopted, and entries whose file is gone are dropped.
+    pub fn load(budget: u64) -> Self {
+        let mut entries: HashMap<u64, CacheEntry> = index_path()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+
+        let mut files = HashMap::new();
+        if let Some(dir) = cache_dir()
+            && let Ok(read_dir) = std::fs::read_dir(dir)
+        {
+            for entry in read_dir.flatten() {
+                let path = entry.path();
+                if path.extension().is_none_or(|ext| ext != "audio") {
+                    continue;
+                }
+                let Some(track_id) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                else {
+                    continue;
+                };
+                if let Ok(metadata) = entry.metadata() {
+                    files.insert(track_id, metadata);
+                }
+            }
+        }
+
+        let indexed = entries.len();
+        entries.retain(|track_id, _| files.contains_key(track_id));
+        let mut changed = entries.len() != indexed;
+        for (track_id, metadata) in files {
+            changed |= !entries.contains_key(&track_id);
+            entries.entry(track_id).or_insert_with(|| CacheEntry {
+                codec: None,
+                transcoding: None,
+                quality: None,
+                size: metadata.len(),
+                last_access: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_secs()),
+            });
+        }
+
+        // Eviction waits for `set_budget`, so a budget larger than the
+        // default doesn't lose tracks at startup
+        let mut cache = Self {
+            entries,
+            budget,
+            unsaved_since: None,
+        };
+        if changed {
+            cache.save();
+        }
+        cache
+    }
+
+    /// Check whether audio data is cached for the given track.
+    pub fn contains(&self, track_id: u64) -> bool {
+        self.entries.contains_key(&track_id)
+    }
+
+    /// Codec and quality of a cached track, as far as the index knows
+    pub fn format(&self, track_id: u64) -> Option<StreamFormat> {
+        self.entries.get(&track_id).map(|entry| StreamFormat {
+            codec: entry.codec,
+            quality: entry.quality.clone(),
+            bitrate: None,
+        })
+    }
+
+    /// Open a track's cached audio, marking it as recently used. The index
+    /// is saved later, by `save_if_due`. Returns `None` if not cached.
+    pub fn open(&mut self, track_id: u64) -> Option<File> {
+        self.entries.get(&track_id)?;
+        let Some(file) = cache_path(track_id).and_then(|path| File::open(path).ok()) else {
+            // The file was removed behind our back
+            self.entries.remove(&track_id);
+            self.mark_unsaved();
+            return None;
+        };
+        if let Some(entry) = self.entries.get_mut(&track_id) {
+            entry.last_access = now();
+        }
+        self.mark_unsaved();
+        Some(file)
+    }
+
+    /// Save changes `open` made once they've waited long enough, so playing
+    /// tracks doesn't rewrite the index every time
+    pub fn save_if_due(&mut self) {
+        if self.unsaved_since.is_some_and(|since| since.elapsed() >= SAVE_DELAY) {
+            self.save();
+        }
+    }
+
+    /// Remove every cached track, files and index alike
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.unsaved_since = None;
+        if let Some(dir) = cache_dir() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    /// Save any changes still waiting, e.g. when the player shuts down
+    pub fn flush(&mut self) {
+        if self.unsaved_since.is_some() {
+            self.save();
+        }
+    }
+
+    fn mark_unsaved(&mut self) {
+        self.unsaved_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Record a track written with [`write_file`], then evict older tracks to
+    /// stay within the budget. `transcoding` is the one the audio came from,
+    /// if any.
+    pub fn insert(
+        &mut self,
+        track_id: u64,
+        size: u64,
+        transcoding: Option<TranscodingFormat>,
+        format: &StreamFormat,
+    ) -> Result<(), String> {
+        if size > self.budget {
+            if let Some(path) = cache_path(track_id) {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(format!("Track is larger than the cache budget ({size} bytes)"));
+        }
+
+        self.entries.insert(
+            track_id,
+            CacheEntry {
+                codec: format.codec,
+                transcoding,
+                quality: format.quality.clone(),
+                size,
+                last_access: now(),
+            },
+        );
+        self.evict(Some(track_id));
+        self.save();
+        Ok(())
+    }
+
+    /// Change the byte budget, evicting tracks if the cache is now too big
+    pub fn set_budget(&mut self, budget: u64) {
+        self.budget = budget;
+        if self.evict(None) {
+            self.save();
+        }
+    }
+
+    /// Total size of the cached files in bytes
+    pub fn size(&self) -> u64 {
+        self.entries.values().map(|e| e.size).sum()
+    }
+
+    /// Remove least recently used tracks until the cache fits its budget,
+    /// never removing `keep`. Returns whether anything was removed.
+    fn evict(&mut self, keep: Option<u64>) -> bool {
+        let mut total = self.size();
+        if total <= self.budget {
+            return false;
+        }
+
+        let mut by_age: Vec<(u64, u64, u64)> = self
+            .entries
+            .iter()
+            .filter(|(track_id, _)| Some(**track_id) != keep)
+            .map(|(track_id, e)| (e.last_access, *track_id, e.size))
+            .collect();
+        by_age.sort_unstable();
+
+        for (_, track_id, size) in by_age {
+            if total <= self.budget {
+                break;
+            }
+            if let Some(path) = cache_path(track_id) {
+                let _ = std::fs::remove_file(path);
+            }
+            self.entries.remove(&track_id);
+            total -= size;
+            eprintln!("[cache] Evicted track {track_id} ({size} bytes)");
+        }
+        true
+    }
+
+    fn save(&mut self) {
+        self.unsaved_since = None;
+        if let Err(e) = self.write_index() {
+            eprintln!("[cache] Failed to save index: {e}");
+        }
+    }
+
+    fn write_index(&self) -> Result<(), String> {
+        let path = index_path().ok_or("No cache directory available")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create cache dir: {e}"))?;
+        }
+        let data = serde_json::to_vec(&self.entries).map_err(|e| e.to_string())?;
+        std::fs::write(path, data).map_err(|e| format!("Failed to write cache index: {e}"))
     }
 }
 
-/// Remove all cached audio files.
-pub fn clear_cache() {
-    if let Some(dir) = cache_dir() {
-        let _ = std::fs::remove_dir_all(dir);
+/// Copy a track's audio into the cache directory, returning its size. This
+/// blocks until the copy is done, so run it off the player loop and
+/// [`AudioCache::insert`] the track afterwards.
+pub fn write_file(track_id: u64, data: &mut impl Read) -> Result<u64, String> {
+    let path = cache_path(track_id).ok_or("No cache directory available")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create cache dir: {e}"))?;
     }
+    // Readers never see a partly written file
+    let partial = path.with_extension("part");
+    let size = File::create(&partial)
+        .and_then(|mut file| std::io::copy(data, &mut file))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&partial);
+            format!("Failed to write cache file: {e}")
+        })?;
+    std::fs::rename(&partial, &path).map_err(|e| format!("Failed to write cache file: {e}"))?;
+    Ok(size)
+}
+
+/// Whether a track's audio is in the cache, for callers without the index
+pub fn is_cached(track_id: u64) -> bool {
+    cache_path(track_id).is_some_and(|path| path.exists())
+}
+
+/// Open a track's cached audio without the index, e.g. to export it
+pub fn open_file(track_id: u64) -> Option<File> {
+    cache_path(track_id).and_then(|path| File::open(path).ok())
 }
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Downloading whole tracks to disk.
+//!
+//! Shared by the player's preloading and the offline store, so both fetch
+//! HLS playlists (with variant fallback and AES-128 decryption) and
+//! progressive streams the same way. DRM-protected playlists fail; finding
+//! another stream is up to the stream resolvers. Tracks are written
+//! to an anonymous temporary file as they arrive, so memory use doesn't grow
+//! with their length.
+
+use super::fmp4::SegmentDemuxer;
+use super::hls;
+use crate::api::StreamPreference;
+use futures_util::StreamExt;
+use reqwest::Client;
+use std::fs::File;
+use std::io::{Seek, Write};
+
+/// Fetches complete tracks from stream URLs
+pub struct Downloader {
+    client: Client,
+    preference: StreamPreference,
+}
+
+impl Downloader {
+    pub fn new(client: Client, preference: StreamPreference) -> Self {
+        Self { client, preference }
+    }
+
+    /// Download a track's audio data into a temporary file, returned rewound
+    /// to the start. `progress` is called with the number of HLS segments
+    /// downloaded so far and the total.
+    pub async fn download(&self, url: &str, progress: impl Fn(usize, usize)) -> Option<File> {
+        let mut file = tempfile::tempfile()
+            .inspect_err(|e| eprintln!("[download] Failed to create temp file: {e}"))
+            .ok()?;
+        if url.contains(".m3u8") {
+            self.download_hls(url, &mut file, &progress).await?;
+        } else {
+            self.download_progressive(url, &mut file).await?;
+        }
+
+        if file.stream_position().ok()? == 0 {
+            return None;
+        }
+        file.rewind().ok()?;
+        Some(file)
+    }
+
+    /// Download HLS audio data, writing the concatenated segment bytes to `file`
+    async fn download_hls(&self, url: &str, file: &mut File, progress: &impl Fn(usize, usize)) -> Option<()> {
+        let playlist = hls::fetch_playlist(&self.client, url, self.preference).await.ok()?;
+        self.download_segments(&playlist, file, progress).await
+    }
+
+    /// Download all HLS segments into `file`, moving on to the next variant of
+    /// a master playlist if one fails or is DRM-protected.
+    async fn download_segments(
+        &self,
+        playlist: &hls::HlsStream,
+        file: &mut File,
+        progress: &impl Fn(usize, usize),
+    ) -> Option<()> {
+        if self.download_variant(playlist, file, progress).await.is_some() {
+            return Some(());
+        }
+
+        let mut playlist = hls::fetch_fallback(&self.client, playlist).await?;
+        loop {
+            eprintln!("[download] Variant failed, trying another");
+            if self.download_variant(&playlist, file, progress).await.is_some() {
+                return Some(());
+            }
+            playlist = hls::fetch_fallback(&self.client, &playlist).await?;
+        }
+    }
+
+    /// Download all segments of one media playlist into `file`, replacing
+    /// whatever a failed variant left there
+    async fn download_variant(
+        &self,
+        playlist: &hls::HlsStream,
+        file: &mut File,
+        progress: &impl Fn(usize, usize),
+    ) -> Option<()> {
+        if playlist.segments.is_empty() {
+            return None;
+        }
+        // Plain AES-128 is decrypted while downloading, anything else can't be
+        if let Some(enc) = playlist.encryption()
+            && enc.needs_fallback()
+        {
+            eprintln!("[download] Variant is DRM-protected ({})", enc.describe());
+            return None;
+        }
+
+        file.set_len(0).ok()?;
+        file.rewind().ok()?;
+        let decryptor = hls::SegmentDecryptor::for_playlist(&self.client, playlist)
+            .await
+            .inspect_err(|e| eprintln!("[download] {e}"))
+            .ok()?;
+
+        // Download init segment if present, which also tells fMP4 apart
+        let init = hls::download_init_segment(&self.client, playlist, decryptor.as_ref())
+            .await
+            .ok()?;
+        let (demuxer, header) = SegmentDemuxer::new(init)
+            .inspect_err(|e| eprintln!("[download] {e}"))
+            .ok()?;
+        write(file, &header)?;
+
+        // Download all segments, several at a time
+        let total = playlist.segments.len();
+        let mut downloads =
+            std::pin::pin!(hls::download_segments(&self.client, playlist, decryptor.as_ref()));
+        while let Some((request, result)) = downloads.next().await {
+            let parts = result
+                .inspect_err(|e| eprintln!("[download] Segment {} failed: {e}", request.start))
+                .ok()?;
+            for data in parts {
+                let data = demuxer
+                    .demux(data)
+                    .inspect_err(|e| eprintln!("[download] Segment {} failed: {e}", request.start))
+                    .ok()?;
+                write(file, &data)?;
+            }
+            progress(request.end, total);
+        }
+
+        Some(())
+    }
+
+    /// Download a progressive stream into `file`, chunk by chunk
+    async fn download_progressive(&self, url: &str, file: &mut File) -> Option<()> {
+        let mut response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .inspect_err(|e| eprintln!("[download] {e}"))
+            .ok()?;
+        while let Some(chunk) = response.chunk().await.ok()? {
+            write(file, &chunk)?;
+        }
+        Some(())
+    }
+}
+
+fn write(file: &mut File, data: &[u8]) -> Option<()> {
+    file.write_all(data)
+        .inspect_err(|e| eprintln!("[download] Failed to write temp file: {e}"))
+        .ok()
+}
@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! 10-band graphic equalizer applied between the decoder and the sink.
+//!
+//! Every source the player appends runs through an [`Equalizer`]. All of them
+//! share the player's [`EqualizerControl`], so a new curve takes effect on the
+//! playing track within a few milliseconds, without restarting it.
+
+use super::biquad::Biquad;
+use parking_lot::Mutex;
+use rodio::Source;
+use rodio::source::SeekError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Number of equalizer bands
+pub const BAND_COUNT: usize = 10;
+
+/// Centre frequency of each band (Hz)
+pub const BAND_FREQUENCIES: [f32; BAND_COUNT] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1_000.0, 2_000.0, 4_000.0, 8_000.0, 16_000.0,
+];
+
+/// Bandwidth of each band - one octave
+const BAND_Q: f64 = std::f64::consts::SQRT_2;
+
+/// Number of samples between checks for a new curve
+const CONTROL_INTERVAL: u32 = 1024;
+
+#[derive(Default)]
+struct EqualizerShared {
+    /// Bumped whenever the curve changes
+    generation: AtomicU32,
+    gains: Mutex<[f32; BAND_COUNT]>,
+}
+
+/// Handle used by the player to change the curve of every playing source
+#[derive(Clone, Default)]
+pub struct EqualizerControl(Arc<EqualizerShared>);
+
+impl EqualizerControl {
+    /// Set the gain of each band (dB). All zeros bypasses the equalizer.
+    pub fn set_gains(&self, gains: [f32; BAND_COUNT]) {
+        *self.0.gains.lock() = gains;
+        self.0.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Source wrapper running samples through the equalizer bands
+pub struct Equalizer<S> {
+    inner: S,
+    control: EqualizerControl,
+    seen_generation: Option<u32>,
+    until_check: u32,
+    /// One filter per band, per channel
+    filters: Vec<[Biquad; BAND_COUNT]>,
+    /// Bands that are actually boosting or cutting
+    active: Vec<usize>,
+    /// Attenuation that leaves headroom for the largest boost
+    preamp: f64,
+    channel: usize,
+}
+
+impl<S> Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S, control: EqualizerControl) -> Self {
+        let channels = usize::from(inner.channels().max(1));
+        Self {
+            inner,
+            control,
+            seen_generation: None,
+            until_check: 0,
+            filters: vec![[Biquad::default(); BAND_COUNT]; channels],
+            active: Vec::new(),
+            preamp: 1.0,
+            channel: 0,
+        }
+    }
+
+    fn check_control(&mut self) {
+        self.until_check = CONTROL_INTERVAL;
+        let generation = self.control.0.generation.load(Ordering::Acquire);
+        if self.seen_generation == Some(generation) {
+            return;
+        }
+        self.seen_generation = Some(generation);
+
+        let gains = *self.control.0.gains.lock();
+        let sample_rate = f64::from(self.inner.sample_rate());
+        self.active.clear();
+        for (band, (&frequency, &gain_db)) in BAND_FREQUENCIES.iter().zip(&gains).enumerate() {
+            // Bands at or above Nyquist can't be represented
+            if gain_db == 0.0 || f64::from(frequency) >= sample_rate / 2.0 {
+                continue;
+            }
+            let filter = Biquad::peaking(
+                f64::from(frequency),
+                BAND_Q,
+                f64::from(gain_db),
+                sample_rate,
+            );
+            for channel in &mut self.filters {
+                channel[band].set_coefficients(&filter);
+            }
+            self.active.push(band);
+        }
+
+        let max_boost = gains.iter().copied().fold(0.0f32, f32::max);
+        self.preamp = 10f64.powf(-f64::from(max_boost) / 20.0);
+    }
+}
+
+impl<S> Iterator for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.until_check == 0 {
+            self.check_control();
+        }
+        self.until_check -= 1;
+
+        let sample = self.inner.next()?;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.filters.len();
+        if self.active.is_empty() {
+            return Some(sample);
+        }
+
+        let filters = &mut self.filters[channel];
+        let mut value = f64::from(sample) * self.preamp;
+        for &band in &self.active {
+            value = filters[band].process(value);
+        }
+        Some(value as f32)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Exporting tracks to tagged audio files.
+//!
+//! The audio comes from wherever the stream resolvers find it, so offline and
+//! cached tracks export without the network, and anything else is downloaded
+//! the way the player preloads it. MP3 is written as it is behind an ID3v2
+//! tag. AAC arrives as ADTS frames and is remuxed into M4A with iTunes-style
+//! tags. Both get the track's title, artist, album when known, and artwork.
+
+use super::cache;
+use super::download::Downloader;
+use super::m4a;
+use super::resolver::{ResolverChain, StreamSource};
+use crate::api::{StreamPreference, Track};
+use id3::TagLike;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Container an export is written in
+#[derive(Debug, Clone, Copy)]
+enum Container {
+    Mp3,
+    M4a,
+}
+
+impl Container {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::M4a => "m4a",
+        }
+    }
+}
+
+/// Where exports go unless another folder is set: the user's music folder
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::audio_dir().or_else(|| dirs::home_dir().map(|home| home.join("Music")))
+}
+
+/// Export a track's audio into `dir` as a tagged file named after its artist
+/// and title. Returns the path of the new file.
+pub async fn export_track(
+    chain: ResolverChain,
+    track: Track,
+    album: Option<String>,
+    dir: PathBuf,
+    preference: StreamPreference,
+) -> Result<PathBuf, String> {
+    let audio = fetch_audio(&chain, &track, preference).await?;
+
+    // Artwork is nice to have, a file without it still plays
+    let artwork = match &track.artwork_url {
+        Some(url) => fetch_artwork(url)
+            .await
+            .inspect_err(|e| eprintln!("[export] {e}"))
+            .ok(),
+        None => None,
+    };
+
+    tokio::task::spawn_blocking(move || write_export(audio, &track, album.as_deref(), artwork.as_deref(), &dir))
+        .await
+        .map_err(|e| format!("Export task failed: {e}"))?
+}
+
+/// The track's audio, from the first resolver that has it
+async fn fetch_audio(chain: &ResolverChain, track: &Track, preference: StreamPreference) -> Result<File, String> {
+    let resolved = chain.resolve(track, 0).await.map_err(|declined| {
+        let reasons: Vec<String> = declined.iter().map(ToString::to_string).collect();
+        format!("No stream available ({})", reasons.join("; "))
+    })?;
+    match resolved.source {
+        StreamSource::Offline(path) => {
+            File::open(&path).map_err(|e| format!("Failed to open {}: {e}", path.display()))
+        }
+        StreamSource::Cached => cache::open_file(track.id).ok_or_else(|| "Track is no longer cached".into()),
+        StreamSource::Stream { url, .. } => Downloader::new(reqwest::Client::new(), preference)
+            .download(&url, |_, _| {})
+            .await
+            .ok_or_else(|| "Failed to download audio".into()),
+    }
+}
+
+/// Download cover art, in the largest size SoundCloud serves by default
+async fn fetch_artwork(url: &str) -> Result<Vec<u8>, String> {
+    let url = url.replace("-large.", "-t500x500.");
+    let response = reqwest::get(&url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Failed to download artwork: {e}"))?;
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to download artwork: {e}"))?;
+    Ok(bytes.to_vec())
+}
+
+fn write_export(
+    mut audio: File,
+    track: &Track,
+    album: Option<&str>,
+    artwork: Option<&[u8]>,
+    dir: &Path,
+) -> Result<PathBuf, String> {
+    let audio_start = skip_id3(&mut audio).map_err(|e| format!("Failed to read audio: {e}"))?;
+    let mut head = [0u8; 4];
+    audio
+        .read_exact(&mut head)
+        .map_err(|e| format!("Failed to read audio: {e}"))?;
+    audio
+        .seek(SeekFrom::Start(audio_start))
+        .map_err(|e| format!("Failed to read audio: {e}"))?;
+
+    let container = if m4a::is_adts(&head) {
+        Container::M4a
+    } else if is_mp3(&head) {
+        Container::Mp3
+    } else {
+        return Err("This track's audio format can't be exported".into());
+    };
+
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    let path = unique_path(dir, &file_stem(track), container.extension());
+    let partial = path.with_extension("part");
+    let result = File::create(&partial)
+        .map_err(|e| format!("Failed to create {}: {e}", partial.display()))
+        .and_then(|file| {
+            let mut out = io::BufWriter::new(file);
+            match container {
+                Container::Mp3 => write_mp3(&mut audio, &mut out, track, album, artwork),
+                Container::M4a => {
+                    let metadata = m4a::Metadata {
+                        title: &track.title,
+                        artist: &track.user.username,
+                        album,
+                        artwork,
+                    };
+                    m4a::write(&mut audio, &mut out, &metadata)
+                }
+            }?;
+            out.flush().map_err(|e| format!("Failed to write {}: {e}", partial.display()))
+        })
+        .and_then(|()| {
+            std::fs::rename(&partial, &path).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    result.map(|()| path)
+}
+
+/// Write an ID3v2.4 tag, then the MP3 frames from the current position
+fn write_mp3(
+    audio: &mut File,
+    out: &mut impl Write,
+    track: &Track,
+    album: Option<&str>,
+    artwork: Option<&[u8]>,
+) -> Result<(), String> {
+    let mut tag = id3::Tag::new();
+    tag.set_title(track.title.as_str());
+    tag.set_artist(track.user.username.as_str());
+    if let Some(album) = album {
+        tag.set_album(album);
+    }
+    if let Some(artwork) = artwork {
+        let mime_type = if artwork.starts_with(b"\x89PNG") { "image/png" } else { "image/jpeg" };
+        tag.add_frame(id3::frame::Picture {
+            mime_type: mime_type.to_string(),
+            picture_type: id3::frame::PictureType::CoverFront,
+            description: String::new(),
+            data: artwork.to_vec(),
+        });
+    }
+    tag.write_to(&mut *out, id3::Version::Id3v24)
+        .map_err(|e| format!("Failed to write tags: {e}"))?;
+    io::copy(audio, out).map_err(|e| format!("Failed to write audio: {e}"))?;
+    Ok(())
+}
+
+/// Move past an ID3v2 tag at the start of `audio`, returning where the audio
+/// itself starts
+fn skip_id3(audio: &mut File) -> io::Result<u64> {
+    audio.rewind()?;
+    let mut header = [0u8; 10];
+    if audio.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        return audio.rewind().map(|()| 0);
+    }
+    // Synchsafe size, excluding the header and any footer
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, byte| (size << 7) | u64::from(byte & 0x7F));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    audio.seek(SeekFrom::Start(10 + size + footer))
+}
+
+/// Whether `data` starts with an MPEG audio frame header (layers I-III)
+fn is_mp3(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0 && data[1] & 0x06 != 0
+}
+
+/// "Artist - Title", without characters file systems won't take
+fn file_stem(track: &Track) -> String {
+    let name = format!("{} - {}", track.user.username, track.title);
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() {
+        format!("{}", track.id)
+    } else {
+        name.to_string()
+    }
+}
+
+/// `dir/stem.extension`, numbered if a file of that name exists
+fn unique_path(dir: &Path, stem: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(format!("{stem}.{extension}"));
+    let mut number = 2;
+    while path.exists() {
+        path = dir.join(format!("{stem} ({number}).{extension}"));
+        number += 1;
+    }
+    path
+}
@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Per-source gain stage: sample-accurate ramps for crossfading between
+//! tracks, on top of a fixed level used for loudness normalization.
+//!
+//! Every source the player appends is wrapped in a [`Fade`], which follows the
+//! gain ramps and level requested through its [`FadeControl`]. The control is
+//! shared with the player thread; the source only looks at it every
+//! [`CONTROL_INTERVAL`] samples so the audio callback never contends on the lock.
+
+use parking_lot::Mutex;
+use rodio::Source;
+use rodio::source::SeekError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Number of samples between checks for a new ramp request
+const CONTROL_INTERVAL: u32 = 256;
+
+#[derive(Debug, Clone, Copy)]
+struct Ramp {
+    target: f32,
+    duration: Duration,
+}
+
+struct FadeShared {
+    /// Bumped whenever a new ramp is requested
+    generation: AtomicU32,
+    ramp: Mutex<Option<Ramp>>,
+    /// Normalization level (`f32` bits), applied on top of the fade
+    level: AtomicU32,
+}
+
+/// Handle used by the player to ramp a playing source's gain
+#[derive(Clone)]
+pub struct FadeControl(Arc<FadeShared>);
+
+impl FadeControl {
+    /// Create a control with the given normalization level (linear)
+    pub fn new(level: f32) -> Self {
+        Self(Arc::new(FadeShared {
+            generation: AtomicU32::new(0),
+            ramp: Mutex::new(None),
+            level: AtomicU32::new(level.to_bits()),
+        }))
+    }
+
+    /// Change the normalization level (linear)
+    pub fn set_level(&self, level: f32) {
+        self.0.level.store(level.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Ramp the gain linearly from its current value to `target` over `duration`
+    pub fn fade_to(&self, target: f32, duration: Duration) {
+        *self.0.ramp.lock() = Some(Ramp { target, duration });
+        self.0.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Source wrapper applying the gain ramps requested through a [`FadeControl`]
+pub struct Fade<S> {
+    inner: S,
+    control: FadeControl,
+    seen_generation: u32,
+    until_check: u32,
+    level: f32,
+    gain: f32,
+    target: f32,
+    step: f32,
+}
+
+impl<S> Fade<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Wrap `inner`, starting at `initial_gain`
+    pub fn new(inner: S, initial_gain: f32, control: FadeControl) -> Self {
+        Self {
+            inner,
+            seen_generation: control.0.generation.load(Ordering::Acquire),
+            level: f32::from_bits(control.0.level.load(Ordering::Relaxed)),
+            control,
+            until_check: 0,
+            gain: initial_gain,
+            target: initial_gain,
+            step: 0.0,
+        }
+    }
+
+    fn check_control(&mut self) {
+        self.until_check = CONTROL_INTERVAL;
+        self.level = f32::from_bits(self.control.0.level.load(Ordering::Relaxed));
+        let generation = self.control.0.generation.load(Ordering::Acquire);
+        if generation == self.seen_generation {
+            return;
+        }
+        self.seen_generation = generation;
+
+        let Some(ramp) = *self.control.0.ramp.lock() else {
+            return;
+        };
+        let samples = ramp.duration.as_secs_f32()
+            * self.inner.sample_rate() as f32
+            * f32::from(self.inner.channels());
+        self.target = ramp.target;
+        if samples < 1.0 {
+            self.gain = ramp.target;
+            self.step = 0.0;
+        } else {
+            self.step = (ramp.target - self.gain) / samples;
+        }
+    }
+}
+
+impl<S> Iterator for Fade<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.until_check == 0 {
+            self.check_control();
+        }
+        self.until_check -= 1;
+
+        let sample = self.inner.next()?;
+        if self.step != 0.0 {
+            self.gain += self.step;
+            let overshot = (self.step > 0.0 && self.gain >= self.target)
+                || (self.step < 0.0 && self.gain <= self.target);
+            if overshot {
+                self.gain = self.target;
+                self.step = 0.0;
+            }
+        }
+        Some(sample * self.gain * self.level)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Fade<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
@@ -0,0 +1,729 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Demuxing fragmented MP4 (fMP4) HLS segments.
+//!
+//! fMP4 playlists carry the codec setup in an init segment (`#EXT-X-MAP`) and
+//! the audio in `moof`/`mdat` fragments. Joined back to back those don't make
+//! a file symphonia can read, so the samples are pulled out of each fragment
+//! instead: AAC frames get an ADTS header, MP3 frames pass through as they
+//! are. Either way the decoder sees a plain elementary stream, the same as a
+//! progressive download.
+//!
+//! Playing Opus is out of scope: rodio's symphonia build has no Opus decoder,
+//! so demuxing it would only move the failure. Opus in fMP4 is recognised and
+//! rejected, and the variant fallback moves on to another codec.
+
+/// Sampling frequencies by ADTS frequency index
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Largest frame an ADTS header can describe (13-bit length, header included)
+const MAX_ADTS_FRAME: usize = 0x1FFF;
+
+const ADTS_HEADER_LEN: usize = 7;
+
+/// How a playlist's media segments become bytes the decoder can read
+pub enum SegmentDemuxer {
+    /// Segments are already a stream symphonia can read (MPEG-TS, MP3, ADTS)
+    Passthrough,
+    /// fMP4 fragments, unpacked into an elementary stream
+    Fmp4(Fmp4Track),
+}
+
+impl SegmentDemuxer {
+    /// Choose how to handle a playlist's segments from its init segment.
+    /// Also returns the bytes to write ahead of the first media segment.
+    pub fn new(init: Option<Vec<u8>>) -> Result<(Self, Vec<u8>), String> {
+        match init {
+            Some(init) if is_fmp4(&init) => {
+                let track = Fmp4Track::from_init(&init)?;
+                eprintln!("[fmp4] Demuxing {} fragments", track.codec.name());
+                Ok((Self::Fmp4(track), Vec::new()))
+            }
+            Some(init) => Ok((Self::Passthrough, init)),
+            None => Ok((Self::Passthrough, Vec::new())),
+        }
+    }
+
+    /// Turn a downloaded media segment into bytes for the decoder
+    pub fn demux(&self, segment: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Passthrough => Ok(segment),
+            Self::Fmp4(track) => track.demux(&segment),
+        }
+    }
+}
+
+/// Whether an init segment is an ISO BMFF movie header rather than, say, an
+/// MPEG-TS program table
+fn is_fmp4(init: &[u8]) -> bool {
+    boxes(init).is_ok_and(|boxes| boxes.iter().any(|b| &b.kind == b"moov"))
+}
+
+/// What the audio track's samples are
+#[derive(Debug, Clone, Copy)]
+enum Codec {
+    Aac(AdtsConfig),
+    Mp3,
+}
+
+impl Codec {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Aac(_) => "AAC",
+            Self::Mp3 => "MP3",
+        }
+    }
+}
+
+/// The fields of an ADTS header that stay the same for every frame
+#[derive(Debug, Clone, Copy)]
+struct AdtsConfig {
+    /// MPEG-4 audio object type minus one
+    profile: u8,
+    frequency_index: u8,
+    channels: u8,
+}
+
+impl AdtsConfig {
+    fn header(&self, frame_len: usize) -> [u8; ADTS_HEADER_LEN] {
+        let len = frame_len as u32;
+        [
+            0xFF,
+            // MPEG-4, layer 0, no CRC
+            0xF1,
+            (self.profile << 6) | (self.frequency_index << 2) | (self.channels >> 2),
+            ((self.channels & 0x3) << 6) | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 0x7) << 5) as u8 | 0x1F,
+            // Variable bitrate buffer fullness, one raw data block
+            0xFC,
+        ]
+    }
+}
+
+/// The audio track of an fMP4 stream, as described by its init segment
+pub struct Fmp4Track {
+    track_id: u32,
+    codec: Codec,
+    /// Sample size from the movie's `trex` box, for fragments that don't give one
+    default_sample_size: Option<u32>,
+}
+
+impl Fmp4Track {
+    fn from_init(init: &[u8]) -> Result<Self, String> {
+        let moov = find(init, &[b"moov"])?.ok_or("fMP4 init segment has no moov box")?;
+
+        let mut audio = None;
+        for trak in boxes(moov)?.iter().filter(|b| &b.kind == b"trak") {
+            if handler_type(trak.payload)? == Some(*b"soun") {
+                audio = Some(trak.payload);
+                break;
+            }
+        }
+        let trak = audio.ok_or("fMP4 init segment has no audio track")?;
+
+        let tkhd = find(trak, &[b"tkhd"])?.ok_or("fMP4 track has no tkhd box")?;
+        let mut reader = Reader::new(tkhd);
+        let version = reader.u8()?;
+        // Flags, then creation and modification times
+        reader.skip(if version == 1 { 3 + 16 } else { 3 + 8 })?;
+        let track_id = reader.u32()?;
+
+        let stsd = find(trak, &[b"mdia", b"minf", b"stbl", b"stsd"])?
+            .ok_or("fMP4 track has no sample description")?;
+        let codec = parse_sample_description(stsd)?;
+
+        let mut default_sample_size = None;
+        if let Some(mvex) = find(moov, &[b"mvex"])? {
+            for trex in boxes(mvex)?.iter().filter(|b| &b.kind == b"trex") {
+                let mut reader = Reader::new(trex.payload);
+                reader.skip(4)?;
+                if reader.u32()? == track_id {
+                    // Sample description index, duration
+                    reader.skip(8)?;
+                    default_sample_size = Some(reader.u32()?).filter(|size| *size > 0);
+                }
+            }
+        }
+
+        Ok(Self {
+            track_id,
+            codec,
+            default_sample_size,
+        })
+    }
+
+    /// Extract the audio samples of every fragment in a media segment
+    fn demux(&self, segment: &[u8]) -> Result<Vec<u8>, String> {
+        let top = boxes(segment)?;
+        let mut out = Vec::with_capacity(segment.len());
+        for (index, moof) in top.iter().enumerate().filter(|(_, b)| &b.kind == b"moof") {
+            // Samples without an explicit offset start in the next mdat
+            let mdat_start = top[index..]
+                .iter()
+                .find(|b| &b.kind == b"mdat")
+                .map(|mdat| mdat.offset + mdat.header_len);
+
+            for traf in boxes(moof.payload)?.iter().filter(|b| &b.kind == b"traf") {
+                self.demux_fragment(segment, moof.offset, mdat_start, traf.payload, &mut out)?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Copy the samples of one track fragment to `out`
+    fn demux_fragment(
+        &self,
+        segment: &[u8],
+        moof_offset: usize,
+        mdat_start: Option<usize>,
+        traf: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let tfhd = find(traf, &[b"tfhd"])?.ok_or("fMP4 fragment has no tfhd box")?;
+        let mut reader = Reader::new(tfhd);
+        let flags = reader.u32()? & 0xFF_FFFF;
+        if reader.u32()? != self.track_id {
+            return Ok(());
+        }
+        // An explicit base offset counts from the start of the resource,
+        // which a byte range segment doesn't begin at
+        let explicit_base = flags & 0x01 != 0;
+        reader.skip(if explicit_base { 8 } else { 0 })?;
+        reader.skip(if flags & 0x02 != 0 { 4 } else { 0 })?;
+        reader.skip(if flags & 0x08 != 0 { 4 } else { 0 })?;
+        let default_size = if flags & 0x10 != 0 {
+            Some(reader.u32()?)
+        } else {
+            self.default_sample_size
+        };
+
+        let mut position = mdat_start;
+        for trun in boxes(traf)?.iter().filter(|b| &b.kind == b"trun") {
+            let mut reader = Reader::new(trun.payload);
+            let flags = reader.u32()? & 0xFF_FFFF;
+            let sample_count = reader.u32()?;
+            if flags & 0x01 != 0 {
+                let data_offset = reader.u32()? as i32;
+                if !explicit_base {
+                    position = moof_offset.checked_add_signed(data_offset as isize);
+                }
+            }
+            reader.skip(if flags & 0x04 != 0 { 4 } else { 0 })?;
+
+            let mut start = position.ok_or("fMP4 fragment has no sample data")?;
+            for _ in 0..sample_count {
+                reader.skip(if flags & 0x100 != 0 { 4 } else { 0 })?;
+                let size = if flags & 0x200 != 0 {
+                    reader.u32()?
+                } else {
+                    default_size.ok_or("fMP4 fragment doesn't give its sample sizes")?
+                } as usize;
+                reader.skip(if flags & 0x400 != 0 { 4 } else { 0 })?;
+                reader.skip(if flags & 0x800 != 0 { 4 } else { 0 })?;
+
+                let sample = start
+                    .checked_add(size)
+                    .and_then(|end| segment.get(start..end))
+                    .ok_or("fMP4 sample runs past the end of the segment")?;
+                self.write_sample(sample, out)?;
+                start += size;
+            }
+            position = Some(start);
+        }
+        Ok(())
+    }
+
+    fn write_sample(&self, sample: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        match &self.codec {
+            Codec::Aac(config) => {
+                let frame_len = ADTS_HEADER_LEN + sample.len();
+                if frame_len > MAX_ADTS_FRAME {
+                    return Err(format!("AAC frame of {} bytes is too large for ADTS", sample.len()));
+                }
+                out.extend_from_slice(&config.header(frame_len));
+                out.extend_from_slice(sample);
+            }
+            Codec::Mp3 => out.extend_from_slice(sample),
+        }
+        Ok(())
+    }
+}
+
+/// Handler type of a `trak` box (`soun` for audio)
+fn handler_type(trak: &[u8]) -> Result<Option<[u8; 4]>, String> {
+    let Some(hdlr) = find(trak, &[b"mdia", b"hdlr"])? else {
+        return Ok(None);
+    };
+    let mut reader = Reader::new(hdlr);
+    // Version and flags, pre-defined
+    reader.skip(8)?;
+    reader.fourcc().map(Some)
+}
+
+/// Codec of the first entry of an `stsd` box
+fn parse_sample_description(stsd: &[u8]) -> Result<Codec, String> {
+    // Version and flags, entry count
+    let entries = stsd.get(8..).ok_or("Truncated fMP4 sample description")?;
+    let entry = boxes(entries)?
+        .into_iter()
+        .next()
+        .ok_or("fMP4 sample description is empty")?;
+
+    match &entry.kind {
+        b"mp4a" => {}
+        b".mp3" | b"mp3 " => return Ok(Codec::Mp3),
+        b"Opus" => return Err("Opus in fMP4 can't be decoded".into()),
+        other => {
+            return Err(format!(
+                "Unsupported fMP4 audio format '{}'",
+                String::from_utf8_lossy(other)
+            ));
+        }
+    }
+
+    // Reserved, data reference index, then the sound description version
+    let mut reader = Reader::new(entry.payload);
+    reader.skip(8)?;
+    let version = reader.u16()?;
+    // Revision, vendor, channels, sample size, compression ID, packet size,
+    // sample rate; QuickTime's later versions append more fields
+    reader.skip(18)?;
+    reader.skip(match version {
+        1 => 16,
+        2 => 36,
+        _ => 0,
+    })?;
+    let children = &entry.payload[reader.position()..];
+    let esds = match find(children, &[b"esds"])? {
+        Some(esds) => esds,
+        None => find(children, &[b"wave", b"esds"])?.ok_or("fMP4 AAC track has no esds box")?,
+    };
+    parse_esds(esds)
+}
+
+/// Find the decoder configuration in an `esds` box
+fn parse_esds(esds: &[u8]) -> Result<Codec, String> {
+    let mut reader = Reader::new(esds);
+    // Version and flags
+    reader.skip(4)?;
+
+    let (tag, es) = reader.descriptor()?;
+    if tag != 0x03 {
+        return Err("fMP4 esds box has no ES descriptor".into());
+    }
+    let mut reader = Reader::new(es);
+    reader.skip(2)?;
+    let flags = reader.u8()?;
+    if flags & 0x80 != 0 {
+        reader.skip(2)?;
+    }
+    if flags & 0x40 != 0 {
+        let url_len = reader.u8()?;
+        reader.skip(url_len as usize)?;
+    }
+    if flags & 0x20 != 0 {
+        reader.skip(2)?;
+    }
+
+    let (tag, config) = reader.descriptor()?;
+    if tag != 0x04 {
+        return Err("fMP4 esds box has no decoder config".into());
+    }
+    let mut reader = Reader::new(config);
+    let object_type = reader.u8()?;
+    match object_type {
+        // MPEG-4 audio, MPEG-2 AAC profiles
+        0x40 | 0x66..=0x68 => {}
+        // MPEG-2 and MPEG-1 audio
+        0x69 | 0x6B => return Ok(Codec::Mp3),
+        other => return Err(format!("Unsupported fMP4 object type 0x{other:02x}")),
+    }
+    // Stream type, buffer size, max and average bitrate
+    reader.skip(12)?;
+    let (tag, specific) = reader.descriptor()?;
+    if tag != 0x05 {
+        return Err("fMP4 AAC track has no AudioSpecificConfig".into());
+    }
+    parse_audio_specific_config(specific).map(Codec::Aac)
+}
+
+/// Read the ADTS header fields from an MPEG-4 AudioSpecificConfig
+fn parse_audio_specific_config(config: &[u8]) -> Result<AdtsConfig, String> {
+    let mut bits = BitReader::new(config);
+    let mut object_type = read_object_type(&mut bits)?;
+    let frequency_index = read_frequency_index(&mut bits)?;
+    let channels = bits.read(4)? as u8;
+
+    // HE-AAC signals SBR first and the core AAC object type after it. The
+    // core is played without SBR, at the base frequency.
+    if object_type == 5 || object_type == 29 {
+        read_frequency_index(&mut bits)?;
+        object_type = read_object_type(&mut bits)?;
+    }
+
+    if !(1..=4).contains(&object_type) {
+        return Err(format!("AAC object type {object_type} can't be framed as ADTS"));
+    }
+    if channels == 0 {
+        return Err("AAC channel layouts from a program config aren't supported".into());
+    }
+    Ok(AdtsConfig {
+        profile: object_type - 1,
+        frequency_index,
+        channels,
+    })
+}
+
+fn read_object_type(bits: &mut BitReader) -> Result<u8, String> {
+    match bits.read(5)? {
+        31 => Ok(32 + bits.read(6)? as u8),
+        object_type => Ok(object_type as u8),
+    }
+}
+
+/// Sampling frequency index, mapping an explicit frequency to its index
+fn read_frequency_index(bits: &mut BitReader) -> Result<u8, String> {
+    match bits.read(4)? {
+        15 => {
+            let frequency = bits.read(24)?;
+            SAMPLE_RATES
+                .iter()
+                .position(|rate| *rate == frequency)
+                .map(|index| index as u8)
+                .ok_or_else(|| format!("Sample rate {frequency} Hz can't be framed as ADTS"))
+        }
+        index if (index as usize) < SAMPLE_RATES.len() => Ok(index as u8),
+        index => Err(format!("Invalid AAC frequency index {index}")),
+    }
+}
+
+/// A box located in its parent's data
+struct Mp4Box<'a> {
+    kind: [u8; 4],
+    /// Offset of the box header within the parent
+    offset: usize,
+    header_len: usize,
+    payload: &'a [u8],
+}
+
+/// The boxes directly inside `data`
+fn boxes(data: &[u8]) -> Result<Vec<Mp4Box<'_>>, String> {
+    let mut boxes = Vec::new();
+    let mut reader = Reader::new(data);
+    while reader.remaining() >= 8 {
+        let offset = reader.position();
+        let size = reader.u32()? as u64;
+        let kind = reader.fourcc()?;
+        let size = match size {
+            1 => reader.u64()?,
+            0 => (data.len() - offset) as u64,
+            size => size,
+        };
+        let header_len = reader.position() - offset;
+        let end = usize::try_from(size)
+            .ok()
+            .and_then(|size| offset.checked_add(size))
+            .filter(|end| *end <= data.len() && *end >= offset + header_len)
+            .ok_or_else(|| format!("fMP4 box '{}' is truncated", String::from_utf8_lossy(&kind)))?;
+
+        boxes.push(Mp4Box {
+            kind,
+            offset,
+            header_len,
+            payload: &data[offset + header_len..end],
+        });
+        reader.skip(end - reader.position())?;
+    }
+    Ok(boxes)
+}
+
+/// Payload of the box at `path`, following the first match at each level
+fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Result<Option<&'a [u8]>, String> {
+    let mut payload = data;
+    for kind in path {
+        match boxes(payload)?.into_iter().find(|b| &b.kind == *kind) {
+            Some(found) => payload = found.payload,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(payload))
+}
+
+/// Big-endian reads from a box payload
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or("Truncated fMP4 data")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), String> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.fourcc()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok((self.u32()? as u64) << 32 | self.u32()? as u64)
+    }
+
+    fn fourcc(&mut self) -> Result<[u8; 4], String> {
+        let bytes = self.bytes(4)?;
+        Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    /// An MPEG-4 descriptor: its tag and body. The length is coded in up to
+    /// four bytes of seven bits each.
+    fn descriptor(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let tag = self.u8()?;
+        let mut len = 0usize;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            len = (len << 7) | (byte & 0x7F) as usize;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok((tag, self.bytes(len)?))
+    }
+}
+
+/// MSB-first bit reads, for the AudioSpecificConfig
+struct BitReader<'a> {
+    data: &'a [u8],
+    bit: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, bit: 0 }
+    }
+
+    fn read(&mut self, count: usize) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self.data.get(self.bit / 8).ok_or("Truncated AudioSpecificConfig")?;
+            value = (value << 1) | ((byte >> (7 - self.bit % 8)) & 1) as u32;
+            self.bit += 1;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ADTS header of a 3-byte AAC-LC frame at 44.1 kHz, stereo
+    const LC_STEREO_HEADER: [u8; ADTS_HEADER_LEN] = [0xFF, 0xF1, 0x50, 0x80, 0x01, 0x5F, 0xFC];
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn full_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; 4];
+        data.extend_from_slice(payload);
+        mp4_box(kind, &data)
+    }
+
+    fn descriptor(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![tag, body.len() as u8];
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// Init segment with one AAC track, ID 1, described by `asc`
+    fn aac_init(asc: &[u8]) -> Vec<u8> {
+        let mut decoder_config = vec![0x40];
+        decoder_config.extend_from_slice(&[0; 12]);
+        decoder_config.extend(descriptor(0x05, asc));
+        let mut es = vec![0, 1, 0];
+        es.extend(descriptor(0x04, &decoder_config));
+        let esds = full_box(b"esds", &descriptor(0x03, &es));
+
+        let mut mp4a = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        mp4a.extend_from_slice(&[0; 20]);
+        mp4a.extend(esds);
+        let mut stsd = 1u32.to_be_bytes().to_vec();
+        stsd.extend(mp4_box(b"mp4a", &mp4a));
+        let stbl = mp4_box(b"stbl", &full_box(b"stsd", &stsd));
+        let minf = mp4_box(b"minf", &stbl);
+
+        let mut hdlr = vec![0; 4];
+        hdlr.extend_from_slice(b"soun");
+        hdlr.extend_from_slice(&[0; 13]);
+        let mut mdia = full_box(b"hdlr", &hdlr);
+        mdia.extend(minf);
+
+        let mut tkhd = vec![0; 8];
+        tkhd.extend_from_slice(&1u32.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 68]);
+        let mut trak = full_box(b"tkhd", &tkhd);
+        trak.extend(mp4_box(b"mdia", &mdia));
+
+        let mut trex = 1u32.to_be_bytes().to_vec();
+        trex.extend_from_slice(&[0; 16]);
+        let mut moov = mp4_box(b"trak", &trak);
+        moov.extend(mp4_box(b"mvex", &full_box(b"trex", &trex)));
+        mp4_box(b"moov", &moov)
+    }
+
+    /// AudioSpecificConfig for AAC-LC at 44.1 kHz, stereo
+    const LC_STEREO: [u8; 2] = [0x12, 0x10];
+
+    /// A moof/mdat fragment of track 1 holding `samples`, their sizes in the trun
+    fn fragment(samples: &[&[u8]]) -> Vec<u8> {
+        let tfhd = full_box(b"tfhd", &1u32.to_be_bytes());
+        let trun_len = 8 + 4 + 4 + 4 + 4 * samples.len();
+        let traf_len = 8 + tfhd.len() + trun_len;
+        let moof_len = 8 + 16 + traf_len;
+
+        // Data offset flag and sample size flag
+        let mut trun = vec![0, 0, 0x02, 0x01];
+        trun.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+        trun.extend_from_slice(&((moof_len + 8) as u32).to_be_bytes());
+        for sample in samples {
+            trun.extend_from_slice(&(sample.len() as u32).to_be_bytes());
+        }
+        let mut traf = tfhd;
+        traf.extend(mp4_box(b"trun", &trun));
+        let mut moof = full_box(b"mfhd", &1u32.to_be_bytes());
+        moof.extend(mp4_box(b"traf", &traf));
+
+        let mut segment = mp4_box(b"moof", &moof);
+        assert_eq!(segment.len(), moof_len);
+        segment.extend(mp4_box(b"mdat", &samples.concat()));
+        segment
+    }
+
+    fn demuxer(init: Vec<u8>) -> SegmentDemuxer {
+        let (demuxer, header) = SegmentDemuxer::new(Some(init)).unwrap();
+        assert!(header.is_empty());
+        assert!(matches!(demuxer, SegmentDemuxer::Fmp4(_)));
+        demuxer
+    }
+
+    #[test]
+    fn aac_fragments_become_adts_frames() {
+        let demuxer = demuxer(aac_init(&LC_STEREO));
+        let out = demuxer.demux(fragment(&[&[1, 2, 3], &[4, 5, 6]])).unwrap();
+
+        let mut expected = LC_STEREO_HEADER.to_vec();
+        expected.extend_from_slice(&[1, 2, 3]);
+        expected.extend_from_slice(&LC_STEREO_HEADER);
+        expected.extend_from_slice(&[4, 5, 6]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn adts_header_fields() {
+        let config = parse_audio_specific_config(&LC_STEREO).unwrap();
+        assert_eq!(config.header(10), LC_STEREO_HEADER);
+
+        // HE-AAC plays its AAC-LC core at the base rate: 24 kHz mono
+        let he_aac = parse_audio_specific_config(&[0x2B, 0x09, 0x88]).unwrap();
+        assert_eq!((he_aac.profile, he_aac.frequency_index, he_aac.channels), (1, 6, 1));
+
+        // Largest length the 13 bits can hold
+        let header = config.header(MAX_ADTS_FRAME);
+        assert_eq!(header[3] & 0x3, 0x3);
+        assert_eq!(header[4], 0xFF);
+        assert_eq!(header[5] >> 5, 0x7);
+    }
+
+    #[test]
+    fn oversized_aac_frame_is_rejected() {
+        let demuxer = demuxer(aac_init(&LC_STEREO));
+        let frame = vec![0; MAX_ADTS_FRAME];
+        assert!(demuxer.demux(fragment(&[&frame])).is_err());
+    }
+
+    #[test]
+    fn truncated_init_segment_is_rejected() {
+        let init = aac_init(&LC_STEREO);
+        for len in [4, 8, init.len() / 2, init.len() - 1] {
+            assert!(Fmp4Track::from_init(&init[..len]).is_err(), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn truncated_fragment_is_rejected() {
+        let demuxer = demuxer(aac_init(&LC_STEREO));
+        let segment = fragment(&[&[1, 2, 3], &[4, 5, 6]]);
+        assert!(demuxer.demux(segment[..segment.len() - 1].to_vec()).is_err());
+    }
+
+    #[test]
+    fn box_sizes_past_the_data_are_rejected() {
+        // Declared larger than the data
+        assert!(boxes(&[0, 0, 0, 16, b'm', b'o', b'o', b'v', 0, 0]).is_err());
+        assert!(boxes(&[0xFF, 0xFF, 0xFF, 0xFF, b'm', b'd', b'a', b't']).is_err());
+        // 64-bit size near the top of the range
+        let mut large = vec![0, 0, 0, 1, b'm', b'd', b'a', b't'];
+        large.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(boxes(&large).is_err());
+        // Smaller than its own header
+        assert!(boxes(&[0, 0, 0, 4, b'f', b'r', b'e', b'e']).is_err());
+        // Zero extends to the end of the data
+        let rest = boxes(&[0, 0, 0, 0, b'm', b'd', b'a', b't', 1, 2]).unwrap();
+        assert_eq!(rest[0].payload, &[1, 2]);
+    }
+
+    #[test]
+    fn sample_sizes_past_the_segment_are_rejected() {
+        let demuxer = demuxer(aac_init(&LC_STEREO));
+        let mut segment = fragment(&[&[1, 2, 3]]);
+        // The trun's only sample size is the last field of the moof
+        let moof_len = u32::from_be_bytes(segment[..4].try_into().unwrap()) as usize;
+        segment[moof_len - 4..moof_len].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(demuxer.demux(segment).is_err());
+    }
+
+    #[test]
+    fn other_segments_pass_through() {
+        // An MPEG-TS init segment starts with a sync byte, not a box
+        let init = vec![0x47, 0x40, 0x00, 0x10];
+        let (demuxer, header) = SegmentDemuxer::new(Some(init.clone())).unwrap();
+        assert!(matches!(demuxer, SegmentDemuxer::Passthrough));
+        assert_eq!(header, init);
+        assert_eq!(demuxer.demux(vec![1, 2, 3]).unwrap(), vec![1, 2, 3]);
+    }
+}
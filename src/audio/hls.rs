@@ -1,9 +1,53 @@
 // SPDX-License-Identifier: MPL-2.0
 
-//! HLS streaming support for SoundCloud's encrypted streams
+//! HLS streaming support for SoundCloud's encrypted streams.
+//!
+//! Plain AES-128 (CBC) playlists are decrypted here as segments download;
+//! only commercial DRM (PlayReady, Widevine, FairPlay) needs a fallback.
+//! fMP4 segments are unpacked by [`SegmentDemuxer`] on their way into the
+//! buffer.
 
-use m3u8_rs::{MediaPlaylist, Playlist};
-use reqwest::Client;
+use super::fmp4::SegmentDemuxer;
+use super::stream_buffer::StreamBuffer;
+use crate::api::{AudioCodec, StreamPreference};
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use futures_util::{Stream, StreamExt, stream};
+use m3u8_rs::{MasterPlaylist, MediaPlaylist, Playlist};
+use reqwest::{Client, StatusCode, header};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+/// Key system identifiers of the commercial DRM schemes we can't play
+const DRM_KEY_FORMATS: &[(&str, &str)] = &[
+    ("playready", "PlayReady"),
+    ("9a04f079-9840-4286-ab92-e65be0885f95", "PlayReady"),
+    ("widevine", "Widevine"),
+    ("edef8ba9-79d6-4ace-a3c8-27dcd51d21ed", "Widevine"),
+    ("fairplay", "FairPlay"),
+    ("com.apple.streamingkeydelivery", "FairPlay"),
+    ("94ce86fb-07ff-4f43-adb8-93d2fa968ca2", "FairPlay"),
+];
+
+/// Number of media segments to buffer before playback starts
+const PREBUFFER_SEGMENTS: usize = 2;
+
+/// Largest request adjacent byte-range segments are merged into
+const MAX_MERGED_BYTES: u64 = 1024 * 1024;
+
+/// Number of segment requests in flight at once
+const CONCURRENT_REQUESTS: usize = 4;
+
+/// Attempts per segment request before the download gives up
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry, doubled after every failed attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
 
 /// HLS stream information
 #[derive(Debug, Clone)]
@@ -11,9 +55,27 @@ use reqwest::Client;
 pub struct HlsStream {
     pub segments: Vec<HlsSegment>,
     pub target_duration: u64,
-    pub encryption: Option<HlsEncryption>,
+    /// Every key the playlist's `EXT-X-KEY` tags switch to, in order
+    pub keys: Vec<HlsEncryption>,
     /// fMP4 init segment URL (from #EXT-X-MAP)
     pub init_segment_url: Option<String>,
+    /// Byte range of the init segment within its URL
+    pub init_segment_range: Option<SegmentRange>,
+    /// The master playlist variant this is, if it came from one
+    pub variant: Option<Variant>,
+    /// The other variants in the master playlist, best first, to fall back
+    /// to when this one fails
+    pub fallback_variants: Vec<Variant>,
+}
+
+/// A variant of a master playlist
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// URL of its media playlist (or of its audio rendition)
+    pub url: String,
+    pub codec: Option<AudioCodec>,
+    /// Average bandwidth in bits per second, or the peak if not given
+    pub bandwidth: u64,
 }
 
 /// A single HLS segment
@@ -22,11 +84,47 @@ pub struct HlsStream {
 pub struct HlsSegment {
     pub uri: String,
     pub duration: f32,
-    pub byte_range: Option<(u64, u64)>,
+    /// Sub-range of the URI holding this segment (`EXT-X-BYTERANGE`)
+    pub byte_range: Option<SegmentRange>,
+    /// Media sequence number, the default AES-128 IV
+    pub sequence: u64,
+    /// Index into [`HlsStream::keys`] of the key the segment is encrypted
+    /// with, if any
+    pub key: Option<usize>,
+}
+
+impl HlsStream {
+    /// Total duration of the media segments
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f32(self.segments.iter().map(|s| s.duration).sum())
+    }
+
+    /// The encryption that decides whether the stream can be played: the
+    /// first key we can't decrypt, or else the first key
+    pub fn encryption(&self) -> Option<&HlsEncryption> {
+        self.keys
+            .iter()
+            .find(|key| key.needs_fallback())
+            .or(self.keys.first())
+    }
+}
+
+/// Absolute byte range of a segment within its resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentRange {
+    pub start: u64,
+    pub length: u64,
+}
+
+impl SegmentRange {
+    /// First byte after the range
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
 }
 
 /// HLS encryption information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct HlsEncryption {
     pub method: String,
@@ -35,8 +133,255 @@ pub struct HlsEncryption {
     pub keyformat: Option<String>,
 }
 
-/// Fetch and parse an HLS m3u8 playlist
-pub async fn fetch_playlist(client: &Client, url: &str) -> Result<HlsStream, String> {
+impl HlsEncryption {
+    /// Name of the commercial DRM system protecting the stream, if any
+    pub fn drm_system(&self) -> Option<&'static str> {
+        let keyformat = self.keyformat.as_deref()?.to_ascii_lowercase();
+        DRM_KEY_FORMATS
+            .iter()
+            .find(|(id, _)| keyformat.contains(id))
+            .map(|(_, name)| *name)
+    }
+
+    /// Plain AES-128 with a fetchable key, which we decrypt ourselves
+    pub fn is_aes128(&self) -> bool {
+        self.method == "AES128" && self.uri.is_some() && self.drm_system().is_none()
+    }
+
+    /// Whether the segments can't be played as downloaded or decrypted
+    /// natively (commercial DRM, or an encryption method we don't support)
+    pub fn needs_fallback(&self) -> bool {
+        self.method != "None" && !self.is_aes128()
+    }
+
+    /// Short description for the DRM fallback
+    pub fn describe(&self) -> String {
+        match self.drm_system() {
+            Some(system) => system.to_string(),
+            None => self.keyformat.clone().unwrap_or_else(|| self.method.clone()),
+        }
+    }
+}
+
+/// AES-128-CBC key for decrypting whole media segments
+#[derive(Debug, Clone)]
+struct SegmentKey {
+    key: [u8; 16],
+    /// Explicit IV from the key tag; otherwise the segment's sequence number
+    iv: Option<[u8; 16]>,
+}
+
+/// Keys for decrypting a playlist's AES-128 encrypted segments, which may
+/// switch keys partway through
+#[derive(Debug, Clone)]
+pub struct SegmentDecryptor {
+    /// One per [`HlsStream::keys`] entry, `None` where the key isn't AES-128
+    keys: Vec<Option<SegmentKey>>,
+    /// Key of the first segment, which the init segment shares
+    init_key: Option<usize>,
+}
+
+impl SegmentDecryptor {
+    /// Fetch the keys for a playlist, if any of its segments are AES-128
+    /// encrypted. Each key URI is fetched once.
+    pub async fn for_playlist(client: &Client, playlist: &HlsStream) -> Result<Option<Self>, String> {
+        if !playlist.keys.iter().any(HlsEncryption::is_aes128) {
+            return Ok(None);
+        }
+
+        let mut fetched: HashMap<&str, [u8; 16]> = HashMap::new();
+        let mut keys = Vec::with_capacity(playlist.keys.len());
+        for encryption in &playlist.keys {
+            if !encryption.is_aes128() {
+                keys.push(None);
+                continue;
+            }
+            let key_uri = encryption.uri.as_deref().unwrap_or_default();
+            let key = match fetched.get(key_uri) {
+                Some(key) => *key,
+                None => {
+                    let key = fetch_key(client, key_uri).await?;
+                    fetched.insert(key_uri, key);
+                    key
+                }
+            };
+            let iv = encryption.iv.as_deref().map(parse_iv).transpose()?;
+            keys.push(Some(SegmentKey { key, iv }));
+        }
+        Ok(Some(Self {
+            keys,
+            init_key: playlist.segments.first().and_then(|segment| segment.key),
+        }))
+    }
+
+    fn key(&self, index: Option<usize>) -> Option<&SegmentKey> {
+        index.and_then(|index| self.keys.get(index)?.as_ref())
+    }
+
+    /// Decrypt a media segment with its key, stripping its PKCS#7 padding.
+    /// Segments without a key are returned as they are.
+    pub fn decrypt(&self, segment: &HlsSegment, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self.key(segment.key) {
+            Some(key) => decrypt(key, segment.sequence, data),
+            None => Ok(data),
+        }
+    }
+
+    /// Decrypt the init segment. It is only encrypted when the key tag gives
+    /// an explicit IV, since it has no sequence number to derive one from.
+    pub fn decrypt_init(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self.key(self.init_key) {
+            Some(key) if key.iv.is_some() => decrypt(key, 0, data),
+            _ => Ok(data),
+        }
+    }
+}
+
+/// Fetch a 16-byte AES-128 key
+async fn fetch_key(client: &Client, key_uri: &str) -> Result<[u8; 16], String> {
+    let response = client
+        .get(key_uri)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to fetch key: {e}"))?;
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to read key: {e}"))?;
+    bytes
+        .as_ref()
+        .try_into()
+        .map_err(|_| format!("Invalid AES-128 key length: {} bytes", bytes.len()))
+}
+
+/// Decrypt AES-128-CBC data, stripping its PKCS#7 padding
+fn decrypt(key: &SegmentKey, sequence: u64, mut data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let iv = key.iv.unwrap_or_else(|| {
+        let mut iv = [0u8; 16];
+        iv[8..].copy_from_slice(&sequence.to_be_bytes());
+        iv
+    });
+    let len = Aes128CbcDec::new(&key.key.into(), &iv.into())
+        .decrypt_padded_mut::<Pkcs7>(&mut data)
+        .map_err(|_| "Failed to decrypt segment".to_string())?
+        .len();
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Parse a hex IV attribute (`0x` followed by up to 32 hex digits)
+fn parse_iv(iv: &str) -> Result<[u8; 16], String> {
+    let hex = iv.trim_start_matches("0x").trim_start_matches("0X");
+    let value = u128::from_str_radix(hex, 16).map_err(|e| format!("Invalid IV {iv}: {e}"))?;
+    Ok(value.to_be_bytes())
+}
+
+/// Fetch and parse an HLS m3u8 playlist. For a master playlist, the variant
+/// best matching `preference` is loaded, with the rest kept as fallbacks.
+pub async fn fetch_playlist(
+    client: &Client,
+    url: &str,
+    preference: StreamPreference,
+) -> Result<HlsStream, String> {
+    match fetch_parsed(client, url).await? {
+        Playlist::MediaPlaylist(playlist) => Ok(parse_media_playlist(&playlist, url)),
+        Playlist::MasterPlaylist(master) => {
+            let variants = rank_variants(&master, url, preference);
+            eprintln!("[hls] Master playlist with {} variants", variants.len());
+            load_first_variant(client, variants)
+                .await
+                .ok_or_else(|| "No playable variant in master playlist".to_string())
+        }
+    }
+}
+
+/// Load the next fallback variant after `playlist` failed to download or decode
+pub async fn fetch_fallback(client: &Client, playlist: &HlsStream) -> Option<HlsStream> {
+    load_first_variant(client, playlist.fallback_variants.clone()).await
+}
+
+/// Load the first variant whose media playlist can be fetched, keeping the
+/// ones after it as fallbacks
+async fn load_first_variant(client: &Client, mut variants: Vec<Variant>) -> Option<HlsStream> {
+    while !variants.is_empty() {
+        let variant = variants.remove(0);
+        match fetch_parsed(client, &variant.url).await {
+            Ok(Playlist::MediaPlaylist(playlist)) => {
+                let mut stream = parse_media_playlist(&playlist, &variant.url);
+                stream.variant = Some(variant);
+                stream.fallback_variants = variants;
+                return Some(stream);
+            }
+            Ok(Playlist::MasterPlaylist(_)) => {
+                eprintln!("[hls] Variant is another master playlist, skipping");
+            }
+            Err(e) => eprintln!("[hls] Variant failed: {e}"),
+        }
+    }
+    None
+}
+
+/// Order a master playlist's variants by preference
+fn rank_variants(master: &MasterPlaylist, base_url: &str, preference: StreamPreference) -> Vec<Variant> {
+    let mut variants: Vec<_> = master
+        .variants
+        .iter()
+        .filter(|v| !v.is_i_frame)
+        .map(|variant| {
+            let bandwidth = variant.average_bandwidth.unwrap_or(variant.bandwidth);
+            let codec = variant.codecs.as_deref().and_then(AudioCodec::from_codecs);
+
+            // Audio-only apps want the audio rendition when the variant has one
+            let uri = variant
+                .audio
+                .as_ref()
+                .and_then(|group| {
+                    master
+                        .alternatives
+                        .iter()
+                        .find(|alt| &alt.group_id == group && alt.uri.is_some())
+                })
+                .and_then(|alt| alt.uri.clone())
+                .unwrap_or_else(|| variant.uri.clone());
+
+            Variant {
+                url: resolve_uri(base_url, &uri),
+                codec,
+                bandwidth,
+            }
+        })
+        .collect();
+
+    match preference {
+        StreamPreference::HighestQuality => variants.sort_by_key(|v| std::cmp::Reverse(v.bandwidth)),
+        StreamPreference::DataSaver => variants.sort_by_key(|v| v.bandwidth),
+        StreamPreference::Codec(preferred) => {
+            variants.sort_by_key(|v| (v.codec != Some(preferred), std::cmp::Reverse(v.bandwidth)))
+        }
+    }
+
+    let mut ranked: Vec<Variant> = Vec::with_capacity(variants.len());
+    for variant in variants {
+        // Several variants can share an audio rendition
+        if !ranked.iter().any(|v| v.url == variant.url) {
+            ranked.push(variant);
+        }
+    }
+    ranked
+}
+
+/// Resolve a playlist URI against the URL of the playlist that references it
+fn resolve_uri(playlist_url: &str, uri: &str) -> String {
+    if uri.starts_with("http") {
+        return uri.to_string();
+    }
+    let base = playlist_url.rsplit_once('/').map(|(b, _)| b).unwrap_or(playlist_url);
+    format!("{}/{}", base, uri)
+}
+
+/// Fetch and parse a playlist of either kind
+async fn fetch_parsed(client: &Client, url: &str) -> Result<Playlist, String> {
     // Fetch the playlist
     let response = client
         .get(url)
@@ -50,83 +395,348 @@ pub async fn fetch_playlist(client: &Client, url: &str) -> Result<HlsStream, Str
         .map_err(|e| format!("Failed to read playlist: {e}"))?;
 
     // Parse the playlist
-    let parsed = m3u8_rs::parse_playlist(&bytes);
-
-    match parsed {
-        Ok((_, Playlist::MediaPlaylist(playlist))) => {
-            Ok(parse_media_playlist(&playlist, url))
-        }
-        Ok((_, Playlist::MasterPlaylist(_))) => {
-            Err("Master playlists not yet supported".into())
-        }
-        Err(e) => Err(format!("Failed to parse playlist: {e:?}")),
-    }
+    m3u8_rs::parse_playlist(&bytes)
+        .map(|(_, playlist)| playlist)
+        .map_err(|e| format!("Failed to parse playlist: {e:?}"))
 }
 
 fn parse_media_playlist(playlist: &MediaPlaylist, base_url: &str) -> HlsStream {
-    // Extract base URL for relative segment paths
-    let base = base_url.rsplit_once('/').map(|(b, _)| b).unwrap_or(base_url);
-
-    // Get encryption info from the first key tag if present
-    let encryption = playlist.segments.iter()
-        .find_map(|s| s.key.as_ref())
-        .map(|key| HlsEncryption {
-            method: format!("{:?}", key.method),
-            uri: key.uri.clone(),
-            iv: key.iv.clone(),
-            keyformat: key.keyformat.clone(),
-        });
+    // A key tag applies to every segment after it, until the next one
+    let mut keys: Vec<HlsEncryption> = Vec::new();
+    let mut current_key: Option<usize> = None;
 
     // Get init segment URL from EXT-X-MAP (for fMP4 streams)
-    let init_segment_url = playlist.segments.iter()
-        .find_map(|seg| seg.map.as_ref())
-        .map(|map| {
-            if map.uri.starts_with("http") {
-                map.uri.clone()
-            } else {
-                format!("{}/{}", base, map.uri)
-            }
+    let init_map = playlist.segments.iter().find_map(|seg| seg.map.as_ref());
+    let init_segment_url = init_map.map(|map| resolve_uri(base_url, &map.uri));
+    let init_segment_range = init_map
+        .and_then(|map| map.byte_range.as_ref())
+        .map(|br| SegmentRange {
+            start: br.offset.unwrap_or(0),
+            length: br.length,
         });
 
-    // Parse segments
-    let segments = playlist.segments.iter()
-        .map(|seg| {
-            let uri = if seg.uri.starts_with("http") {
-                seg.uri.clone()
-            } else {
-                format!("{}/{}", base, seg.uri)
+    // Parse segments. A byte range without an offset starts right after the
+    // previous segment's range, which must be in the same resource.
+    let mut segments = Vec::with_capacity(playlist.segments.len());
+    let mut previous_range: Option<(String, u64)> = None;
+    for (seg, sequence) in playlist.segments.iter().zip(playlist.media_sequence..) {
+        if let Some(key) = &seg.key {
+            let encryption = HlsEncryption {
+                method: format!("{:?}", key.method),
+                uri: key.uri.as_ref().map(|uri| resolve_uri(base_url, uri)),
+                iv: key.iv.clone(),
+                keyformat: key.keyformat.clone(),
             };
-
-            HlsSegment {
-                uri,
-                duration: seg.duration,
-                byte_range: seg.byte_range.as_ref().map(|br| {
-                    (br.length, br.offset.unwrap_or(0))
-                }),
+            current_key = (encryption.method != "None").then(|| {
+                keys.iter().position(|k| *k == encryption).unwrap_or_else(|| {
+                    keys.push(encryption);
+                    keys.len() - 1
+                })
+            });
+        }
+        let uri = resolve_uri(base_url, &seg.uri);
+        let byte_range = seg.byte_range.as_ref().map(|br| {
+            let implicit_start = previous_range
+                .as_ref()
+                .filter(|(previous_uri, _)| *previous_uri == uri)
+                .map_or(0, |(_, end)| *end);
+            SegmentRange {
+                start: br.offset.unwrap_or(implicit_start),
+                length: br.length,
             }
-        })
-        .collect();
+        });
+        previous_range = byte_range.map(|range| (uri.clone(), range.end()));
+
+        segments.push(HlsSegment {
+            uri,
+            duration: seg.duration,
+            byte_range,
+            sequence,
+            key: current_key,
+        });
+    }
 
     HlsStream {
         segments,
         target_duration: playlist.target_duration,
-        encryption,
+        keys,
         init_segment_url,
+        init_segment_range,
+        variant: None,
+        fallback_variants: Vec::new(),
     }
 }
 
-/// Download a segment
-pub async fn download_segment(client: &Client, url: &str) -> Result<Vec<u8>, String> {
-    let response = client
-        .get(url)
+/// A failed segment request
+struct SegmentError {
+    message: String,
+    /// Whether trying again may help: connection problems, timeouts, server
+    /// errors and rate limiting, but not e.g. a missing segment
+    transient: bool,
+}
+
+impl SegmentError {
+    fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            transient: false,
+        }
+    }
+
+    fn request(context: &str, e: reqwest::Error) -> Self {
+        let transient = match e.status() {
+            Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+            None => e.is_connect() || e.is_timeout() || e.is_request() || e.is_body(),
+        };
+        Self {
+            message: format!("{context}: {e}"),
+            transient,
+        }
+    }
+}
+
+impl From<SegmentError> for String {
+    fn from(e: SegmentError) -> Self {
+        e.message
+    }
+}
+
+/// Download a segment, or just a byte range of it
+async fn download_segment(
+    client: &Client,
+    url: &str,
+    range: Option<SegmentRange>,
+) -> Result<Vec<u8>, SegmentError> {
+    let mut request = client.get(url);
+    if let Some(range) = range {
+        let value = format!("bytes={}-{}", range.start, range.end() - 1);
+        request = request.header(header::RANGE, value);
+    }
+    let response = request
         .send()
         .await
-        .map_err(|e| format!("Failed to fetch segment: {e}"))?;
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| SegmentError::request("Failed to fetch segment", e))?;
+    let status = response.status();
 
     let bytes = response
         .bytes()
         .await
-        .map_err(|e| format!("Failed to read segment: {e}"))?;
+        .map_err(|e| SegmentError::request("Failed to read segment", e))?;
 
-    Ok(bytes.to_vec())
+    match range {
+        // The server ignored the Range header and sent the whole resource
+        Some(range) if status != StatusCode::PARTIAL_CONTENT => {
+            let start = range.start as usize;
+            let end = range.end() as usize;
+            bytes
+                .get(start..end)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| {
+                    SegmentError::permanent(format!("Segment range {start}-{end} is past the end of the file"))
+                })
+        }
+        _ => Ok(bytes.to_vec()),
+    }
+}
+
+/// Group segments into requests: consecutive byte ranges of the same
+/// resource are fetched together, up to [`MAX_MERGED_BYTES`] per request.
+/// Returns index ranges into `segments`.
+fn plan_requests(segments: &[HlsSegment]) -> Vec<Range<usize>> {
+    let mut requests: Vec<Range<usize>> = Vec::new();
+    for (index, segment) in segments.iter().enumerate() {
+        if let Some(request) = requests.last_mut()
+            && let Some(range) = segment.byte_range
+        {
+            let first = &segments[request.start];
+            let last = &segments[request.end - 1];
+            let mergeable = last.uri == segment.uri
+                && last.byte_range.is_some_and(|r| r.end() == range.start)
+                && first
+                    .byte_range
+                    .is_some_and(|r| range.end() - r.start <= MAX_MERGED_BYTES);
+            if mergeable {
+                request.end = index + 1;
+                continue;
+            }
+        }
+        requests.push(index..index + 1);
+    }
+    requests
+}
+
+/// Download a group of segments from [`plan_requests`] in one request,
+/// returning each segment's data, decrypted if the playlist is AES-128
+/// encrypted
+async fn download_media_segments(
+    client: &Client,
+    segments: &[HlsSegment],
+    decryptor: Option<&SegmentDecryptor>,
+) -> Result<Vec<Vec<u8>>, SegmentError> {
+    let (Some(first), Some(last)) = (segments.first(), segments.last()) else {
+        return Ok(Vec::new());
+    };
+    let range = first.byte_range.zip(last.byte_range).map(|(first, last)| SegmentRange {
+        start: first.start,
+        length: last.end() - first.start,
+    });
+    let mut data = download_segment(client, &first.uri, range).await?;
+
+    // Split the merged response back into segments
+    let mut parts = Vec::with_capacity(segments.len());
+    for segment in segments.iter().rev() {
+        let part = match segment.byte_range {
+            Some(range) if segments.len() > 1 => {
+                let offset = (range.start - first.byte_range.map_or(0, |r| r.start)) as usize;
+                if offset > data.len() {
+                    return Err(SegmentError::permanent("Merged segment response was too short"));
+                }
+                data.split_off(offset)
+            }
+            _ => std::mem::take(&mut data),
+        };
+        parts.push(match decryptor {
+            Some(decryptor) => decryptor
+                .decrypt(segment, part)
+                .map_err(SegmentError::permanent)?,
+            None => part,
+        });
+    }
+    parts.reverse();
+    Ok(parts)
+}
+
+/// [`download_media_segments`], retrying requests that failed for a reason
+/// that may pass with exponential backoff
+async fn download_with_retry(
+    client: &Client,
+    segments: &[HlsSegment],
+    decryptor: Option<&SegmentDecryptor>,
+) -> Result<Vec<Vec<u8>>, String> {
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match download_media_segments(client, segments, decryptor).await {
+            Ok(parts) => return Ok(parts),
+            Err(e) if e.transient && attempt < MAX_ATTEMPTS => {
+                eprintln!("[hls] Attempt {attempt} failed: {}, retrying in {delay:?}", e.message);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) if attempt > 1 => return Err(format!("{} (after {attempt} attempts)", e.message)),
+            Err(e) => return Err(e.message),
+        }
+    }
+}
+
+/// Download every media segment of a playlist, with up to
+/// [`CONCURRENT_REQUESTS`] requests in flight. Results come out in playlist
+/// order, one item per request: the range of segment indices it covers and
+/// the data of each of those segments.
+pub fn download_segments<'a>(
+    client: &'a Client,
+    playlist: &'a HlsStream,
+    decryptor: Option<&'a SegmentDecryptor>,
+) -> impl Stream<Item = (Range<usize>, Result<Vec<Vec<u8>>, String>)> + 'a {
+    stream::iter(plan_requests(&playlist.segments))
+        .map(move |request| async move {
+            let segments = &playlist.segments[request.clone()];
+            (request, download_with_retry(client, segments, decryptor).await)
+        })
+        .buffered(CONCURRENT_REQUESTS)
+}
+
+/// Download a playlist's init segment (if any), decrypting it when needed
+pub async fn download_init_segment(
+    client: &Client,
+    playlist: &HlsStream,
+    decryptor: Option<&SegmentDecryptor>,
+) -> Result<Option<Vec<u8>>, String> {
+    let Some(init_url) = &playlist.init_segment_url else {
+        return Ok(None);
+    };
+    let data = download_segment(client, init_url, playlist.init_segment_range).await?;
+    match decryptor {
+        Some(decryptor) => decryptor.decrypt_init(data).map(Some),
+        None => Ok(Some(data)),
+    }
+}
+
+/// Download a playlist's segments in order into a shared buffer.
+///
+/// `ready` fires once the init segment and the first few media segments are
+/// buffered (or the whole playlist, if it is shorter), so playback can start
+/// while the remaining segments keep downloading. `progress` is called with
+/// the number of segments downloaded so far and the total.
+pub async fn download_to_buffer(
+    client: Client,
+    playlist: HlsStream,
+    buffer: Arc<StreamBuffer>,
+    ready: oneshot::Sender<Result<(), String>>,
+    progress: impl Fn(usize, usize),
+) {
+    let mut ready = Some(ready);
+
+    let init = match SegmentDecryptor::for_playlist(&client, &playlist).await {
+        Ok(decryptor) => download_init_segment(&client, &playlist, decryptor.as_ref())
+            .await
+            .and_then(SegmentDemuxer::new)
+            .map(|(demuxer, header)| (decryptor, demuxer, header)),
+        Err(e) => Err(e),
+    };
+    let (decryptor, demuxer) = match init {
+        Ok((decryptor, demuxer, header)) => {
+            if !header.is_empty() {
+                buffer.append(&header);
+            }
+            (decryptor, demuxer)
+        }
+        Err(e) => {
+            buffer.fail(e.clone());
+            if let Some(ready) = ready.take() {
+                let _ = ready.send(Err(e));
+            }
+            return;
+        }
+    };
+
+    let total = playlist.segments.len();
+    let mut downloads = std::pin::pin!(download_segments(&client, &playlist, decryptor.as_ref()));
+    while let Some((request, result)) = downloads.next().await {
+        let result = result.and_then(|parts| {
+            parts
+                .into_iter()
+                .map(|data| demuxer.demux(data))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        match result {
+            Ok(parts) => {
+                for data in parts {
+                    buffer.append(&data);
+                }
+                progress(request.end, total);
+            }
+            Err(e) => {
+                eprintln!("[hls] Segment {} failed: {e}", request.start);
+                buffer.fail(e.clone());
+                if let Some(ready) = ready.take() {
+                    let _ = ready.send(Err(e));
+                }
+                return;
+            }
+        }
+
+        if request.end >= PREBUFFER_SEGMENTS
+            && let Some(ready) = ready.take()
+        {
+            let _ = ready.send(Ok(()));
+        }
+    }
+
+    buffer.finish();
+    if let Some(ready) = ready.take() {
+        let _ = ready.send(Ok(()));
+    }
 }
@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Opening a track's stream for playback.
+//!
+//! Fetching playlists and buffering the first segments can take seconds, so
+//! the player runs a [`Loader`] in a task of its own and keeps handling
+//! commands meanwhile. Starting another track aborts
+//! the task; anything it had started downloading is cancelled with it.
+
+use super::player::AudioEvent;
+use super::stream_buffer::{StreamBuffer, StreamReader};
+use super::hls;
+use crate::api::{StreamFormat, StreamPreference};
+use reqwest::Client;
+use rodio::Decoder;
+use std::sync::Arc;
+use std::time::Duration;
+use stream_download::storage::temp::TempStorageProvider;
+use stream_download::{Settings, StreamDownload};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// A decoder ready to be played
+pub enum Loaded {
+    /// HLS segments streaming into a buffer
+    Hls {
+        source: Decoder<StreamReader>,
+        download: HlsDownload,
+        /// Length of the playlist, until the decoder knows better
+        duration: Duration,
+        /// What's actually playing, down to the variant
+        format: StreamFormat,
+    },
+    /// A progressive stream, fetched on demand as it's read
+    Progressive(Decoder<StreamDownload<TempStorageProvider>>),
+}
+
+/// A background download filling a stream buffer. Dropping it cancels the
+/// download and releases readers waiting on the buffer.
+pub struct HlsDownload {
+    task: Option<JoinHandle<()>>,
+    buffer: Arc<StreamBuffer>,
+}
+
+impl HlsDownload {
+    /// Hand the download over to the player, which cancels it from then on
+    pub fn into_parts(mut self) -> (Option<JoinHandle<()>>, Arc<StreamBuffer>) {
+        (self.task.take(), Arc::clone(&self.buffer))
+    }
+}
+
+impl Drop for HlsDownload {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            self.buffer.finish();
+        }
+    }
+}
+
+/// Everything needed to open a stream, detached from the player
+pub struct Loader {
+    pub client: Client,
+    pub preference: StreamPreference,
+    /// For download progress, which is reported as it happens
+    pub event_tx: mpsc::Sender<AudioEvent>,
+    pub track_id: Option<u64>,
+    /// Format of the transcoding being loaded
+    pub format: StreamFormat,
+}
+
+impl Loader {
+    /// Open `url` for playback. On failure, returns the event telling the UI
+    /// why, for the player to send if this is still the track it wants.
+    pub async fn load(&self, url: &str, permalink_url: Option<&str>) -> Result<Loaded, AudioEvent> {
+        // Check if this is an HLS stream (m3u8)
+        if url.contains(".m3u8") {
+            eprintln!("  -> HLS stream detected");
+            return self.load_hls(url, permalink_url).await;
+        }
+
+        eprintln!("  -> Progressive stream, downloading...");
+        self.load_progressive(url).await
+    }
+
+    async fn load_progressive(&self, url: &str) -> Result<Loaded, AudioEvent> {
+        let url = url
+            .parse::<reqwest::Url>()
+            .map_err(|e| AudioEvent::Error(format!("Invalid URL: {e}")))?;
+
+        // Create streaming download
+        let stream = StreamDownload::new_http(url, TempStorageProvider::default(), Settings::default())
+            .await
+            .map_err(|e| {
+                eprintln!("  -> Stream download FAILED: {e}");
+                AudioEvent::Error(format!("Failed to stream: {e}"))
+            })?;
+        eprintln!("  -> Stream download started");
+
+        // Probing reads from the network, so decode off the runtime thread
+        eprintln!("  -> Decoding audio...");
+        match tokio::task::spawn_blocking(move || Decoder::new(stream)).await {
+            Ok(Ok(source)) => {
+                eprintln!("  -> Decoder created successfully");
+                Ok(Loaded::Progressive(source))
+            }
+            Ok(Err(e)) => {
+                eprintln!("  -> Decode FAILED: {e}");
+                Err(AudioEvent::Error(format!("Failed to decode: {e}")))
+            }
+            Err(e) => Err(AudioEvent::Error(format!("Decoder task failed: {e}"))),
+        }
+    }
+
+    /// Open an HLS stream. DRM-protected playlists are reported back, for the
+    /// app to try the next stream resolver.
+    async fn load_hls(&self, url: &str, permalink_url: Option<&str>) -> Result<Loaded, AudioEvent> {
+        let playlist = self.fetch_playlist(url).await?;
+        self.stream_playlist(playlist, permalink_url).await
+    }
+
+    async fn fetch_playlist(&self, url: &str) -> Result<hls::HlsStream, AudioEvent> {
+        hls::fetch_playlist(&self.client, url, self.preference)
+            .await
+            .map_err(|e| AudioEvent::Error(format!("Failed to parse HLS: {e}")))
+    }
+
+    /// Play HLS segments from a parsed playlist while they download.
+    /// Playback starts after the first few segments; the rest are fetched in
+    /// a background task and fed to the decoder as they arrive. If the
+    /// playlist fails to download or decode, or needs DRM, the next variant
+    /// of its master playlist is tried. If none plays and one of them was
+    /// DRM-protected, that's reported instead of an error.
+    async fn stream_playlist(
+        &self,
+        mut playlist: hls::HlsStream,
+        permalink_url: Option<&str>,
+    ) -> Result<Loaded, AudioEvent> {
+        let mut format = self.format.clone();
+        let mut drm_type = None;
+        loop {
+            let started = match drm_protection(&playlist) {
+                Some(drm) => {
+                    let error = format!("DRM-protected ({drm})");
+                    drm_type.get_or_insert(drm);
+                    Err(error)
+                }
+                None => self.start_variant(&playlist).await,
+            };
+            match started {
+                Ok((source, download)) => {
+                    if let Some(variant) = &playlist.variant {
+                        format.codec = variant.codec.or(format.codec);
+                        format.bitrate = Some(variant.bandwidth);
+                    }
+                    return Ok(Loaded::Hls {
+                        source,
+                        download,
+                        duration: playlist.duration(),
+                        format,
+                    });
+                }
+                Err(e) => {
+                    eprintln!("  -> HLS variant FAILED: {e}");
+                    let Some(fallback) = hls::fetch_fallback(&self.client, &playlist).await else {
+                        return Err(match drm_type {
+                            Some(drm_type) => AudioEvent::DrmProtected {
+                                drm_type,
+                                track_url: permalink_url.unwrap_or("").to_string(),
+                            },
+                            None => AudioEvent::Error(e),
+                        });
+                    };
+                    eprintln!("  -> Trying another variant...");
+                    playlist = fallback;
+                }
+            }
+        }
+    }
+
+    /// Start downloading one media playlist into a stream buffer and create a
+    /// decoder for it. The download is cancelled again on failure.
+    async fn start_variant(
+        &self,
+        playlist: &hls::HlsStream,
+    ) -> Result<(Decoder<StreamReader>, HlsDownload), String> {
+        if playlist.segments.is_empty() {
+            return Err("HLS playlist has no segments".into());
+        }
+
+        let buffer = StreamBuffer::new()?;
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let event_tx = self.event_tx.clone();
+        let track_id = self.track_id;
+        let download = HlsDownload {
+            task: Some(tokio::spawn(hls::download_to_buffer(
+                self.client.clone(),
+                playlist.clone(),
+                Arc::clone(&buffer),
+                ready_tx,
+                move |downloaded, total| {
+                    // Progress is advisory, so drop updates while the app is busy
+                    let _ = event_tx.try_send(AudioEvent::DownloadProgress {
+                        track_id,
+                        downloaded,
+                        total,
+                    });
+                },
+            ))),
+            buffer: Arc::clone(&buffer),
+        };
+
+        eprintln!("  -> Buffering first HLS segments...");
+        ready_rx
+            .await
+            .unwrap_or_else(|_| Err("HLS download was cancelled".into()))
+            .map_err(|e| format!("Failed to download HLS segments: {e}"))?;
+
+        // Probing may read past the buffered segments, so decode off the runtime
+        // thread while the download task keeps filling the buffer
+        let reader = buffer.reader();
+        match tokio::task::spawn_blocking(move || Decoder::new(reader)).await {
+            Ok(Ok(source)) => Ok((source, download)),
+            Ok(Err(e)) => Err(format!("Failed to decode: {e}")),
+            Err(e) => Err(format!("Decoder task failed: {e}")),
+        }
+    }
+}
+
+/// The DRM a media playlist needs, if it's encrypted in a way we can't
+/// decrypt. SoundCloud uses CENC/PlayReady, which needs a license server, and
+/// SAMPLE-AES encrypts inside the media; plain AES-128 is decrypted while the
+/// segments download.
+fn drm_protection(playlist: &hls::HlsStream) -> Option<String> {
+    let enc = playlist.encryption()?;
+    eprintln!("HLS encryption: method={}, keyformat={:?}, key_uri={:?}",
+        enc.method, enc.keyformat, enc.uri);
+    enc.needs_fallback().then(|| {
+        let drm_type = enc.describe();
+        eprintln!("Encrypted stream detected ({drm_type})");
+        drm_type
+    })
+}
@@ -0,0 +1,408 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Loudness normalization (ReplayGain-style) based on EBU R128.
+//!
+//! Tracks are measured from their decoded samples, either while preloading or
+//! the first time they play through. The resulting gain is kept per track id in
+//! `~/.cache/cosmic-soundcloud/loudness.json`, next to the audio cache, so each
+//! track only has to be measured once.
+
+use super::biquad::Biquad;
+use rodio::Source;
+use rodio::source::SeekError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Loudness all tracks are normalized to (LUFS), the ReplayGain 2.0 reference
+const REFERENCE_LUFS: f32 = -18.0;
+
+/// Blocks quieter than this never count towards the integrated loudness
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+
+/// Blocks more than this far below the ungated loudness are ignored (LU)
+const RELATIVE_GATE_LU: f64 = 10.0;
+
+/// Gating blocks are 400 ms long and overlap by 75%, so a new block starts
+/// every 100 ms
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+/// How the player picks the gain for a track
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Play tracks as they are
+    #[default]
+    Off,
+    /// Bring every track to the reference loudness
+    Track,
+    /// Use one gain for all of these tracks, preserving the level differences
+    /// between them. Tracks outside the album fall back to their track gain.
+    Album(Vec<u64>),
+}
+
+/// Measured gain for one track
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TrackGain {
+    /// Gain (dB) that brings the track to the reference loudness
+    pub gain_db: f32,
+    /// Sample peak (linear), used to keep positive gains from clipping
+    pub peak: f32,
+    /// Measured duration in seconds, used to weight album loudness
+    pub duration: f32,
+}
+
+impl TrackGain {
+    fn from_loudness(lufs: f64, peak: f32, duration: f32) -> Self {
+        Self {
+            gain_db: REFERENCE_LUFS - lufs as f32,
+            peak,
+            duration,
+        }
+    }
+
+    /// Integrated loudness (LUFS) the gain was derived from
+    fn loudness(&self) -> f32 {
+        REFERENCE_LUFS - self.gain_db
+    }
+}
+
+/// Return the loudness index path (`~/.cache/cosmic-soundcloud/loudness.json`).
+fn index_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("cosmic-soundcloud").join("loudness.json"))
+}
+
+/// Per-track gains, persisted to disk as they are measured
+#[derive(Debug, Default)]
+pub struct LoudnessIndex {
+    gains: HashMap<u64, TrackGain>,
+}
+
+impl LoudnessIndex {
+    /// Load the index from disk, starting empty if it is missing or unreadable
+    pub fn load() -> Self {
+        let gains = index_path()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self { gains }
+    }
+
+    pub fn contains(&self, track_id: u64) -> bool {
+        self.gains.contains_key(&track_id)
+    }
+
+    /// Record a measurement and write the index back to disk
+    pub fn insert(&mut self, track_id: u64, gain: TrackGain) {
+        self.gains.insert(track_id, gain);
+        if let Err(e) = self.save() {
+            eprintln!("[loudness] Failed to save index: {e}");
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = index_path().ok_or("No cache directory available")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create cache dir: {e}"))?;
+        }
+        let data = serde_json::to_vec(&self.gains).map_err(|e| e.to_string())?;
+        std::fs::write(path, data).map_err(|e| format!("Failed to write loudness index: {e}"))
+    }
+
+    /// Linear gain to apply to a track. Unmeasured tracks play unchanged.
+    pub fn gain(&self, normalization: &Normalization, track_id: Option<u64>) -> f32 {
+        let Some(track_id) = track_id else {
+            return 1.0;
+        };
+        let gain = match normalization {
+            Normalization::Off => None,
+            Normalization::Track => self.gains.get(&track_id).copied(),
+            Normalization::Album(tracks) if tracks.contains(&track_id) => self
+                .album_gain(tracks)
+                .or_else(|| self.gains.get(&track_id).copied()),
+            Normalization::Album(_) => self.gains.get(&track_id).copied(),
+        };
+        let Some(gain) = gain else {
+            return 1.0;
+        };
+
+        let linear = 10f32.powf(gain.gain_db / 20.0);
+        if gain.peak > 0.0 {
+            linear.min(1.0 / gain.peak)
+        } else {
+            linear
+        }
+    }
+
+    /// Combined gain of the measured tracks in an album, treating them as one
+    /// long recording
+    fn album_gain(&self, tracks: &[u64]) -> Option<TrackGain> {
+        let measured: Vec<&TrackGain> = tracks.iter().filter_map(|id| self.gains.get(id)).collect();
+        let total_duration: f32 = measured.iter().map(|g| g.duration).sum();
+        if total_duration <= 0.0 {
+            return None;
+        }
+
+        let energy: f64 = measured
+            .iter()
+            .map(|g| f64::from(g.duration) * 10f64.powf(f64::from(g.loudness()) / 10.0))
+            .sum::<f64>()
+            / f64::from(total_duration);
+        let peak = measured.iter().map(|g| g.peak).fold(0.0, f32::max);
+        Some(TrackGain::from_loudness(
+            10.0 * energy.log10(),
+            peak,
+            total_duration,
+        ))
+    }
+}
+
+/// K-weighting filter from ITU-R BS.1770: a high shelf modelling the head,
+/// followed by a high-pass. Coefficients are derived for any sample rate.
+#[derive(Debug, Clone, Copy)]
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = f64::from(sample_rate);
+
+        let f0 = 1_681.974_450_955_533;
+        let gain_db = 3.999_843_853_973_347;
+        let q = 0.707_175_236_955_419_6;
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let f0 = 38.135_470_876_024_44;
+        let q = 0.500_327_037_323_877_3;
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        Self { shelf, high_pass }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
+
+/// Gated integrated loudness meter (EBU R128) fed with interleaved samples
+pub struct LoudnessMeter {
+    filters: Vec<KWeighting>,
+    channel: usize,
+    sample_rate: u32,
+    frames: u64,
+    /// Frames per 100 ms sub-block
+    sub_block_frames: usize,
+    sub_block_pos: usize,
+    sub_block_sum: f64,
+    /// Mean square of the most recent sub-blocks
+    recent: [f64; SUB_BLOCKS_PER_BLOCK],
+    recent_count: usize,
+    /// Mean square of every 400 ms gating block
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            filters: vec![KWeighting::new(sample_rate); usize::from(channels.max(1))],
+            channel: 0,
+            sample_rate,
+            frames: 0,
+            sub_block_frames: (sample_rate as usize / 10).max(1),
+            sub_block_pos: 0,
+            sub_block_sum: 0.0,
+            recent: [0.0; SUB_BLOCKS_PER_BLOCK],
+            recent_count: 0,
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Feed the next interleaved sample
+    #[inline]
+    pub fn push(&mut self, sample: f32) {
+        self.peak = self.peak.max(sample.abs());
+        let weighted = self.filters[self.channel].process(f64::from(sample));
+        self.sub_block_sum += weighted * weighted;
+
+        self.channel += 1;
+        if self.channel < self.filters.len() {
+            return;
+        }
+        self.channel = 0;
+        self.frames += 1;
+        self.sub_block_pos += 1;
+        if self.sub_block_pos == self.sub_block_frames {
+            self.end_sub_block();
+        }
+    }
+
+    fn end_sub_block(&mut self) {
+        let mean_square = self.sub_block_sum / self.sub_block_frames as f64;
+        self.sub_block_sum = 0.0;
+        self.sub_block_pos = 0;
+
+        self.recent.rotate_left(1);
+        self.recent[SUB_BLOCKS_PER_BLOCK - 1] = mean_square;
+        self.recent_count += 1;
+        if self.recent_count >= SUB_BLOCKS_PER_BLOCK {
+            let block = self.recent.iter().sum::<f64>() / SUB_BLOCKS_PER_BLOCK as f64;
+            self.blocks.push(block);
+        }
+    }
+
+    /// Finish the measurement. Returns `None` for tracks too short or too
+    /// quiet to measure.
+    pub fn finish(self) -> Option<TrackGain> {
+        let absolute = block_energy(ABSOLUTE_GATE_LUFS);
+        let above_absolute: Vec<f64> = self.blocks.into_iter().filter(|&b| b > absolute).collect();
+        if above_absolute.is_empty() {
+            return None;
+        }
+
+        let ungated = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64;
+        let relative = ungated * 10f64.powf(-RELATIVE_GATE_LU / 10.0);
+        let gated: Vec<f64> = above_absolute.into_iter().filter(|&b| b > relative).collect();
+        if gated.is_empty() {
+            return None;
+        }
+
+        let mean = gated.iter().sum::<f64>() / gated.len() as f64;
+        let duration = self.frames as f32 / self.sample_rate as f32;
+        Some(TrackGain::from_loudness(
+            -0.691 + 10.0 * mean.log10(),
+            self.peak,
+            duration,
+        ))
+    }
+}
+
+/// Mean square energy corresponding to a block loudness (LUFS)
+fn block_energy(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+/// Measure a whole source. Blocks until the source is exhausted, so callers
+/// should run it on a blocking thread.
+pub fn measure<S>(source: S) -> Option<TrackGain>
+where
+    S: Source<Item = f32>,
+{
+    let mut meter = LoudnessMeter::new(source.channels(), source.sample_rate());
+    for sample in source {
+        meter.push(sample);
+    }
+    meter.finish()
+}
+
+/// Source wrapper that measures a track as it plays and reports the result
+/// once it has played through. Seeking abandons the measurement, since the
+/// skipped audio would be missing from it.
+pub struct Measure<S> {
+    inner: S,
+    meter: Option<(u64, LoudnessMeter)>,
+    result_tx: mpsc::UnboundedSender<(u64, TrackGain)>,
+}
+
+impl<S> Measure<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Wrap `inner`, measuring it only when `track_id` is given
+    pub fn new(
+        inner: S,
+        track_id: Option<u64>,
+        result_tx: mpsc::UnboundedSender<(u64, TrackGain)>,
+    ) -> Self {
+        let meter = track_id.map(|id| (id, LoudnessMeter::new(inner.channels(), inner.sample_rate())));
+        Self {
+            inner,
+            meter,
+            result_tx,
+        }
+    }
+}
+
+impl<S> Iterator for Measure<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        match self.inner.next() {
+            Some(sample) => {
+                if let Some((_, meter)) = &mut self.meter {
+                    meter.push(sample);
+                }
+                Some(sample)
+            }
+            None => {
+                if let Some((track_id, meter)) = self.meter.take()
+                    && let Some(gain) = meter.finish()
+                {
+                    let _ = self.result_tx.send((track_id, gain));
+                }
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Measure<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.meter = None;
+        self.inner.try_seek(pos)
+    }
+}
@@ -0,0 +1,349 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Writing AAC into an M4A file.
+//!
+//! Downloads hold AAC as ADTS frames, which most music libraries and DJ
+//! software won't import. Here the frames are remuxed, unchanged, into an MP4
+//! container with the movie header up front and iTunes-style tags. The input
+//! is read twice, once for the frame sizes the header lists and once for the
+//! audio, so nothing is held in memory beyond the sample table.
+
+use std::io::{self, Read, Seek, Write};
+
+/// Samples per AAC frame
+const SAMPLES_PER_FRAME: u32 = 1024;
+
+/// Sampling frequencies by ADTS frequency index
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Tags for the file's `ilst` box
+pub struct Metadata<'a> {
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub album: Option<&'a str>,
+    /// Cover image, JPEG or PNG
+    pub artwork: Option<&'a [u8]>,
+}
+
+/// Stream parameters from an ADTS header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AdtsConfig {
+    /// MPEG-4 audio object type minus one
+    profile: u8,
+    frequency_index: u8,
+    channels: u8,
+}
+
+/// One ADTS frame's header
+struct AdtsFrame {
+    config: AdtsConfig,
+    /// Bytes of CRC between the header and the raw frame
+    crc_len: u64,
+    payload_len: u64,
+}
+
+/// Whether `data` starts with an ADTS header
+pub fn is_adts(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0xFF && data[1] & 0xF6 == 0xF0
+}
+
+/// Remux the ADTS stream in `input` into an M4A file written to `out`
+pub fn write(input: &mut (impl Read + Seek), out: &mut impl Write, metadata: &Metadata) -> Result<(), String> {
+    // First pass: frame sizes for the sample table
+    input.rewind().map_err(|e| e.to_string())?;
+    let mut config = None;
+    let mut sizes = Vec::new();
+    while let Some(frame) = next_frame(input)? {
+        match config {
+            None => config = Some(frame.config),
+            Some(config) if config != frame.config => {
+                return Err("AAC stream changes format midway".into());
+            }
+            Some(_) => {}
+        }
+        let skipped = io::copy(&mut input.take(frame.crc_len + frame.payload_len), &mut io::sink())
+            .map_err(|e| e.to_string())?;
+        if skipped < frame.crc_len + frame.payload_len {
+            // A truncated last frame is left out
+            break;
+        }
+        sizes.push(frame.payload_len as u32);
+    }
+    let config = config.ok_or("No AAC frames to export")?;
+
+    let ftyp = mp4_box(b"ftyp", &[b"M4A ".as_slice(), &0x200u32.to_be_bytes(), b"M4A isommp42"].concat());
+    let mdat_len: u64 = sizes.iter().map(|size| u64::from(*size)).sum();
+    let mdat_size = u32::try_from(8 + mdat_len).map_err(|_| "Track is too large for an M4A file")?;
+    // The sample table's offset doesn't change the movie header's size
+    let moov_len = moov(&config, &sizes, 0, metadata)?.len();
+    let data_offset = u32::try_from(ftyp.len() + moov_len + 8).map_err(|_| "M4A header is too large")?;
+    let moov = moov(&config, &sizes, data_offset, metadata)?;
+
+    out.write_all(&ftyp).map_err(|e| e.to_string())?;
+    out.write_all(&moov).map_err(|e| e.to_string())?;
+    out.write_all(&mdat_size.to_be_bytes()).map_err(|e| e.to_string())?;
+    out.write_all(b"mdat").map_err(|e| e.to_string())?;
+
+    // Second pass: the raw frames, without their ADTS headers
+    input.rewind().map_err(|e| e.to_string())?;
+    for size in sizes {
+        let frame = next_frame(input)?.ok_or("AAC stream ended early")?;
+        io::copy(&mut input.take(frame.crc_len), &mut io::sink()).map_err(|e| e.to_string())?;
+        let copied = io::copy(&mut input.take(u64::from(size)), out).map_err(|e| e.to_string())?;
+        if copied < u64::from(size) {
+            return Err("AAC stream ended early".into());
+        }
+    }
+    Ok(())
+}
+
+/// Read the next ADTS header, or `None` at the end of the stream
+fn next_frame(input: &mut impl Read) -> Result<Option<AdtsFrame>, String> {
+    let mut header = [0u8; 7];
+    match input.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    if !is_adts(&header) {
+        return Err("Lost ADTS sync in AAC stream".into());
+    }
+    if header[6] & 0x03 != 0 {
+        return Err("ADTS frames with several raw data blocks aren't supported".into());
+    }
+
+    let has_crc = header[1] & 0x01 == 0;
+    let header_len: u64 = if has_crc { 9 } else { 7 };
+    let frame_len =
+        (u64::from(header[3] & 0x03) << 11) | (u64::from(header[4]) << 3) | u64::from(header[5] >> 5);
+    let payload_len = frame_len
+        .checked_sub(header_len)
+        .ok_or("Invalid ADTS frame length")?;
+    let config = AdtsConfig {
+        profile: header[2] >> 6,
+        frequency_index: (header[2] >> 2) & 0x0F,
+        channels: ((header[2] & 0x01) << 2) | (header[3] >> 6),
+    };
+    Ok(Some(AdtsFrame {
+        config,
+        crc_len: header_len - 7,
+        payload_len,
+    }))
+}
+
+/// The movie header: one audio track whose samples all sit in one chunk at
+/// `data_offset`, followed by the tags
+fn moov(config: &AdtsConfig, sizes: &[u32], data_offset: u32, metadata: &Metadata) -> Result<Vec<u8>, String> {
+    let sample_rate = *SAMPLE_RATES
+        .get(usize::from(config.frequency_index))
+        .ok_or("Invalid AAC frequency index")?;
+    let frame_count = u32::try_from(sizes.len()).map_err(|_| "Too many AAC frames")?;
+    let duration = frame_count
+        .checked_mul(SAMPLES_PER_FRAME)
+        .ok_or("Track is too long for an M4A file")?;
+    let total_bytes: u64 = sizes.iter().map(|size| u64::from(*size)).sum();
+    let seconds = (u64::from(duration) / u64::from(sample_rate)).max(1);
+    let avg_bitrate = (total_bytes * 8 / seconds) as u32;
+    let max_frame = sizes.iter().copied().max().unwrap_or(0);
+
+    let mvhd = full_box(
+        b"mvhd",
+        0,
+        0,
+        &[
+            &0u32.to_be_bytes()[..],
+            &0u32.to_be_bytes(),
+            &sample_rate.to_be_bytes(),
+            &duration.to_be_bytes(),
+            // Rate 1.0, volume 1.0, reserved
+            &0x0001_0000u32.to_be_bytes(),
+            &0x0100u16.to_be_bytes(),
+            &[0; 10],
+            &matrix(),
+            // Pre-defined, next track ID
+            &[0; 24],
+            &2u32.to_be_bytes(),
+        ]
+        .concat(),
+    );
+
+    let tkhd = full_box(
+        b"tkhd",
+        0,
+        // Enabled, in movie
+        0x03,
+        &[
+            &0u32.to_be_bytes()[..],
+            &0u32.to_be_bytes(),
+            // Track ID, reserved
+            &1u32.to_be_bytes(),
+            &[0; 4],
+            &duration.to_be_bytes(),
+            // Reserved, layer, alternate group
+            &[0; 12],
+            // Volume 1.0, reserved
+            &0x0100u16.to_be_bytes(),
+            &[0; 2],
+            &matrix(),
+            // Width, height
+            &[0; 8],
+        ]
+        .concat(),
+    );
+
+    let mdhd = full_box(
+        b"mdhd",
+        0,
+        0,
+        &[
+            &0u32.to_be_bytes()[..],
+            &0u32.to_be_bytes(),
+            &sample_rate.to_be_bytes(),
+            &duration.to_be_bytes(),
+            // Language "und", pre-defined
+            &0x55C4u16.to_be_bytes(),
+            &[0; 2],
+        ]
+        .concat(),
+    );
+    let hdlr = full_box(b"hdlr", 0, 0, &[&[0; 4][..], b"soun", &[0; 12], b"SoundHandler\0"].concat());
+
+    let smhd = full_box(b"smhd", 0, 0, &[0; 4]);
+    let dref = full_box(
+        b"dref",
+        0,
+        0,
+        // One entry: the data is in this file
+        &[&1u32.to_be_bytes()[..], &full_box(b"url ", 0, 0x01, &[])].concat(),
+    );
+    let dinf = mp4_box(b"dinf", &dref);
+
+    let stsd = full_box(
+        b"stsd",
+        0,
+        0,
+        &[&1u32.to_be_bytes()[..], &mp4a(config, sample_rate, max_frame, avg_bitrate)].concat(),
+    );
+    let stts = full_box(
+        b"stts",
+        0,
+        0,
+        &[&1u32.to_be_bytes()[..], &frame_count.to_be_bytes(), &SAMPLES_PER_FRAME.to_be_bytes()].concat(),
+    );
+    let stsc = full_box(
+        b"stsc",
+        0,
+        0,
+        &[&1u32.to_be_bytes()[..], &1u32.to_be_bytes(), &frame_count.to_be_bytes(), &1u32.to_be_bytes()].concat(),
+    );
+    let mut stsz = Vec::with_capacity(8 + sizes.len() * 4);
+    stsz.extend_from_slice(&0u32.to_be_bytes());
+    stsz.extend_from_slice(&frame_count.to_be_bytes());
+    for size in sizes {
+        stsz.extend_from_slice(&size.to_be_bytes());
+    }
+    let stsz = full_box(b"stsz", 0, 0, &stsz);
+    let stco = full_box(b"stco", 0, 0, &[&1u32.to_be_bytes()[..], &data_offset.to_be_bytes()].concat());
+    let stbl = mp4_box(b"stbl", &[stsd, stts, stsc, stsz, stco].concat());
+
+    let minf = mp4_box(b"minf", &[smhd, dinf, stbl].concat());
+    let mdia = mp4_box(b"mdia", &[mdhd, hdlr, minf].concat());
+    let trak = mp4_box(b"trak", &[tkhd, mdia].concat());
+
+    Ok(mp4_box(b"moov", &[mvhd, trak, udta(metadata)].concat()))
+}
+
+/// The AAC sample entry, with its decoder configuration
+fn mp4a(config: &AdtsConfig, sample_rate: u32, max_frame: u32, avg_bitrate: u32) -> Vec<u8> {
+    let max_bitrate = (u64::from(max_frame) * 8 * u64::from(sample_rate) / u64::from(SAMPLES_PER_FRAME)) as u32;
+    // AudioSpecificConfig: object type, frequency index, channels
+    let specific = (u16::from(config.profile + 1) << 11)
+        | (u16::from(config.frequency_index) << 7)
+        | (u16::from(config.channels) << 3);
+    let decoder_specific = descriptor(0x05, &specific.to_be_bytes());
+    let decoder_config = descriptor(
+        0x04,
+        &[
+            // MPEG-4 audio, audio stream
+            &[0x40, 0x15][..],
+            &max_frame.to_be_bytes()[1..],
+            &max_bitrate.to_be_bytes(),
+            &avg_bitrate.to_be_bytes(),
+            &decoder_specific,
+        ]
+        .concat(),
+    );
+    let sl_config = descriptor(0x06, &[0x02]);
+    let es = descriptor(0x03, &[&[0, 1, 0][..], &decoder_config, &sl_config].concat());
+    let esds = full_box(b"esds", 0, 0, &es);
+
+    mp4_box(
+        b"mp4a",
+        &[
+            // Reserved, data reference index
+            &[0; 6][..],
+            &1u16.to_be_bytes(),
+            // Version, revision, vendor
+            &[0; 8],
+            &u16::from(config.channels).to_be_bytes(),
+            // Sample size, pre-defined, reserved
+            &16u16.to_be_bytes(),
+            &[0; 4],
+            &(sample_rate.min(0xFFFF) << 16).to_be_bytes(),
+            &esds,
+        ]
+        .concat(),
+    )
+}
+
+/// iTunes-style tags
+fn udta(metadata: &Metadata) -> Vec<u8> {
+    let mut items = vec![
+        tag_item(b"\xA9nam", 1, metadata.title.as_bytes()),
+        tag_item(b"\xA9ART", 1, metadata.artist.as_bytes()),
+    ];
+    if let Some(album) = metadata.album {
+        items.push(tag_item(b"\xA9alb", 1, album.as_bytes()));
+    }
+    if let Some(artwork) = metadata.artwork {
+        let kind = if artwork.starts_with(b"\x89PNG") { 14 } else { 13 };
+        items.push(tag_item(b"covr", kind, artwork));
+    }
+
+    let hdlr = full_box(b"hdlr", 0, 0, &[&[0; 4][..], b"mdir", b"appl", &[0; 8], b"\0"].concat());
+    let ilst = mp4_box(b"ilst", &items.concat());
+    let meta = full_box(b"meta", 0, 0, &[hdlr, ilst].concat());
+    mp4_box(b"udta", &meta)
+}
+
+/// One `ilst` entry holding a `data` box of the given type
+/// (1 = UTF-8, 13 = JPEG, 14 = PNG)
+fn tag_item(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+    let data = mp4_box(b"data", &[&data_type.to_be_bytes()[..], &[0; 4], value].concat());
+    mp4_box(kind, &data)
+}
+
+/// Identity transformation matrix for `mvhd` and `tkhd`
+fn matrix() -> Vec<u8> {
+    [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000]
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect()
+}
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let size = (8 + payload.len()) as u32;
+    [&size.to_be_bytes()[..], kind, payload].concat()
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let header = (u32::from(version) << 24) | (flags & 0xFF_FFFF);
+    mp4_box(kind, &[&header.to_be_bytes()[..], payload].concat())
+}
+
+/// An MPEG-4 descriptor with a one-byte length
+fn descriptor(tag: u8, body: &[u8]) -> Vec<u8> {
+    [&[tag, body.len() as u8][..], body].concat()
+}
@@ -1,11 +1,30 @@
 // SPDX-License-Identifier: MPL-2.0
 
+mod biquad;
 pub mod cache;
+mod download;
+mod equalizer;
+pub mod export;
+mod fade;
+mod fmp4;
 mod hls;
+mod loader;
+mod loudness;
+mod m4a;
+pub mod offline;
+mod output;
 mod player;
+mod position;
+mod resolver;
+mod spectrum;
 pub mod system_volume;
+mod stream_buffer;
 mod webview_player;
 mod ytdlp;
 
-pub use player::{AudioCommand, AudioEvent, AudioPlayer};
+pub use equalizer::{BAND_COUNT, BAND_FREQUENCIES};
+pub use loudness::Normalization;
+pub use player::{AudioCommand, AudioEvent, AudioPlayer, Crossfade};
+pub use resolver::{Decline, Declined, Resolved, ResolverChain, ResolverKind, StreamSource};
 pub use webview_player::open_in_browser;
+pub use ytdlp::{YtDlp, YtDlpError};
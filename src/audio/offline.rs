@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Tracks pinned for offline listening.
+//!
+//! Unlike the audio cache, nothing here is ever evicted: pinned tracks stay
+//! in `~/.local/share/cosmic-soundcloud/offline/` until they're unpinned.
+//! Next to the audio (`tracks/{id}.audio`) and artwork (`artwork/{id}`),
+//! `index.json` records the pins and the full metadata of every pinned track,
+//! so pinned albums and likes can be browsed and queued without the API.
+//!
+//! The store itself is owned by the app. The player only needs
+//! [`audio_path`] to find a pinned track's audio.
+
+use super::download::Downloader;
+use super::resolver::{ResolverChain, ResolverKind, StreamSource};
+use super::ytdlp::YtDlp;
+use crate::api::{SoundCloudClient, StreamPreference, Track};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Return the offline store directory (`~/.local/share/cosmic-soundcloud/offline/`).
+fn offline_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("cosmic-soundcloud").join("offline"))
+}
+
+fn index_path() -> Option<PathBuf> {
+    offline_dir().map(|d| d.join("index.json"))
+}
+
+fn track_path(track_id: u64) -> Option<PathBuf> {
+    offline_dir().map(|d| d.join("tracks").join(format!("{track_id}.audio")))
+}
+
+fn artwork_file(track_id: u64) -> Option<PathBuf> {
+    offline_dir().map(|d| d.join("artwork").join(track_id.to_string()))
+}
+
+/// Path of a pinned track's downloaded audio, if it has been downloaded
+pub fn audio_path(track_id: u64) -> Option<PathBuf> {
+    track_path(track_id).filter(|path| path.exists())
+}
+
+/// Something the user asked to keep available offline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Pin {
+    Track(u64),
+    /// An album or playlist, in track order
+    Album {
+        id: u64,
+        title: String,
+        track_ids: Vec<u64>,
+    },
+    /// All of the user's liked tracks, newest first
+    Likes { track_ids: Vec<u64> },
+}
+
+impl Pin {
+    fn track_ids(&self) -> &[u64] {
+        match self {
+            Self::Track(id) => std::slice::from_ref(id),
+            Self::Album { track_ids, .. } | Self::Likes { track_ids } => track_ids,
+        }
+    }
+}
+
+/// Index record of one pinned track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OfflineTrack {
+    track: Track,
+    /// Size of the downloaded audio, `None` until it has been downloaded
+    size: Option<u64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    pins: Vec<Pin>,
+    tracks: HashMap<u64, OfflineTrack>,
+}
+
+/// The pins and metadata of the offline store
+pub struct OfflineStore {
+    index: Index,
+}
+
+impl OfflineStore {
+    /// Load the index from disk. Tracks whose audio file has gone missing are
+    /// marked for download again.
+    pub fn load() -> Self {
+        let mut index: Index = index_path()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        for (track_id, entry) in &mut index.tracks {
+            if entry.size.is_some() && audio_path(*track_id).is_none() {
+                entry.size = None;
+            }
+        }
+        Self { index }
+    }
+
+    /// Whether the track has been downloaded
+    pub fn is_available(&self, track_id: u64) -> bool {
+        self.index
+            .tracks
+            .get(&track_id)
+            .is_some_and(|t| t.size.is_some())
+    }
+
+    /// Whether the track is pinned on its own (not just as part of an album or likes)
+    pub fn is_track_pinned(&self, track_id: u64) -> bool {
+        self.index.pins.contains(&Pin::Track(track_id))
+    }
+
+    pub fn is_album_pinned(&self, album_id: u64) -> bool {
+        self.index
+            .pins
+            .iter()
+            .any(|pin| matches!(pin, Pin::Album { id, .. } if *id == album_id))
+    }
+
+    pub fn are_likes_pinned(&self) -> bool {
+        self.index.pins.iter().any(|pin| matches!(pin, Pin::Likes { .. }))
+    }
+
+    pub fn pins(&self) -> &[Pin] {
+        &self.index.pins
+    }
+
+    /// Pin `tracks` as `pin`, replacing an earlier pin of the same album or of
+    /// the likes. Tracks no longer pinned afterwards are removed.
+    pub fn pin(&mut self, pin: Pin, tracks: Vec<Track>) {
+        let position = self.index.pins.iter().position(|p| match (p, &pin) {
+            (Pin::Track(a), Pin::Track(b)) => a == b,
+            (Pin::Album { id: a, .. }, Pin::Album { id: b, .. }) => a == b,
+            (Pin::Likes { .. }, Pin::Likes { .. }) => true,
+            _ => false,
+        });
+        match position {
+            Some(i) => self.index.pins[i] = pin,
+            None => self.index.pins.push(pin),
+        }
+
+        for track in tracks {
+            match self.index.tracks.get_mut(&track.id) {
+                // Newer metadata, same audio
+                Some(entry) => entry.track = track,
+                None => {
+                    self.index
+                        .tracks
+                        .insert(track.id, OfflineTrack { track, size: None });
+                }
+            }
+        }
+        self.prune();
+        self.save();
+    }
+
+    pub fn unpin_track(&mut self, track_id: u64) {
+        self.unpin(|pin| *pin == Pin::Track(track_id));
+    }
+
+    pub fn unpin_album(&mut self, album_id: u64) {
+        self.unpin(|pin| matches!(pin, Pin::Album { id, .. } if *id == album_id));
+    }
+
+    pub fn unpin_likes(&mut self) {
+        self.unpin(|pin| matches!(pin, Pin::Likes { .. }));
+    }
+
+    fn unpin(&mut self, matches: impl Fn(&Pin) -> bool) {
+        self.index.pins.retain(|pin| !matches(pin));
+        self.prune();
+        self.save();
+    }
+
+    /// Record a finished download. Returns `false` if the track was unpinned
+    /// while it downloaded, in which case its files are removed again.
+    pub fn mark_downloaded(&mut self, track_id: u64, size: u64) -> bool {
+        let Some(entry) = self.index.tracks.get_mut(&track_id) else {
+            remove_files(track_id);
+            return false;
+        };
+        entry.size = Some(size);
+        self.save();
+        true
+    }
+
+    /// Pinned tracks still to download, in pin order
+    pub fn pending(&self) -> Vec<Track> {
+        self.ordered()
+            .filter(|t| t.size.is_none())
+            .map(|t| t.track.clone())
+            .collect()
+    }
+
+    /// Downloaded tracks, in pin order
+    pub fn tracks(&self) -> Vec<Track> {
+        self.ordered()
+            .filter(|t| t.size.is_some())
+            .map(|t| t.track.clone())
+            .collect()
+    }
+
+    /// Number of pinned tracks, downloaded or not
+    pub fn len(&self) -> usize {
+        self.index.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.tracks.is_empty()
+    }
+
+    /// Total size of the downloaded audio in bytes
+    pub fn size(&self) -> u64 {
+        self.index.tracks.values().filter_map(|t| t.size).sum()
+    }
+
+    /// The tracks of a pinned album, once all of them have been downloaded
+    pub fn album_tracks(&self, album_id: u64) -> Option<Vec<Track>> {
+        let track_ids = self.index.pins.iter().find_map(|pin| match pin {
+            Pin::Album { id, track_ids, .. } if *id == album_id => Some(track_ids),
+            _ => None,
+        })?;
+        track_ids
+            .iter()
+            .map(|id| {
+                self.index
+                    .tracks
+                    .get(id)
+                    .filter(|t| t.size.is_some())
+                    .map(|t| t.track.clone())
+            })
+            .collect()
+    }
+
+    /// Title of a pinned album the track is on
+    pub fn album_title(&self, track_id: u64) -> Option<String> {
+        self.index.pins.iter().find_map(|pin| match pin {
+            Pin::Album { title, track_ids, .. } if track_ids.contains(&track_id) => Some(title.clone()),
+            _ => None,
+        })
+    }
+
+    /// Artwork of downloaded tracks: `(artwork_url, file)` pairs for showing
+    /// it without the network
+    pub fn artwork(&self) -> Vec<(String, PathBuf)> {
+        self.index
+            .tracks
+            .values()
+            .filter_map(|t| {
+                let url = t.track.artwork_url.clone()?;
+                let path = artwork_file(t.track.id).filter(|path| path.exists())?;
+                Some((url, path))
+            })
+            .collect()
+    }
+
+    /// Remove every pin and downloaded file
+    pub fn clear(&mut self) {
+        self.index = Index::default();
+        if let Some(dir) = offline_dir() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    /// Tracks referenced by the pins, each once, in pin order
+    fn ordered(&self) -> impl Iterator<Item = &OfflineTrack> {
+        let mut seen = HashSet::new();
+        self.index
+            .pins
+            .iter()
+            .flat_map(Pin::track_ids)
+            .filter(move |id| seen.insert(**id))
+            .filter_map(|id| self.index.tracks.get(id))
+    }
+
+    /// Drop tracks no pin refers to any more, along with their files
+    fn prune(&mut self) {
+        let pinned: HashSet<u64> = self
+            .index
+            .pins
+            .iter()
+            .flat_map(Pin::track_ids)
+            .copied()
+            .collect();
+        self.index.tracks.retain(|track_id, entry| {
+            let keep = pinned.contains(track_id);
+            if !keep {
+                if entry.size.is_some() {
+                    eprintln!("[offline] Removing track {track_id}");
+                }
+                remove_files(*track_id);
+            }
+            keep
+        });
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.write_index() {
+            eprintln!("[offline] Failed to save index: {e}");
+        }
+    }
+
+    fn write_index(&self) -> Result<(), String> {
+        let path = index_path().ok_or("No data directory available")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create offline dir: {e}"))?;
+        }
+        let data = serde_json::to_vec(&self.index).map_err(|e| e.to_string())?;
+        std::fs::write(path, data).map_err(|e| format!("Failed to write offline index: {e}"))
+    }
+}
+
+fn remove_files(track_id: u64) {
+    for path in [track_path(track_id), artwork_file(track_id)].into_iter().flatten() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Copy `data` to `path` through a temporary file, so a partly written file
+/// is never mistaken for a complete one. Returns the number of bytes written.
+fn write_file(path: &Path, data: &mut impl Read) -> Result<u64, String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create offline dir: {e}"))?;
+    }
+    let partial = path.with_extension("part");
+    let size = File::create(&partial)
+        .and_then(|mut file| std::io::copy(data, &mut file))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&partial);
+            format!("Failed to write {}: {e}", partial.display())
+        })?;
+    std::fs::rename(&partial, path).map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+    Ok(size)
+}
+
+/// Download a pinned track's audio and artwork into the store. The audio comes
+/// from the first of the network `sources` that has it, in their configured
+/// order. Returns the size of the audio in bytes, to pass to
+/// [`OfflineStore::mark_downloaded`].
+pub async fn download_track(
+    client: SoundCloudClient,
+    track: Track,
+    sources: Vec<ResolverKind>,
+    preference: StreamPreference,
+    ytdlp: YtDlp,
+) -> Result<u64, String> {
+    let sources: Vec<ResolverKind> = sources.into_iter().filter(|kind| !kind.is_local()).collect();
+    let chain = ResolverChain::new(&sources, Some(client), preference, ytdlp);
+    let resolved = chain.resolve(&track, 0).await.map_err(|declined| {
+        let reasons: Vec<String> = declined.iter().map(ToString::to_string).collect();
+        format!("No stream available ({})", reasons.join("; "))
+    })?;
+    let StreamSource::Stream { url, .. } = resolved.source else {
+        return Err("No stream available".into());
+    };
+    let mut audio = Downloader::new(reqwest::Client::new(), preference)
+        .download(&url, |_, _| {})
+        .await
+        .ok_or("Failed to download audio")?;
+
+    let path = track_path(track.id).ok_or("No data directory available")?;
+    let size = write_file(&path, &mut audio)?;
+
+    // Artwork is nice to have, a track without it still plays
+    if let Some(artwork_url) = &track.artwork_url
+        && let Some(path) = artwork_file(track.id)
+    {
+        match reqwest::get(artwork_url)
+            .await
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(response) => match response.bytes().await {
+                Ok(bytes) => {
+                    if let Err(e) = write_file(&path, &mut &bytes[..]) {
+                        eprintln!("[offline] {e}");
+                    }
+                }
+                Err(e) => eprintln!("[offline] Failed to download artwork: {e}"),
+            },
+            Err(e) => eprintln!("[offline] Failed to download artwork: {e}"),
+        }
+    }
+
+    Ok(size)
+}
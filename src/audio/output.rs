@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Audio output device handling.
+//!
+//! Sinks aren't played on the device directly. Each sink's queue is shared
+//! behind a [`Route`], and a [`Relay`] source plays it on the current output
+//! stream. Switching devices detaches the relays from the old stream and
+//! starts new ones on the new stream, so the sources themselves - and with
+//! them the playback position - carry over untouched.
+
+use parking_lot::Mutex;
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::queue::SourcesQueueOutput;
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Device checks a device may be missing from before it counts as gone.
+/// ALSA devices that are busy drop out of the enumeration now and then.
+const MISSING_CHECKS: u32 = 3;
+
+/// Names of the available output devices
+pub fn list_devices() -> Vec<String> {
+    match rodio::cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            eprintln!("[output] Failed to list devices: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Open a stream on the named device, or the system default for `None`.
+/// Returns the stream, its handle and the name of the device it opened.
+fn open_stream(device: Option<&str>) -> Result<(OutputStream, OutputStreamHandle, String), String> {
+    let host = rodio::cpal::default_host();
+    let device = match device {
+        Some(name) => host
+            .output_devices()
+            .map_err(|e| format!("Failed to list devices: {e}"))?
+            .find(|d| d.name().is_ok_and(|n| n == name))
+            .ok_or_else(|| format!("Output device '{name}' not found"))?,
+        None => host
+            .default_output_device()
+            .ok_or("No default output device")?,
+    };
+    let name = device.name().unwrap_or_default();
+    let (stream, handle) = OutputStream::try_from_device(&device)
+        .map_err(|e| format!("Failed to open output device '{name}': {e}"))?;
+    Ok((stream, handle, name))
+}
+
+struct RouteShared {
+    queue: Mutex<SourcesQueueOutput<f32>>,
+    /// Bumped when the route moves to another stream; older relays end
+    generation: AtomicU32,
+    /// The sink's queue has ended, so the route can be dropped
+    ended: AtomicBool,
+}
+
+/// A sink's queue, playable on whichever stream is current
+#[derive(Clone)]
+struct Route(Arc<RouteShared>);
+
+impl Route {
+    /// Play the queue on `handle`, detaching it from any previous stream
+    fn attach(&self, handle: &OutputStreamHandle) -> Result<(), String> {
+        let generation = self.0.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        handle
+            .play_raw(Relay {
+                route: self.clone(),
+                generation,
+            })
+            .map_err(|e| format!("Failed to play on output device: {e}"))
+    }
+}
+
+/// Source playing a route's queue on one stream
+struct Relay {
+    route: Route,
+    generation: u32,
+}
+
+impl Relay {
+    fn detached(&self) -> bool {
+        self.route.0.generation.load(Ordering::Acquire) != self.generation
+    }
+}
+
+impl Iterator for Relay {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.detached() {
+            return None;
+        }
+        let sample = self.route.0.queue.lock().next();
+        if sample.is_none() {
+            self.route.0.ended.store(true, Ordering::Release);
+        }
+        sample
+    }
+}
+
+impl Source for Relay {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.detached() {
+            return Some(0);
+        }
+        self.route.0.queue.lock().current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.route.0.queue.lock().channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.route.0.queue.lock().sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The output device the player's sinks play on
+pub struct Output {
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    /// Name of the device the stream is open on
+    device: String,
+    /// Device chosen by the user, `None` for the system default
+    preferred: Option<String>,
+    routes: Vec<Route>,
+    /// Devices seen recently, in the order they turned up, with the number
+    /// of checks each has been missing from in a row
+    devices: Vec<(String, u32)>,
+}
+
+impl Output {
+    /// Open the preferred device, falling back to the system default
+    pub fn open(preferred: Option<String>) -> Result<Self, String> {
+        let (stream, handle, device) = open_stream(preferred.as_deref())
+            .or_else(|e| {
+                eprintln!("[output] {e}, using the default device");
+                open_stream(None)
+            })?;
+        eprintln!("[output] Playing on '{device}'");
+        Ok(Self {
+            _stream: stream,
+            handle,
+            device,
+            preferred,
+            routes: Vec::new(),
+            devices: Vec::new(),
+        })
+    }
+
+    /// Name of the device currently playing
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// Device chosen by the user, `None` for the system default
+    pub fn preferred(&self) -> Option<&str> {
+        self.preferred.as_deref()
+    }
+
+    /// Devices that are available, or were until a moment ago
+    pub fn devices(&self) -> Vec<String> {
+        self.devices.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Update the devices from a fresh [`list_devices`]. A device stays listed
+    /// until it's been missing for several checks in a row, so one that drops
+    /// out of a single enumeration neither flickers nor loses playback.
+    /// Returns whether the list changed.
+    pub fn update_devices(&mut self, listed: Vec<String>) -> bool {
+        if listed.is_empty() {
+            // Enumeration failed, which says nothing about the devices
+            return false;
+        }
+        let before = self.devices.len();
+        for (name, missing) in &mut self.devices {
+            *missing = if listed.contains(name) { 0 } else { *missing + 1 };
+        }
+        self.devices.retain(|(_, missing)| *missing < MISSING_CHECKS);
+        let mut changed = self.devices.len() != before;
+        for name in listed {
+            if !self.devices.iter().any(|(known, _)| *known == name) {
+                self.devices.push((name, 0));
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Create a sink playing on the current device
+    pub fn new_sink(&mut self) -> Result<Sink, String> {
+        self.routes.retain(|route| !route.0.ended.load(Ordering::Acquire));
+
+        let (sink, queue) = Sink::new_idle();
+        let route = Route(Arc::new(RouteShared {
+            queue: Mutex::new(queue),
+            generation: AtomicU32::new(0),
+            ended: AtomicBool::new(false),
+        }));
+        route.attach(&self.handle)?;
+        self.routes.push(route);
+        Ok(sink)
+    }
+
+    /// Make `device` the preferred device and move playback to it
+    pub fn set_preferred(&mut self, device: Option<String>) -> Result<(), String> {
+        self.preferred = device;
+        self.switch(self.preferred.clone().as_deref())
+    }
+
+    /// Move every sink to another device, without interrupting its sources
+    fn switch(&mut self, device: Option<&str>) -> Result<(), String> {
+        let (stream, handle, name) = open_stream(device)?;
+        self.routes.retain(|route| !route.0.ended.load(Ordering::Acquire));
+        for route in &self.routes {
+            route.attach(&handle)?;
+        }
+        eprintln!("[output] Switched from '{}' to '{name}'", self.device);
+        self._stream = stream;
+        self.handle = handle;
+        self.device = name;
+        Ok(())
+    }
+
+    /// React to devices being plugged in or removed, as seen by
+    /// `update_devices`: fall back to the default device when the current one
+    /// disappears, and return to the preferred one when it comes back.
+    /// Returns whether the device changed.
+    pub fn check_devices(&mut self) -> bool {
+        let available = self.devices();
+        if available.is_empty() {
+            // Nothing has been listed yet, or there's nothing to switch to
+            return false;
+        }
+
+        let target = if let Some(preferred) = &self.preferred
+            && *preferred != self.device
+            && available.contains(preferred)
+        {
+            Some(preferred.clone())
+        } else if !available.contains(&self.device) {
+            eprintln!("[output] Device '{}' disappeared", self.device);
+            None
+        } else {
+            return false;
+        };
+
+        match self.switch(target.as_deref()) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("[output] {e}");
+                false
+            }
+        }
+    }
+}
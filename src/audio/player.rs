@@ -1,36 +1,130 @@
 // SPDX-License-Identifier: MPL-2.0
 
-use super::{cache, hls, ytdlp};
+use super::equalizer::{BAND_COUNT, Equalizer, EqualizerControl};
+use super::fade::{Fade, FadeControl};
+use super::loudness::{self, LoudnessIndex, Measure, Normalization, TrackGain};
+use super::output::{self, Output};
+use super::position::{Counted, PositionCounter};
+use super::stream_buffer::StreamBuffer;
+use super::cache::{self, AudioCache};
+use super::download::Downloader;
+use super::loader::{Loaded, Loader};
+use super::offline;
+use super::resolver::StreamSource;
+use super::spectrum::{Spectrum, SpectrumControl};
+use crate::api::{StreamFormat, StreamPreference, Transcoding, TranscodingFormat};
 use reqwest::Client;
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
-use std::io::Cursor;
-use stream_download::storage::temp::TempStorageProvider;
-use stream_download::{Settings, StreamDownload};
+use rodio::source::EmptyCallback;
+use rodio::{Decoder, Sink, Source};
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How often to look for output devices being plugged in or removed
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(3);
+
+/// How quickly the volume comes back when a fade-out is cancelled
+const FADE_OUT_CANCEL: Duration = Duration::from_millis(500);
 
 /// Commands sent to the audio player thread
 #[derive(Debug, Clone)]
 pub enum AudioCommand {
-    /// Play audio from a stream URL, with optional track ID for cache lookup
+    /// Play a track from where the stream resolvers found it. The track ID
+    /// keys the cache; `permalink_url` goes with `AudioEvent::DrmProtected`.
     Play {
         track_id: Option<u64>,
-        stream_url: String,
+        source: StreamSource,
         permalink_url: Option<String>,
     },
-    /// Preload audio data for a track into the disk cache without playing
-    Preload {
+    /// Preload audio data for a track into the disk cache without playing.
+    /// Completes right away for tracks already on disk.
+    Preload { track_id: u64, source: StreamSource },
+    /// Append a preloaded track to the current one so it plays without a gap,
+    /// or overlaps the end of the current one when `crossfade` is set
+    Enqueue {
         track_id: u64,
-        stream_url: String,
-        permalink_url: Option<String>,
+        crossfade: Option<Crossfade>,
     },
     /// Pause playback
     Pause,
+    /// Fade the volume to zero over this many seconds, then pause. Pausing,
+    /// resuming or playing another track cancels the fade, and playback
+    /// resumes at full volume.
+    FadeOutAndPause(f32),
     /// Resume playback
     Resume,
     /// Stop playback completely
     Stop,
     /// Set volume (0.0 to 1.0)
     SetVolume(f32),
+    /// Seek to a position in the current track (seconds)
+    Seek(f32),
+    /// Choose how tracks are loudness normalized
+    SetNormalization(Normalization),
+    /// Set the equalizer band gains (dB), applied to the playing track live
+    SetEqualizer([f32; BAND_COUNT]),
+    /// Start or stop sending `AudioEvent::Spectrum` for a visualizer
+    SetVisualizer(bool),
+    /// Choose which variant of a multi-variant HLS stream to play
+    SetStreamPreference(StreamPreference),
+    /// Set the audio cache budget in bytes
+    SetCacheBudget(u64),
+    /// Remove every cached track
+    ClearCache,
+    /// Report the available output devices with `AudioEvent::OutputDevices`
+    ListOutputDevices,
+    /// Move playback to the named output device (`None` for the system default)
+    SetOutputDevice(Option<String>),
+}
+
+/// How to overlap an enqueued track with the end of the current one
+#[derive(Debug, Clone, Copy)]
+pub struct Crossfade {
+    /// Length of the overlap in seconds
+    pub duration: f32,
+    /// Duration of the current track in seconds, used to schedule the fade
+    pub track_duration: f32,
+}
+
+/// A decoded next track waiting for its crossfade to start
+struct PendingCrossfade {
+    track_id: u64,
+    source: Decoder<File>,
+    /// Position in the current track (seconds) at which the fade starts
+    start_at: f32,
+    duration: Duration,
+}
+
+/// Audio downloaded by a preload task
+struct Preloaded {
+    track_id: u64,
+    transcoding: Option<Transcoding>,
+    format: StreamFormat,
+    /// Size of the audio written to the cache, and the downloaded temporary
+    /// file rewound to the start
+    written: Result<(u64, File), String>,
+}
+
+/// A streamed track written to the cache directory, waiting for the index
+struct Cached {
+    track_id: u64,
+    transcoding: Option<TranscodingFormat>,
+    format: StreamFormat,
+    written: Result<u64, String>,
+}
+
+/// A decoded source wrapped in the player's DSP chain
+type DspChain<S> = Counted<Spectrum<Fade<Equalizer<Measure<S>>>>>;
+
+/// Handles to a source appended to a sink
+struct SourceControls {
+    fade: FadeControl,
+    position: PositionCounter,
+    /// Decoded length of the source, if known
+    duration: Option<Duration>,
 }
 
 /// Events emitted by the audio player
@@ -54,24 +148,88 @@ pub enum AudioEvent {
     DrmProtected { drm_type: String, track_url: String },
     /// Playback position update (elapsed seconds)
     Position(f32),
+    /// Decoded duration of the current track (seconds)
+    Duration(f32),
+    /// Available output devices and the one currently playing
+    OutputDevices { devices: Vec<String>, current: String },
     /// Preloading complete for the given track ID
     PreloadComplete(u64),
+    /// Playback moved on to the enqueued track with this ID without a gap
+    TrackChanged(u64),
+    /// Codec and quality of the track that started playing
+    Format(StreamFormat),
+    /// HLS segments downloaded so far for a playing or preloading track
+    DownloadProgress {
+        track_id: Option<u64>,
+        downloaded: usize,
+        total: usize,
+    },
+    /// Spectrum of what's playing, as bar levels from 0.0 to 1.0, lowest
+    /// frequency first. Sent about 30 times a second while the visualizer is on.
+    Spectrum(Vec<f32>),
 }
 
 /// Audio player that runs in a background thread
 pub struct AudioPlayer {
-    _stream: OutputStream,
-    stream_handle: OutputStreamHandle,
-    sink: Option<Sink>,
+    output: Output,
+    sink: Option<Arc<Sink>>,
+    /// Gain and position of the source playing in `sink`
+    controls: Option<SourceControls>,
+    /// Track playing in `sink`, if known
+    current_track_id: Option<u64>,
+    normalization: Normalization,
+    loudness: LoudnessIndex,
+    /// Measurements reported by sources that played through
+    loudness_tx: mpsc::UnboundedSender<(u64, TrackGain)>,
+    /// Equalizer curve shared by every source
+    equalizer: EqualizerControl,
+    /// Spectrum analysis shared by every source
+    spectrum: SpectrumControl,
+    stream_preference: StreamPreference,
+    /// Downloaded tracks, kept across plays
+    cache: AudioCache,
+    /// Transcoding of the track being played, for the cache index
+    current_transcoding: Option<Transcoding>,
+    /// Format of the stream being played, as resolved
+    current_format: StreamFormat,
     volume: f32,
     event_tx: mpsc::Sender<AudioEvent>,
     http_client: Client,
-    /// Time when playback started (for position tracking)
-    playback_start: Option<std::time::Instant>,
-    /// Accumulated time from before pause (for accurate position after pause/resume)
-    accumulated_time: f32,
     /// Whether currently paused
     is_paused: bool,
+    /// Buffer being filled by an in-progress HLS download
+    stream_buffer: Option<Arc<StreamBuffer>>,
+    /// Background task downloading HLS segments into `stream_buffer`
+    hls_download: Option<JoinHandle<()>>,
+    /// Whether playback is waiting for the HLS download to catch up
+    is_stalled: bool,
+    /// Task opening the stream of the track to play
+    load_task: Option<JoinHandle<()>>,
+    /// Bumped whenever a load is superseded, so stale results are dropped
+    load_generation: u64,
+    load_tx: mpsc::UnboundedSender<(u64, Result<Loaded, AudioEvent>)>,
+    /// Task downloading the next track into the cache
+    preload_task: Option<JoinHandle<()>>,
+    preload_tx: mpsc::UnboundedSender<Preloaded>,
+    cached_tx: mpsc::UnboundedSender<Cached>,
+    /// Bumped by every seek and whenever the sink is replaced, so only the
+    /// latest seek reports back
+    seek_generation: u64,
+    /// Whether a seek is waiting on the audio thread
+    seeking: bool,
+    seek_tx: mpsc::UnboundedSender<(u64, f32, Result<(), String>)>,
+    /// Track appended to the current sink, waiting for the current one to end
+    queued_track: Option<(u64, SourceControls)>,
+    /// Next track to fade in once the current one nears its end
+    pending_crossfade: Option<PendingCrossfade>,
+    /// Outgoing sink that is fading out, and when its fade ends
+    fading_sink: Option<(Arc<Sink>, Instant)>,
+    /// Signalled from the audio thread when playback crosses into the queued track
+    boundary_tx: mpsc::UnboundedSender<u64>,
+    /// When a fade-out requested with `FadeOutAndPause` ends and playback pauses
+    fade_out_until: Option<tokio::time::Instant>,
+    /// Paused by a finished fade-out, with the gain still at zero until resumed
+    faded_out: bool,
 }
 
 impl AudioPlayer {
@@ -80,6 +238,13 @@ impl AudioPlayer {
     pub fn spawn() -> (mpsc::Sender<AudioCommand>, mpsc::Receiver<AudioEvent>) {
         let (cmd_tx, mut cmd_rx) = mpsc::channel::<AudioCommand>(32);
         let (evt_tx, evt_rx) = mpsc::channel::<AudioEvent>(32);
+        let (boundary_tx, mut boundary_rx) = mpsc::unbounded_channel::<u64>();
+        let (loudness_tx, mut loudness_rx) = mpsc::unbounded_channel::<(u64, TrackGain)>();
+        let (spectrum_tx, mut spectrum_rx) = mpsc::channel::<Vec<f32>>(2);
+        let (load_tx, mut load_rx) = mpsc::unbounded_channel();
+        let (preload_tx, mut preload_rx) = mpsc::unbounded_channel();
+        let (cached_tx, mut cached_rx) = mpsc::unbounded_channel();
+        let (seek_tx, mut seek_rx) = mpsc::unbounded_channel();
 
         std::thread::spawn(move || {
             // Set up panic handler for this thread
@@ -94,8 +259,8 @@ impl AudioPlayer {
             }));
 
             // Create the audio output stream - must be kept alive
-            let (stream, stream_handle) = match OutputStream::try_default() {
-                Ok(s) => s,
+            let output = match Output::open(None) {
+                Ok(output) => output,
                 Err(e) => {
                     let _ = evt_tx.blocking_send(AudioEvent::Error(format!(
                         "Failed to create audio output: {e}"
@@ -105,45 +270,84 @@ impl AudioPlayer {
             };
 
             let mut player = AudioPlayer {
-                _stream: stream,
-                stream_handle,
+                output,
                 sink: None,
+                controls: None,
+                current_track_id: None,
+                normalization: Normalization::Off,
+                loudness: LoudnessIndex::load(),
+                loudness_tx,
+                equalizer: EqualizerControl::default(),
+                spectrum: SpectrumControl::new(spectrum_tx),
+                stream_preference: StreamPreference::default(),
+                cache: AudioCache::load(cache::DEFAULT_BUDGET),
+                current_transcoding: None,
+                current_format: StreamFormat::default(),
                 volume: 0.8,
                 event_tx: evt_tx.clone(),
                 http_client: Client::new(),
-                playback_start: None,
-                accumulated_time: 0.0,
                 is_paused: false,
+                stream_buffer: None,
+                hls_download: None,
+                is_stalled: false,
+                load_task: None,
+                load_generation: 0,
+                load_tx,
+                preload_task: None,
+                preload_tx,
+                cached_tx,
+                seek_generation: 0,
+                seeking: false,
+                seek_tx,
+                queued_track: None,
+                pending_crossfade: None,
+                fading_sink: None,
+                boundary_tx,
+                fade_out_until: None,
+                faded_out: false,
             };
 
             // Signal ready
             let _ = evt_tx.blocking_send(AudioEvent::Ready);
 
-            // Create a tokio runtime for this thread
-            let rt = tokio::runtime::Builder::new_current_thread()
+            // Create a tokio runtime for the player. Commands are handled on
+            // this thread while downloads run on the workers, so they keep
+            // filling buffers a seek may be waiting on.
+            let rt = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(2)
                 .enable_all()
                 .build()
                 .unwrap();
 
             // Process commands and monitor playback completion
             rt.block_on(async {
-                let mut check_interval = tokio::time::interval(std::time::Duration::from_millis(500));
+                let mut check_interval = tokio::time::interval(Duration::from_millis(500));
+                let mut device_interval = tokio::time::interval(DEVICE_CHECK_INTERVAL);
                 let mut was_playing = false;
 
                 loop {
+                    let crossfade_at = player.crossfade_deadline();
+                    let fade_out_at = player.fade_out_until;
+
                     tokio::select! {
                         cmd = cmd_rx.recv() => {
                             match cmd {
-                                Some(AudioCommand::Play { track_id, stream_url, permalink_url }) => {
-                                    player.play_url(track_id, &stream_url, permalink_url.as_deref()).await;
+                                Some(AudioCommand::Play { track_id, source, permalink_url }) => {
+                                    player.play(track_id, source, permalink_url).await;
                                     was_playing = true;
                                 }
-                                Some(AudioCommand::Preload { track_id, stream_url, permalink_url }) => {
-                                    player.preload(track_id, &stream_url, permalink_url.as_deref()).await;
+                                Some(AudioCommand::Preload { track_id, source }) => {
+                                    player.preload(track_id, source).await;
+                                }
+                                Some(AudioCommand::Enqueue { track_id, crossfade }) => {
+                                    player.enqueue(track_id, crossfade);
                                 }
                                 Some(AudioCommand::Pause) => {
                                     player.pause().await;
                                 }
+                                Some(AudioCommand::FadeOutAndPause(secs)) => {
+                                    player.fade_out(secs).await;
+                                }
                                 Some(AudioCommand::Resume) => {
                                     player.resume().await;
                                 }
@@ -154,152 +358,261 @@ impl AudioPlayer {
                                 Some(AudioCommand::SetVolume(vol)) => {
                                     player.set_volume(vol);
                                 }
+                                Some(AudioCommand::SetNormalization(normalization)) => {
+                                    player.set_normalization(normalization);
+                                }
+                                Some(AudioCommand::SetEqualizer(gains)) => {
+                                    player.equalizer.set_gains(gains);
+                                }
+                                Some(AudioCommand::SetVisualizer(enabled)) => {
+                                    player.spectrum.set_enabled(enabled);
+                                }
+                                Some(AudioCommand::SetStreamPreference(preference)) => {
+                                    player.stream_preference = preference;
+                                }
+                                Some(AudioCommand::SetCacheBudget(budget)) => {
+                                    player.cache.set_budget(budget);
+                                }
+                                Some(AudioCommand::ClearCache) => {
+                                    // A preload finishing later would cache its track again
+                                    if let Some(task) = player.preload_task.take() {
+                                        task.abort();
+                                    }
+                                    player.cache.clear();
+                                }
+                                Some(AudioCommand::ListOutputDevices) => {
+                                    player.report_output_devices().await;
+                                }
+                                Some(AudioCommand::SetOutputDevice(device)) => {
+                                    if device.as_deref() != player.output.preferred() {
+                                        if let Err(e) = player.output.set_preferred(device) {
+                                            eprintln!("[output] {e}");
+                                            let _ = player.event_tx.send(AudioEvent::Error(e)).await;
+                                        }
+                                        player.report_output_devices().await;
+                                    }
+                                }
+                                Some(AudioCommand::Seek(position)) => {
+                                    player.seek(position);
+                                }
                                 None => break, // Channel closed
                             }
                         }
+                        Some((generation, result)) = load_rx.recv() => {
+                            player.finish_load(generation, result).await;
+                        }
+                        Some(preloaded) = preload_rx.recv() => {
+                            player.finish_preload(preloaded).await;
+                        }
+                        Some(cached) = cached_rx.recv() => {
+                            player.finish_caching(cached);
+                        }
+                        Some((generation, position, result)) = seek_rx.recv() => {
+                            player.finish_seek(generation, position, result).await;
+                        }
+                        Some(track_id) = boundary_rx.recv() => {
+                            player.track_boundary(track_id).await;
+                        }
+                        Some((track_id, gain)) = loudness_rx.recv() => {
+                            eprintln!("[loudness] Track {track_id} measured, gain {:+.1} dB", gain.gain_db);
+                            player.loudness.insert(track_id, gain);
+                        }
+                        Some(bars) = spectrum_rx.recv() => {
+                            // Frames keep coming, so drop them while the app is busy
+                            let _ = player.event_tx.try_send(AudioEvent::Spectrum(bars));
+                        }
+                        _ = device_interval.tick() => {
+                            // Enumerating devices can be slow, keep it off the runtime thread
+                            if let Ok(listed) = tokio::task::spawn_blocking(output::list_devices).await {
+                                let changed = player.output.update_devices(listed);
+                                if player.output.check_devices() || changed {
+                                    let _ = player.event_tx.send(AudioEvent::OutputDevices {
+                                        devices: player.output.devices(),
+                                        current: player.output.device().to_string(),
+                                    }).await;
+                                }
+                            }
+                        }
+                        () = sleep_until(crossfade_at) => {
+                            player.start_crossfade().await;
+                        }
+                        () = sleep_until(fade_out_at) => {
+                            player.finish_fade_out().await;
+                        }
                         _ = check_interval.tick() => {
                             // Check if playback finished
                             if was_playing
                                 && let Some(sink) = &player.sink
                                 && sink.empty()
                             {
-                                eprintln!("Track finished playing");
-                                was_playing = false;
-                                player.playback_start = None;
-                                player.accumulated_time = 0.0;
-                                let _ = player.event_tx.send(AudioEvent::Finished).await;
+                                if player.pending_crossfade.is_some() {
+                                    // Ended before the fade was due - start the next track now
+                                    player.start_crossfade().await;
+                                } else {
+                                    eprintln!("Track finished playing");
+                                    was_playing = false;
+                                    let _ = player.event_tx.send(AudioEvent::Finished).await;
+                                }
+                            }
+
+                            // Drop the outgoing sink once its fade-out has finished
+                            if player
+                                .fading_sink
+                                .as_ref()
+                                .is_some_and(|(sink, ends)| sink.empty() || Instant::now() >= *ends)
+                            {
+                                player.fading_sink = None;
                             }
 
-                            // Emit position update if playing
-                            if was_playing && !player.is_paused {
-                                if let Some(start) = player.playback_start {
-                                    let elapsed = start.elapsed().as_secs_f32() + player.accumulated_time;
+                            // Keep a fully streamed track for the next time it plays
+                            if player.hls_download.as_ref().is_some_and(|task| task.is_finished()) {
+                                player.cache_streamed_track();
+                            }
+                            player.cache.save_if_due();
+
+                            // Report buffering when playback catches up with an HLS download
+                            if was_playing
+                                && let Some(buffer) = &player.stream_buffer
+                            {
+                                let stalled = buffer.is_stalled();
+                                if stalled != player.is_stalled {
+                                    player.set_stalled(stalled).await;
+                                }
+                            }
+
+                            // Emit position update if playing, unless it's about to jump
+                            if was_playing && !player.is_paused && !player.is_stalled && !player.seeking {
+                                if let Some(elapsed) = player.position() {
                                     let _ = player.event_tx.send(AudioEvent::Position(elapsed)).await;
                                 }
                             }
                         }
                     }
                 }
+                player.cache.flush();
             });
         });
 
         (cmd_tx, evt_rx)
     }
 
-    async fn play_url(&mut self, track_id: Option<u64>, url: &str, permalink_url: Option<&str>) {
-        // Stop any existing playback
+    /// Play a track: straight away from disk if that's where it was found,
+    /// otherwise by opening the stream in a load task. The command loop keeps
+    /// running while the task works; a later `Play` or `Stop` aborts it.
+    async fn play(&mut self, track_id: Option<u64>, source: StreamSource, permalink_url: Option<String>) {
+        // Stop any existing playback or load
         self.stop().await;
+        self.current_track_id = track_id;
+        self.is_paused = false;
+        self.current_transcoding = None;
 
-        eprintln!("play_url: {}...", &url[..url.len().min(80)]);
-
-        // Check if we have cached audio data for this track
-        if let Some(id) = track_id
-            && cache::has_cached(id)
-        {
-            eprintln!("  -> Found cached audio for track {id}, playing from cache");
-            if let Some(data) = cache::read_cached(id) {
-                self.play_from_data(data).await;
-                // Clean up cache file after loading into player
-                cache::remove_cached(id);
+        let (url, transcoding, format) = match source {
+            StreamSource::Stream {
+                url,
+                transcoding,
+                format,
+            } => (url, transcoding, format),
+            StreamSource::Offline(path) => {
+                eprintln!("play: {} from the offline store", path.display());
+                match File::open(&path) {
+                    Ok(file) => {
+                        self.play_from_file(file).await;
+                        let _ = self.event_tx.send(AudioEvent::Format(StreamFormat::default())).await;
+                    }
+                    Err(e) => {
+                        let _ = self
+                            .event_tx
+                            .send(AudioEvent::Error(format!("Failed to open {}: {e}", path.display())))
+                            .await;
+                    }
+                }
                 return;
             }
-            eprintln!("  -> Cache read failed, falling back to download");
-        }
-
-        let _ = self.event_tx.send(AudioEvent::Buffering(true)).await;
-
-        // Check if this is an HLS stream (m3u8)
-        if url.contains(".m3u8") {
-            eprintln!("  -> HLS stream detected");
-            self.play_hls(url, permalink_url, false).await;
-            return;
-        }
-
-        eprintln!("  -> Progressive stream, downloading...");
-
-        // Regular progressive stream
-        let url = match url.parse::<reqwest::Url>() {
-            Ok(u) => u,
-            Err(e) => {
-                let _ = self
-                    .event_tx
-                    .send(AudioEvent::Error(format!("Invalid URL: {e}")))
-                    .await;
+            StreamSource::Cached => {
+                eprintln!("play: track {track_id:?} from the cache");
+                match track_id.and_then(|id| Some((self.cache.open(id)?, self.cache.format(id)))) {
+                    Some((file, format)) => {
+                        self.play_from_file(file).await;
+                        let _ = self.event_tx.send(AudioEvent::Format(format.unwrap_or_default())).await;
+                    }
+                    None => {
+                        let _ = self
+                            .event_tx
+                            .send(AudioEvent::Error("Track is no longer cached".into()))
+                            .await;
+                    }
+                }
                 return;
             }
         };
 
-        // Create streaming download
-        let stream = match StreamDownload::new_http(
-            url,
-            TempStorageProvider::default(),
-            Settings::default(),
-        )
-        .await
-        {
-            Ok(s) => {
-                eprintln!("  -> Stream download started");
-                s
-            }
-            Err(e) => {
-                eprintln!("  -> Stream download FAILED: {e}");
-                let _ = self
-                    .event_tx
-                    .send(AudioEvent::Error(format!("Failed to stream: {e}")))
-                    .await;
-                let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
-                return;
-            }
+        eprintln!("play_url: {}...", &url[..url.len().min(80)]);
+        self.current_transcoding = transcoding;
+        self.current_format = format;
+
+        let _ = self.event_tx.send(AudioEvent::Buffering(true)).await;
+
+        let loader = Loader {
+            client: self.http_client.clone(),
+            preference: self.stream_preference,
+            event_tx: self.event_tx.clone(),
+            track_id,
+            format: self.current_format.clone(),
         };
+        let generation = self.load_generation;
+        let load_tx = self.load_tx.clone();
+        self.load_task = Some(tokio::spawn(async move {
+            let result = loader.load(&url, permalink_url.as_deref()).await;
+            let _ = load_tx.send((generation, result));
+        }));
+    }
 
+    /// Start playing what a load task opened, unless playback has moved on
+    async fn finish_load(&mut self, generation: u64, result: Result<Loaded, AudioEvent>) {
+        if generation != self.load_generation {
+            // Dropping the result cancels any download it started
+            return;
+        }
+        self.load_task = None;
         let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
 
-        // Decode audio
-        eprintln!("  -> Decoding audio...");
-        let source = match Decoder::new(stream) {
-            Ok(s) => {
-                eprintln!("  -> Decoder created successfully");
-                s
-            }
-            Err(e) => {
-                eprintln!("  -> Decode FAILED: {e}");
-                let _ = self
-                    .event_tx
-                    .send(AudioEvent::Error(format!("Failed to decode: {e}")))
-                    .await;
-                return;
+        match result {
+            Ok(Loaded::Hls {
+                source,
+                download,
+                duration,
+                format,
+            }) => {
+                let (task, buffer) = download.into_parts();
+                self.hls_download = task;
+                self.stream_buffer = Some(buffer);
+                self.start_playback(source).await;
+                self.current_format = format.clone();
+                let _ = self.event_tx.send(AudioEvent::Format(format)).await;
+                // A stream that is still downloading has no decoded length
+                // yet; the playlist knows it
+                if let Some(controls) = &mut self.controls
+                    && controls.duration.is_none()
+                {
+                    controls.duration = Some(duration);
+                    self.report_duration().await;
+                }
             }
-        };
-
-        // Create sink and play
-        match Sink::try_new(&self.stream_handle) {
-            Ok(sink) => {
-                eprintln!("  -> Playing!");
-                sink.set_volume(self.volume);
-                sink.append(source);
-                self.sink = Some(sink);
-                // Start position tracking
-                self.playback_start = Some(std::time::Instant::now());
-                self.accumulated_time = 0.0;
-                self.is_paused = false;
-                let _ = self.event_tx.send(AudioEvent::Playing).await;
+            Ok(Loaded::Progressive(source)) => {
+                self.start_playback(source).await;
+                let _ = self.event_tx.send(AudioEvent::Format(self.current_format.clone())).await;
             }
-            Err(e) => {
-                eprintln!("  -> Sink creation FAILED: {e}");
-                let _ = self
-                    .event_tx
-                    .send(AudioEvent::Error(format!("Failed to create sink: {e}")))
-                    .await;
+            Err(event) => {
+                let _ = self.event_tx.send(event).await;
             }
         }
     }
 
-    /// Play audio directly from in-memory data (used for cached tracks)
-    async fn play_from_data(&mut self, data: Vec<u8>) {
+    /// Play audio straight from a file on disk (used for cached and offline tracks)
+    async fn play_from_file(&mut self, file: File) {
         let _ = self.event_tx.send(AudioEvent::Buffering(true)).await;
 
-        let cursor = Cursor::new(data);
-        let source = match Decoder::new(cursor) {
+        let source = match Decoder::new(file) {
             Ok(s) => s,
             Err(e) => {
                 eprintln!("  -> Decode cached data FAILED: {e}");
@@ -314,16 +627,34 @@ impl AudioPlayer {
 
         let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
 
-        match Sink::try_new(&self.stream_handle) {
+        self.start_playback(source).await;
+    }
+
+    /// Create a new sink for a decoded source and start playing it
+    async fn start_playback<R>(&mut self, source: Decoder<R>)
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        match self.output.new_sink() {
             Ok(sink) => {
-                eprintln!("  -> Playing from cache!");
+                eprintln!("  -> Playing!");
+                let (source, controls) =
+                    self.dsp_chain(self.current_track_id, source.convert_samples(), 1.0);
                 sink.set_volume(self.volume);
                 sink.append(source);
-                self.sink = Some(sink);
-                self.playback_start = Some(std::time::Instant::now());
-                self.accumulated_time = 0.0;
-                self.is_paused = false;
-                let _ = self.event_tx.send(AudioEvent::Playing).await;
+                // Paused while the track was loading
+                if self.is_paused {
+                    sink.pause();
+                }
+                self.sink = Some(Arc::new(sink));
+                self.controls = Some(controls);
+                let event = if self.is_paused {
+                    AudioEvent::Paused
+                } else {
+                    AudioEvent::Playing
+                };
+                let _ = self.event_tx.send(event).await;
+                self.report_duration().await;
             }
             Err(e) => {
                 eprintln!("  -> Sink creation FAILED: {e}");
@@ -336,249 +667,560 @@ impl AudioPlayer {
     }
 
     /// Preload audio data for a track into the disk cache without playing it.
-    /// Downloads the audio data (HLS segments or progressive stream) and writes
-    /// it to ~/.cache/cosmic-soundcloud/audio/{track_id}.audio
-    async fn preload(&mut self, track_id: u64, url: &str, permalink_url: Option<&str>) {
-        // Skip if already cached
-        if cache::has_cached(track_id) {
-            eprintln!("[preload] Track {track_id} already cached, skipping");
+    /// Downloads the audio data (HLS segments or progressive stream) in a
+    /// background task, replacing any preload still running, which writes it
+    /// to ~/.cache/cosmic-soundcloud/audio/{track_id}.audio for
+    /// `finish_preload` to add to the index
+    async fn preload(&mut self, track_id: u64, source: StreamSource) {
+        // Skip if already on disk
+        let StreamSource::Stream {
+            url,
+            transcoding,
+            format,
+        } = source
+        else {
+            eprintln!("[preload] Track {track_id} already on disk, skipping");
+            let _ = self.event_tx.send(AudioEvent::PreloadComplete(track_id)).await;
+            return;
+        };
+        if offline::audio_path(track_id).is_some() || self.cache.contains(track_id) {
+            eprintln!("[preload] Track {track_id} already on disk, skipping");
             let _ = self.event_tx.send(AudioEvent::PreloadComplete(track_id)).await;
             return;
         }
 
         eprintln!("[preload] Starting preload for track {track_id}: {}...", &url[..url.len().min(80)]);
 
-        let audio_data = if url.contains(".m3u8") {
-            self.download_hls_data(url, permalink_url).await
-        } else {
-            self.download_progressive_data(url).await
-        };
+        if let Some(task) = self.preload_task.take() {
+            task.abort();
+        }
+        let downloader = Downloader::new(self.http_client.clone(), self.stream_preference);
+        let event_tx = self.event_tx.clone();
+        let preload_tx = self.preload_tx.clone();
+        self.preload_task = Some(tokio::spawn(async move {
+            let file = downloader
+                .download(&url, |downloaded, total| {
+                    let _ = event_tx.try_send(AudioEvent::DownloadProgress {
+                        track_id: Some(track_id),
+                        downloaded,
+                        total,
+                    });
+                })
+                .await;
+            // Copying a whole track to the cache blocks, keep it off the runtime
+            let written = match file {
+                Some(mut file) => tokio::task::spawn_blocking(move || {
+                    let size = cache::write_file(track_id, &mut file)?;
+                    file.rewind().map_err(|e| e.to_string())?;
+                    Ok((size, file))
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string())),
+                None => Err("Failed to download audio data".into()),
+            };
+            let _ = preload_tx.send(Preloaded {
+                track_id,
+                transcoding,
+                format,
+                written,
+            });
+        }));
+    }
 
-        match audio_data {
-            Some(data) if !data.is_empty() => {
-                match cache::write_cached(track_id, &data) {
-                    Ok(()) => {
-                        eprintln!("[preload] Track {track_id} cached ({} bytes)", data.len());
-                        let _ = self.event_tx.send(AudioEvent::PreloadComplete(track_id)).await;
-                    }
-                    Err(e) => {
-                        eprintln!("[preload] Failed to write cache for track {track_id}: {e}");
-                    }
+    /// Cache the audio a preload task downloaded
+    async fn finish_preload(&mut self, preloaded: Preloaded) {
+        let Preloaded {
+            track_id,
+            transcoding,
+            format,
+            written,
+        } = preloaded;
+
+        let (size, file) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                eprintln!("[preload] Failed to cache track {track_id}: {e}");
+                return;
+            }
+        };
+        match self.cache.insert(track_id, size, transcoding.map(|t| t.format), &format) {
+            Ok(()) => {
+                eprintln!("[preload] Track {track_id} cached ({size} bytes)");
+                if !self.loudness.contains(track_id) {
+                    self.measure_loudness(track_id, file);
                 }
+                let _ = self.event_tx.send(AudioEvent::PreloadComplete(track_id)).await;
             }
-            _ => {
-                eprintln!("[preload] Failed to download audio data for track {track_id}");
+            Err(e) => {
+                eprintln!("[preload] Failed to write cache for track {track_id}: {e}");
             }
         }
     }
 
-    /// Download HLS audio data without playing it. Returns the concatenated segment bytes.
-    async fn download_hls_data(&self, url: &str, permalink_url: Option<&str>) -> Option<Vec<u8>> {
-        let playlist = hls::fetch_playlist(&self.http_client, url).await.ok()?;
-
-        // Handle encryption - try yt-dlp fallback if needed
-        if let Some(enc) = &playlist.encryption {
-            let is_commercial_drm = enc.keyformat.as_ref().is_some_and(|k| {
-                k.contains("playready")
-                    || k.contains("widevine")
-                    || k.contains("fairplay")
-                    || k.contains("urn:uuid")
-            });
+    /// Append a preloaded track to the current sink so it starts the instant
+    /// the current source ends. An empty callback source between the two marks
+    /// the boundary, so position tracking switches over exactly.
+    ///
+    /// With a crossfade, the decoded track is held back instead and started on
+    /// its own sink `crossfade.duration` seconds before the current one ends.
+    fn enqueue(&mut self, track_id: u64, crossfade: Option<Crossfade>) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        if sink.empty() || self.queued_track.is_some() || self.pending_crossfade.is_some() {
+            return;
+        }
 
-            if is_commercial_drm || (enc.method.contains("AES") && enc.uri.is_some()) {
-                // Try yt-dlp for encrypted content
-                if let Some(track_url) = permalink_url
-                    && !track_url.is_empty()
-                {
-                    if let Ok(ytdlp_url) = ytdlp::extract_stream_url(track_url) {
-                        let fallback_playlist = hls::fetch_playlist(&self.http_client, &ytdlp_url).await.ok()?;
-                        return self.download_hls_segments(&fallback_playlist).await;
-                    }
-                }
-                return None;
+        let Some(file) = open_local(&mut self.cache, track_id) else {
+            eprintln!("[gapless] Track {track_id} is not on disk, can't enqueue");
+            return;
+        };
+        let source = match Decoder::new(file) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("[gapless] Failed to decode track {track_id}: {e}");
+                return;
             }
+        };
+
+        if let Some(crossfade) = crossfade {
+            // Prefer the decoded length over the one from the API
+            let track_duration = self
+                .controls
+                .as_ref()
+                .and_then(|c| c.duration)
+                .map_or(crossfade.track_duration, |d| d.as_secs_f32());
+            let duration = crossfade.duration.min(track_duration / 2.0).max(0.0);
+            eprintln!("[crossfade] Track {track_id} will fade in over {duration:.1}s");
+            self.pending_crossfade = Some(PendingCrossfade {
+                track_id,
+                source,
+                start_at: track_duration - duration,
+                duration: Duration::from_secs_f32(duration),
+            });
+            return;
         }
 
-        self.download_hls_segments(&playlist).await
+        let boundary_tx = self.boundary_tx.clone();
+        let (source, controls) = self.dsp_chain(Some(track_id), source.convert_samples(), 1.0);
+        sink.append(EmptyCallback::<f32>::new(Box::new(move || {
+            let _ = boundary_tx.send(track_id);
+        })));
+        sink.append(source);
+        self.queued_track = Some((track_id, controls));
+        eprintln!("[gapless] Track {track_id} queued after the current track");
     }
 
-    /// Download all HLS segments and return concatenated bytes.
-    async fn download_hls_segments(&self, playlist: &hls::HlsStream) -> Option<Vec<u8>> {
-        if playlist.segments.is_empty() {
+    /// When the pending crossfade should start. `None` while nothing is pending
+    /// or playback isn't advancing (paused or buffering). Recomputed on every
+    /// pass of the player loop, so it follows the sink's actual position.
+    fn crossfade_deadline(&self) -> Option<tokio::time::Instant> {
+        let pending = self.pending_crossfade.as_ref()?;
+        if self.is_paused || self.is_stalled {
             return None;
         }
+        let remaining = (pending.start_at - self.position()?).max(0.0);
+        Some(tokio::time::Instant::now() + Duration::from_secs_f32(remaining))
+    }
+
+    /// Start the pending track on a new sink, fading it in while the current
+    /// source fades out on the old sink
+    async fn start_crossfade(&mut self) {
+        let Some(pending) = self.pending_crossfade.take() else {
+            return;
+        };
+
+        let sink = match self.output.new_sink() {
+            Ok(sink) => sink,
+            Err(e) => {
+                eprintln!("[crossfade] Sink creation FAILED: {e}");
+                return;
+            }
+        };
+        let (source, controls) =
+            self.dsp_chain(Some(pending.track_id), pending.source.convert_samples(), 0.0);
+        sink.set_volume(self.volume);
+        sink.append(source);
+        controls.fade.fade_to(1.0, pending.duration);
+        // Don't fade back in over a fade-out that's under way
+        if let Some(until) = self.fade_out_until {
+            controls
+                .fade
+                .fade_to(0.0, until.saturating_duration_since(tokio::time::Instant::now()));
+        }
+
+        if let Some(old) = self.controls.replace(controls) {
+            old.fade.fade_to(0.0, pending.duration);
+        }
+        if let Some(old_sink) = self.sink.replace(Arc::new(sink)) {
+            self.fading_sink = Some((old_sink, Instant::now() + pending.duration));
+        }
+        self.supersede_seek();
+
+        // The outgoing track's download (if any) finishes on its own
+        self.stream_buffer = None;
+        self.hls_download = None;
+        self.is_stalled = false;
+
+        self.current_track_id = Some(pending.track_id);
+        self.is_paused = false;
+        eprintln!("[crossfade] Now playing track {}", pending.track_id);
+        let _ = self
+            .event_tx
+            .send(AudioEvent::TrackChanged(pending.track_id))
+            .await;
+        let _ = self
+            .event_tx
+            .send(AudioEvent::Format(self.local_format(pending.track_id)))
+            .await;
+        self.report_duration().await;
+    }
+
+    /// Wrap a decoded source in the player's DSP chain: loudness measurement
+    /// for tracks not yet in the index, the equalizer, then fades and
+    /// normalization gain, the spectrum tap, and finally the position counter
+    fn dsp_chain<S>(
+        &self,
+        track_id: Option<u64>,
+        source: S,
+        initial_fade: f32,
+    ) -> (DspChain<S>, SourceControls)
+    where
+        S: Source<Item = f32>,
+    {
+        let duration = source.total_duration();
+        let unmeasured = track_id.filter(|id| !self.loudness.contains(*id));
+        let source = Measure::new(source, unmeasured, self.loudness_tx.clone());
+        let source = Equalizer::new(source, self.equalizer.clone());
+        let fade = FadeControl::new(self.loudness.gain(&self.normalization, track_id));
+        let source = Fade::new(source, initial_fade, fade.clone());
+        let source = Spectrum::new(source, self.spectrum.clone());
+        let position = PositionCounter::default();
+        let controls = SourceControls {
+            fade,
+            position: position.clone(),
+            duration,
+        };
+        (Counted::new(source, position), controls)
+    }
 
-        let mut audio_data = Vec::new();
+    /// Measure the loudness of downloaded audio on a blocking thread. The
+    /// result arrives like a measurement from a source that played through.
+    fn measure_loudness(&self, track_id: u64, file: File) {
+        let loudness_tx = self.loudness_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let gain = Decoder::new(file)
+                .ok()
+                .and_then(|source| loudness::measure(source.convert_samples()));
+            match gain {
+                Some(gain) => {
+                    let _ = loudness_tx.send((track_id, gain));
+                }
+                None => eprintln!("[loudness] Could not measure track {track_id}"),
+            }
+        });
+    }
 
-        // Download init segment if present
-        if let Some(init_url) = &playlist.init_segment_url {
-            let data = hls::download_segment(&self.http_client, init_url).await.ok()?;
-            audio_data.extend(data);
+    /// Switch normalization mode, updating the gain of the playing and queued tracks
+    fn set_normalization(&mut self, normalization: Normalization) {
+        self.normalization = normalization;
+        if let Some(controls) = &self.controls {
+            let level = self.loudness.gain(&self.normalization, self.current_track_id);
+            controls.fade.set_level(level);
+        }
+        if let Some((track_id, controls)) = &self.queued_track {
+            controls.fade.set_level(self.loudness.gain(&self.normalization, Some(*track_id)));
         }
+    }
 
-        // Download all segments
-        for segment in &playlist.segments {
-            let data = hls::download_segment(&self.http_client, &segment.uri).await.ok()?;
-            audio_data.extend(data);
+    /// Playback crossed from the current source into the queued track
+    async fn track_boundary(&mut self, track_id: u64) {
+        // Ignore boundaries from a sink that has since been replaced
+        if self.queued_track.as_ref().map(|(id, _)| *id) != Some(track_id) {
+            return;
         }
+        if let Some((_, controls)) = self.queued_track.take() {
+            self.controls = Some(controls);
+        }
+        self.current_track_id = Some(track_id);
+        self.cancel_hls_download();
 
-        Some(audio_data)
+        eprintln!("[gapless] Now playing track {track_id}");
+        let _ = self.event_tx.send(AudioEvent::TrackChanged(track_id)).await;
+        let _ = self.event_tx.send(AudioEvent::Format(self.local_format(track_id))).await;
+        self.report_duration().await;
     }
 
-    /// Download a progressive stream fully into memory.
-    async fn download_progressive_data(&self, url: &str) -> Option<Vec<u8>> {
-        let response = self.http_client.get(url).send().await.ok()?;
-        let bytes = response.bytes().await.ok()?;
-        Some(bytes.to_vec())
+    /// Tell the UI which output devices are available
+    async fn report_output_devices(&mut self) {
+        let listed = tokio::task::spawn_blocking(output::list_devices)
+            .await
+            .unwrap_or_default();
+        self.output.update_devices(listed);
+        let _ = self
+            .event_tx
+            .send(AudioEvent::OutputDevices {
+                devices: self.output.devices(),
+                current: self.output.device().to_string(),
+            })
+            .await;
+    }
+
+    /// Tell the UI the decoded duration of the current track, if known
+    async fn report_duration(&self) {
+        if let Some(duration) = self.controls.as_ref().and_then(|c| c.duration) {
+            let _ = self
+                .event_tx
+                .send(AudioEvent::Duration(duration.as_secs_f32()))
+                .await;
+        }
     }
 
     async fn pause(&mut self) {
+        self.cancel_fade_out(Duration::ZERO);
+        if let Some((sink, _)) = &self.fading_sink {
+            sink.pause();
+        }
         if let Some(sink) = &self.sink {
             sink.pause();
-            // Track accumulated time before pause
-            if let Some(start) = self.playback_start.take() {
-                self.accumulated_time += start.elapsed().as_secs_f32();
-            }
+            self.is_paused = true;
+            let _ = self.event_tx.send(AudioEvent::Paused).await;
+        } else if self.load_task.is_some() {
+            // Start paused once the track has loaded
             self.is_paused = true;
             let _ = self.event_tx.send(AudioEvent::Paused).await;
         }
     }
 
     async fn resume(&mut self) {
+        self.cancel_fade_out(FADE_OUT_CANCEL);
+        // The sink only notices a pause every few milliseconds, so the gain
+        // stays down until playback resumes rather than coming back early
+        if std::mem::take(&mut self.faded_out) {
+            self.restore_gain(Duration::ZERO);
+        }
+        if let Some((sink, _)) = &self.fading_sink {
+            sink.play();
+        }
         if let Some(sink) = &self.sink {
             sink.play();
-            // Restart the timer from now
-            self.playback_start = Some(std::time::Instant::now());
             self.is_paused = false;
             let _ = self.event_tx.send(AudioEvent::Playing).await;
+        } else if self.load_task.is_some() {
+            self.is_paused = false;
+            let _ = self.event_tx.send(AudioEvent::Buffering(true)).await;
+        }
+    }
+
+    /// Fade the playing track out over `secs` seconds, pausing once it's
+    /// silent. Pauses straight away if there's nothing audible to fade.
+    async fn fade_out(&mut self, secs: f32) {
+        let duration = Duration::from_secs_f32(secs.max(0.0));
+        if self.is_paused || self.controls.is_none() || duration.is_zero() {
+            self.pause().await;
+            return;
+        }
+        eprintln!("[fade-out] Pausing in {secs:.1}s");
+        for controls in self.controls.iter().chain(self.queued_track.as_ref().map(|(_, c)| c)) {
+            controls.fade.fade_to(0.0, duration);
+        }
+        self.fade_out_until = Some(tokio::time::Instant::now() + duration);
+    }
+
+    /// Pause at the end of a fade-out. The gain is brought back on resume.
+    async fn finish_fade_out(&mut self) {
+        self.fade_out_until = None;
+        self.pause().await;
+        self.faded_out = true;
+    }
+
+    /// Stop a fade-out under way, bringing the gain back over `duration`
+    fn cancel_fade_out(&mut self, duration: Duration) {
+        if self.fade_out_until.take().is_some() {
+            eprintln!("[fade-out] Cancelled");
+            self.restore_gain(duration);
+        }
+    }
+
+    fn restore_gain(&self, duration: Duration) {
+        for controls in self.controls.iter().chain(self.queued_track.as_ref().map(|(_, c)| c)) {
+            controls.fade.fade_to(1.0, duration);
         }
     }
 
     async fn stop(&mut self) {
-        if let Some(sink) = self.sink.take() {
-            sink.stop();
-            self.playback_start = None;
-            self.accumulated_time = 0.0;
+        self.fade_out_until = None;
+        self.faded_out = false;
+        // Abort a load in progress, and ignore its result if already sent
+        let was_loading = self.load_task.take().inspect(|task| task.abort()).is_some();
+        self.load_generation += 1;
+        self.supersede_seek();
+        self.cancel_hls_download();
+        self.queued_track = None;
+        self.pending_crossfade = None;
+        self.fading_sink = None;
+        self.controls = None;
+        self.current_track_id = None;
+        let had_sink = self.sink.take().inspect(|sink| sink.stop()).is_some();
+        if had_sink || was_loading {
             self.is_paused = false;
             let _ = self.event_tx.send(AudioEvent::Stopped).await;
         }
     }
 
-    fn set_volume(&mut self, volume: f32) {
-        self.volume = volume.clamp(0.0, 1.0);
-        if let Some(sink) = &self.sink {
-            sink.set_volume(self.volume);
-        }
+    /// Current position in the playing track (seconds), counted from the
+    /// samples the sink has consumed
+    fn position(&self) -> Option<f32> {
+        self.controls
+            .as_ref()
+            .map(|c| c.position.position().as_secs_f32())
+    }
+
+    /// Seek the current source; its position counter follows the new offset.
+    /// Files on disk and HLS stream buffers seek directly, progressive streams
+    /// fetch the requested range on demand.
+    ///
+    /// The sink applies a seek on the audio thread, which waits for the
+    /// download when seeking past what's buffered, so the wait happens on a
+    /// blocking thread and `finish_seek` reports the result. A later seek or
+    /// track supersedes it.
+    fn seek(&mut self, position: f32) {
+        let Some(sink) = self.sink.clone() else {
+            return;
+        };
+
+        self.supersede_seek();
+        self.seeking = true;
+        let generation = self.seek_generation;
+        let seek_tx = self.seek_tx.clone();
+        let position = position.max(0.0);
+        tokio::task::spawn_blocking(move || {
+            let result = sink
+                .try_seek(Duration::from_secs_f32(position))
+                .map_err(|e| e.to_string());
+            let _ = seek_tx.send((generation, position, result));
+        });
     }
 
-    /// Play an HLS stream by downloading and concatenating segments
-    /// `from_ytdlp` indicates this URL came from yt-dlp fallback (prevents recursion)
-    async fn play_hls(&mut self, url: &str, permalink_url: Option<&str>, from_ytdlp: bool) {
-        // Fetch and parse the m3u8 playlist
-        let playlist = match hls::fetch_playlist(&self.http_client, url).await {
-            Ok(p) => p,
+    /// Report a seek the audio thread has applied, unless it's been superseded
+    async fn finish_seek(&mut self, generation: u64, position: f32, result: Result<(), String>) {
+        if generation != self.seek_generation {
+            return;
+        }
+        self.seeking = false;
+        match result {
+            Ok(()) => {
+                let _ = self.event_tx.send(AudioEvent::Position(position)).await;
+            }
             Err(e) => {
-                let _ = self
-                    .event_tx
-                    .send(AudioEvent::Error(format!("Failed to parse HLS: {e}")))
-                    .await;
-                let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
-                return;
+                eprintln!("Seek to {position:.1}s failed: {e}");
             }
-        };
+        }
+    }
+
+    /// Drop the result of any seek still in progress
+    fn supersede_seek(&mut self) {
+        self.seek_generation += 1;
+        self.seeking = false;
+    }
 
-        // Check encryption - SoundCloud uses CENC/PlayReady which requires commercial DRM
-        if let Some(enc) = &playlist.encryption {
-            eprintln!("HLS encryption: method={}, keyformat={:?}, key_uri={:?}",
-                enc.method, enc.keyformat, enc.uri);
-
-            // Check for commercial DRM that requires a license server
-            // SAMPLE-AES alone just means encryption - it's the keyformat that determines DRM
-            let is_commercial_drm = enc.keyformat.as_ref().is_some_and(|k| {
-                k.contains("playready")     // Microsoft PlayReady
-                || k.contains("widevine")   // Google Widevine
-                || k.contains("fairplay")   // Apple FairPlay
-                || k.contains("urn:uuid")   // Generic CENC DRM
+    /// Write the current track to the cache once its HLS download has finished
+    fn cache_streamed_track(&mut self) {
+        self.hls_download = None;
+        let Some(buffer) = &self.stream_buffer else {
+            return;
+        };
+        let Some(track_id) = self.current_track_id else {
+            return;
+        };
+        if self.cache.contains(track_id) {
+            return;
+        }
+        let Some(mut reader) = buffer.completed_reader() else {
+            return;
+        };
+        let transcoding = self.current_transcoding.clone().map(|t| t.format);
+        let format = self.current_format.clone();
+        let cached_tx = self.cached_tx.clone();
+        // The copy blocks, so it's indexed by `finish_caching` once done
+        tokio::task::spawn_blocking(move || {
+            let _ = cached_tx.send(Cached {
+                track_id,
+                transcoding,
+                format,
+                written: cache::write_file(track_id, &mut reader),
             });
+        });
+    }
 
-            if is_commercial_drm || (enc.method.contains("AES") && enc.uri.is_some()) {
-                let drm_type = enc.keyformat.as_deref().unwrap_or("encrypted").to_string();
-
-                // If we're already from yt-dlp, don't try again (prevents infinite recursion)
-                if from_ytdlp {
-                    eprintln!("yt-dlp stream is also encrypted - giving up");
-                    let track_url = permalink_url.unwrap_or("").to_string();
-                    let _ = self
-                        .event_tx
-                        .send(AudioEvent::DrmProtected { drm_type, track_url })
-                        .await;
-                    let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
-                    return;
-                }
+    /// Index a streamed track once it's been written to the cache
+    fn finish_caching(&mut self, cached: Cached) {
+        let track_id = cached.track_id;
+        let result = cached
+            .written
+            .and_then(|size| self.cache.insert(track_id, size, cached.transcoding, &cached.format).map(|()| size));
+        match result {
+            Ok(size) => eprintln!("[cache] Track {track_id} cached after streaming ({size} bytes)"),
+            Err(e) => eprintln!("[cache] Failed to cache track {track_id}: {e}"),
+        }
+    }
 
-                eprintln!("Encrypted stream detected ({}), trying yt-dlp fallback...", drm_type);
+    /// Format of a track's audio on disk. Offline downloads don't record theirs.
+    fn local_format(&self, track_id: u64) -> StreamFormat {
+        if offline::audio_path(track_id).is_some() {
+            return StreamFormat::default();
+        }
+        self.cache.format(track_id).unwrap_or_default()
+    }
 
-                // Try yt-dlp to get an unencrypted stream
-                if let Some(track_url) = permalink_url
-                    && !track_url.is_empty()
-                {
-                    match ytdlp::extract_stream_url(track_url) {
-                        Ok(ytdlp_url) => {
-                            eprintln!("yt-dlp extracted URL: {}...", &ytdlp_url[..ytdlp_url.len().min(80)]);
-                            // Play the yt-dlp URL using play_hls_stream directly to avoid recursion
-                            self.play_hls_stream(&ytdlp_url).await;
-                            return;
-                        }
-                        Err(e) => {
-                            eprintln!("yt-dlp failed: {e}");
-                        }
-                    }
-                }
+    /// Abort any in-progress HLS download and release readers waiting on it
+    fn cancel_hls_download(&mut self) {
+        if let Some(task) = self.hls_download.take() {
+            task.abort();
+        }
+        if let Some(buffer) = self.stream_buffer.take() {
+            buffer.finish();
+        }
+        self.is_stalled = false;
+    }
 
-                // yt-dlp failed, fall back to browser
-                let track_url = permalink_url.unwrap_or("").to_string();
-                let _ = self
-                    .event_tx
-                    .send(AudioEvent::DrmProtected { drm_type, track_url })
-                    .await;
-                let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
-                return;
+    /// Tell the UI when playback is waiting for the download to catch up
+    async fn set_stalled(&mut self, stalled: bool) {
+        self.is_stalled = stalled;
+        if stalled {
+            eprintln!("[hls] Playback caught up with download, buffering...");
+            let _ = self.event_tx.send(AudioEvent::Buffering(true)).await;
+        } else {
+            let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
+            if !self.is_paused {
+                let _ = self.event_tx.send(AudioEvent::Playing).await;
             }
         }
-
-        // Stream the playlist segments
-        self.stream_hls_playlist(&playlist).await;
     }
 
-    /// Stream an HLS playlist (no DRM check - use after verifying stream is playable)
-    async fn play_hls_stream(&mut self, url: &str) {
-        // Fetch and parse the m3u8 playlist
-        let playlist = match hls::fetch_playlist(&self.http_client, url).await {
-            Ok(p) => p,
-            Err(e) => {
-                let _ = self
-                    .event_tx
-                    .send(AudioEvent::Error(format!("Failed to parse HLS: {e}")))
-                    .await;
-                let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
-                return;
-            }
-        };
+    fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        if let Some(sink) = &self.sink {
+            sink.set_volume(self.volume);
+        }
+    }
+}
 
-        self.stream_hls_playlist(&playlist).await;
+/// Sleep until `deadline`, or forever if there is none
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
     }
+}
 
-    /// Download and play HLS segments from a parsed playlist
-    async fn stream_hls_playlist(&mut self, playlist: &hls::HlsStream) {
-        if let Some(audio_data) = self.download_hls_segments(playlist).await {
-            let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
-            self.play_from_data(audio_data).await;
-        } else {
-            let _ = self
-                .event_tx
-                .send(AudioEvent::Error("Failed to download HLS segments".into()))
-                .await;
-            let _ = self.event_tx.send(AudioEvent::Buffering(false)).await;
+/// Open a track's audio in the offline store, or failing that the cache
+fn open_local(cache: &mut AudioCache, track_id: u64) -> Option<File> {
+    if let Some(path) = offline::audio_path(track_id) {
+        match File::open(&path) {
+            Ok(file) => return Some(file),
+            Err(e) => eprintln!("[offline] Failed to open {}: {e}", path.display()),
         }
     }
+    cache.open(track_id)
 }
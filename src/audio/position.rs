@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Playback position derived from the samples the sink has actually pulled.
+//!
+//! The outermost wrapper of every source the player appends is a [`Counted`],
+//! which counts samples as the output consumes them and publishes the elapsed
+//! time through a shared [`PositionCounter`]. Unlike wall-clock timing, this
+//! stops by itself while the sink is paused or starved, and stays exact
+//! across seeks.
+
+use rodio::Source;
+use rodio::source::SeekError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Number of samples between updates of the shared position
+const PUBLISH_INTERVAL: u32 = 256;
+
+/// Handle used by the player to read a source's position
+#[derive(Clone, Default)]
+pub struct PositionCounter(Arc<AtomicU64>);
+
+impl PositionCounter {
+    /// Time played so far
+    pub fn position(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+
+    fn publish(&self, position: Duration) {
+        let nanos = u64::try_from(position.as_nanos()).unwrap_or(u64::MAX);
+        self.0.store(nanos, Ordering::Relaxed);
+    }
+}
+
+/// Source wrapper counting the samples pulled through it
+pub struct Counted<S> {
+    inner: S,
+    counter: PositionCounter,
+    /// Position at the last seek or format change
+    base: Duration,
+    /// Samples since `base`
+    samples: u64,
+    /// Sample format `samples` were counted in
+    format: (u32, u16),
+    until_publish: u32,
+}
+
+impl<S> Counted<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S, counter: PositionCounter) -> Self {
+        let format = (inner.sample_rate(), inner.channels());
+        counter.publish(Duration::ZERO);
+        Self {
+            inner,
+            counter,
+            base: Duration::ZERO,
+            samples: 0,
+            format,
+            until_publish: PUBLISH_INTERVAL,
+        }
+    }
+
+    /// Time covered by the samples counted since `base`
+    fn counted(&self) -> Duration {
+        let (rate, channels) = self.format;
+        let per_second = u64::from(rate) * u64::from(channels);
+        if per_second == 0 {
+            return Duration::ZERO;
+        }
+        let nanos = u128::from(self.samples) * 1_000_000_000 / u128::from(per_second);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    fn publish(&mut self) {
+        self.until_publish = PUBLISH_INTERVAL;
+        let position = self.base + self.counted();
+
+        // Samples after a format change count at the new rate
+        let format = (self.inner.sample_rate(), self.inner.channels());
+        if format != self.format {
+            self.base = position;
+            self.samples = 0;
+            self.format = format;
+        }
+        self.counter.publish(position);
+    }
+}
+
+impl<S> Iterator for Counted<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        let Some(sample) = self.inner.next() else {
+            self.publish();
+            return None;
+        };
+        self.samples += 1;
+        self.until_publish -= 1;
+        if self.until_publish == 0 {
+            self.publish();
+        }
+        Some(sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Counted<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.base = pos;
+        self.samples = 0;
+        self.format = (self.inner.sample_rate(), self.inner.channels());
+        self.until_publish = PUBLISH_INTERVAL;
+        self.counter.publish(pos);
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Finding where a track's audio comes from.
+//!
+//! Each [`StreamResolver`] knows one source: the offline store, the audio
+//! cache, SoundCloud's transcodings or yt-dlp. A [`ResolverChain`] asks them
+//! in the order set in the config and stops at the first one that can provide
+//! the track. The ones before it say why they declined, so a track that
+//! won't play can tell why, and a new source is just another resolver.
+//!
+//! When a stream turns out to be unplayable only once the player opens it
+//! (commercial DRM), the app carries on down the chain from the resolver
+//! after the one that provided it. Only once every resolver has declined, and
+//! DRM was among the reasons, does it fall back to the browser.
+
+use super::ytdlp::{YtDlp, YtDlpError};
+use super::{cache, hls, offline};
+use crate::api::{SoundCloudClient, StreamFormat, StreamPreference, Track, Transcoding};
+use futures_util::FutureExt;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Where to play a track's audio from
+#[derive(Debug, Clone)]
+pub enum StreamSource {
+    /// Pinned in the offline store
+    Offline(PathBuf),
+    /// In the audio cache
+    Cached,
+    /// A stream to open, from `transcoding` when it's one of the track's
+    Stream {
+        url: String,
+        transcoding: Option<Transcoding>,
+        /// As far as the resolver knows
+        format: StreamFormat,
+    },
+}
+
+/// Why a resolver passed on a track
+#[derive(Debug, Clone)]
+pub enum Decline {
+    /// The source doesn't have the track
+    NotFound(String),
+    /// Every stream the source found is protected by this DRM system
+    Drm(String),
+    /// The source should have the track, but resolving it failed
+    Failed(String),
+    /// yt-dlp is missing or couldn't extract the track
+    YtDlp(YtDlpError),
+}
+
+impl fmt::Display for Decline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(reason) => write!(f, "not available: {reason}"),
+            Self::Drm(system) => write!(f, "DRM-protected ({system})"),
+            Self::Failed(reason) => write!(f, "failed: {reason}"),
+            Self::YtDlp(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// The resolvers there are, as stored in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResolverKind {
+    Offline,
+    Cache,
+    SoundCloud,
+    YtDlp,
+}
+
+impl ResolverKind {
+    /// Local audio first, then SoundCloud, with yt-dlp for DRM-protected tracks
+    pub fn default_order() -> Vec<ResolverKind> {
+        vec![Self::Offline, Self::Cache, Self::SoundCloud, Self::YtDlp]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Offline => "Offline downloads",
+            Self::Cache => "Audio cache",
+            Self::SoundCloud => "SoundCloud",
+            Self::YtDlp => "yt-dlp",
+        }
+    }
+
+    /// Whether the resolver finds audio already on disk
+    pub fn is_local(self) -> bool {
+        matches!(self, Self::Offline | Self::Cache)
+    }
+}
+
+/// A resolver's answer to a track it declined
+#[derive(Debug, Clone)]
+pub struct Declined {
+    pub kind: ResolverKind,
+    pub reason: Decline,
+}
+
+impl fmt::Display for Declined {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.name(), self.reason)
+    }
+}
+
+/// The source a chain settled on
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub source: StreamSource,
+    pub kind: ResolverKind,
+    /// Position of the resolver in the chain, to resume after it
+    pub position: usize,
+}
+
+/// One place a track's audio can come from
+pub trait StreamResolver: Send + Sync {
+    fn kind(&self) -> ResolverKind;
+
+    fn resolve<'a>(&'a self, track: &'a Track) -> BoxFuture<'a, Result<StreamSource, Decline>>;
+}
+
+/// Resolvers in the order they're asked
+pub struct ResolverChain {
+    resolvers: Vec<Box<dyn StreamResolver>>,
+}
+
+impl ResolverChain {
+    /// Build a chain of the resolvers in `order`. SoundCloud declines every
+    /// track when there's no `client`.
+    pub fn new(
+        order: &[ResolverKind],
+        client: Option<SoundCloudClient>,
+        preference: StreamPreference,
+        ytdlp: YtDlp,
+    ) -> Self {
+        let resolvers = order
+            .iter()
+            .map(|kind| -> Box<dyn StreamResolver> {
+                match kind {
+                    ResolverKind::Offline => Box::new(OfflineResolver),
+                    ResolverKind::Cache => Box::new(CacheResolver),
+                    ResolverKind::SoundCloud => Box::new(SoundCloudResolver {
+                        client: client.clone(),
+                        preference,
+                    }),
+                    ResolverKind::YtDlp => Box::new(YtDlpResolver { ytdlp: ytdlp.clone() }),
+                }
+            })
+            .collect();
+        Self { resolvers }
+    }
+
+    /// Ask the resolvers from position `start` on. Fails with every
+    /// resolver's reason for declining if none of them has the track.
+    pub async fn resolve(&self, track: &Track, start: usize) -> Result<Resolved, Vec<Declined>> {
+        let mut declined = Vec::new();
+        for (position, resolver) in self.resolvers.iter().enumerate().skip(start) {
+            let kind = resolver.kind();
+            match resolver.resolve(track).await {
+                Ok(source) => {
+                    eprintln!("[resolver] Track {} from {}", track.id, kind.name());
+                    return Ok(Resolved {
+                        source,
+                        kind,
+                        position,
+                    });
+                }
+                Err(reason) => {
+                    let reason = Declined { kind, reason };
+                    eprintln!("[resolver] Track {}: {reason}", track.id);
+                    declined.push(reason);
+                }
+            }
+        }
+        Err(declined)
+    }
+}
+
+struct OfflineResolver;
+
+impl StreamResolver for OfflineResolver {
+    fn kind(&self) -> ResolverKind {
+        ResolverKind::Offline
+    }
+
+    fn resolve<'a>(&'a self, track: &'a Track) -> BoxFuture<'a, Result<StreamSource, Decline>> {
+        let result = offline::audio_path(track.id)
+            .map(StreamSource::Offline)
+            .ok_or_else(|| Decline::NotFound("not downloaded".into()));
+        futures_util::future::ready(result).boxed()
+    }
+}
+
+struct CacheResolver;
+
+impl StreamResolver for CacheResolver {
+    fn kind(&self) -> ResolverKind {
+        ResolverKind::Cache
+    }
+
+    fn resolve<'a>(&'a self, track: &'a Track) -> BoxFuture<'a, Result<StreamSource, Decline>> {
+        let result = if cache::is_cached(track.id) {
+            Ok(StreamSource::Cached)
+        } else {
+            Err(Decline::NotFound("not cached".into()))
+        };
+        futures_util::future::ready(result).boxed()
+    }
+}
+
+/// The track's own transcodings, ranked by the stream preference
+struct SoundCloudResolver {
+    client: Option<SoundCloudClient>,
+    preference: StreamPreference,
+}
+
+impl SoundCloudResolver {
+    async fn resolve_transcodings(&self, track: &Track) -> Result<StreamSource, Decline> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(|| Decline::NotFound("not signed in".into()))?;
+        let track_auth = track
+            .track_authorization
+            .as_deref()
+            .ok_or_else(|| Decline::NotFound("no track authorization".into()))?;
+
+        // Debug: print all available transcodings
+        if let Some(media) = &track.media {
+            eprintln!("Available transcodings for '{}':", track.title);
+            for t in &media.transcodings {
+                eprintln!("  - protocol: {}, mime: {}, quality: {:?}, url: {}...",
+                    t.format.protocol,
+                    t.format.mime_type,
+                    t.quality,
+                    &t.url[..t.url.len().min(80)]);
+            }
+        }
+
+        let transcodings = track.ranked_transcodings(self.preference);
+        if transcodings.is_empty() {
+            return Err(Decline::NotFound("no playable transcodings".into()));
+        }
+
+        let mut drm = None;
+        let mut last_error = String::new();
+        for transcoding in transcodings {
+            let url = match client.resolve_transcoding(transcoding, track_auth).await {
+                Ok(url) => url,
+                Err(e) => {
+                    eprintln!("Transcoding {} failed: {e}", transcoding.format.mime_type);
+                    last_error = e.to_string();
+                    continue;
+                }
+            };
+
+            // Commercial DRM only shows in the playlist. Checking here lets
+            // the chain move on before the player tries it.
+            if transcoding.is_encrypted()
+                && let Some(system) = drm_system(&url, self.preference).await
+            {
+                eprintln!("Transcoding {} is DRM-protected ({system})", transcoding.format.mime_type);
+                drm = Some(system);
+                continue;
+            }
+
+            eprintln!("Selected transcoding: {}", &transcoding.url[..transcoding.url.len().min(100)]);
+            return Ok(StreamSource::Stream {
+                url,
+                transcoding: Some(transcoding.clone()),
+                format: StreamFormat::of(transcoding),
+            });
+        }
+
+        Err(match drm {
+            Some(system) => Decline::Drm(system),
+            None => Decline::Failed(last_error),
+        })
+    }
+}
+
+impl StreamResolver for SoundCloudResolver {
+    fn kind(&self) -> ResolverKind {
+        ResolverKind::SoundCloud
+    }
+
+    fn resolve<'a>(&'a self, track: &'a Track) -> BoxFuture<'a, Result<StreamSource, Decline>> {
+        self.resolve_transcodings(track).boxed()
+    }
+}
+
+/// The DRM system protecting an HLS stream, if it needs one we can't play
+async fn drm_system(url: &str, preference: StreamPreference) -> Option<String> {
+    let playlist = hls::fetch_playlist(&reqwest::Client::new(), url, preference)
+        .await
+        .ok()?;
+    playlist
+        .encryption()
+        .filter(|enc| enc.needs_fallback())
+        .map(|enc| enc.describe())
+}
+
+/// Unencrypted streams yt-dlp extracts from the track page
+struct YtDlpResolver {
+    ytdlp: YtDlp,
+}
+
+impl YtDlpResolver {
+    async fn extract(&self, track: &Track) -> Result<StreamSource, Decline> {
+        let permalink_url = track
+            .permalink_url
+            .as_deref()
+            .filter(|url| !url.is_empty())
+            .ok_or_else(|| Decline::NotFound("no track page".into()))?;
+        let extracted = self.ytdlp.extract(permalink_url).await.map_err(Decline::YtDlp)?;
+
+        let expires_in = extracted
+            .expires
+            .map(|expires| expires.duration_since(SystemTime::now()).unwrap_or_default());
+        if expires_in == Some(Duration::ZERO) {
+            return Err(Decline::Failed("extracted URL has already expired".into()));
+        }
+        eprintln!(
+            "yt-dlp extracted {} ({:?}, expires in {:?}): {}...",
+            extracted.format_id.as_deref().unwrap_or("unknown format"),
+            extracted.codec,
+            expires_in,
+            &extracted.url[..extracted.url.len().min(80)]
+        );
+
+        Ok(StreamSource::Stream {
+            format: StreamFormat {
+                codec: extracted.codec,
+                quality: None,
+                bitrate: extracted.bitrate,
+            },
+            url: extracted.url,
+            transcoding: None,
+        })
+    }
+}
+
+impl StreamResolver for YtDlpResolver {
+    fn kind(&self) -> ResolverKind {
+        ResolverKind::YtDlp
+    }
+
+    fn resolve<'a>(&'a self, track: &'a Track) -> BoxFuture<'a, Result<StreamSource, Decline>> {
+        self.extract(track).boxed()
+    }
+}
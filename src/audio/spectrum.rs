@@ -0,0 +1,350 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Spectrum analysis of what's playing, for the visualizer.
+//!
+//! Every source the player appends runs through a [`Spectrum`] tap after the
+//! equalizer and fades, so the bars show what's heard. The tap runs on the
+//! audio callback, so all it does is mix samples down to mono into a fixed
+//! chunk and hand full chunks to an analysis thread over a bounded channel,
+//! dropping them if the thread falls behind. While no visualizer is shown the
+//! tap only counts samples and the analysis thread sleeps.
+//!
+//! The analysis thread keeps a window of the most recent audio and publishes
+//! bar levels about 30 times a second. During a crossfade both sources are
+//! tapped, and the one that was publishing keeps the visualizer until it ends.
+
+use rodio::Source;
+use rodio::source::SeekError;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self as std_mpsc, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Number of bars in a frame
+pub const BAR_COUNT: usize = 32;
+
+/// Samples per analysis window, per channel
+const FFT_SIZE: usize = 2048;
+
+/// Mono samples handed from a source to the analysis thread at a time
+const CHUNK_SIZE: usize = 512;
+
+/// Chunks that may wait for the analysis thread before new ones are dropped
+const CHUNK_QUEUE: usize = 16;
+
+/// Frames published per second
+const FRAME_RATE: u32 = 30;
+
+/// Frequency range covered by the bars (Hz)
+const MIN_FREQUENCY: f32 = 40.0;
+const MAX_FREQUENCY: f32 = 16_000.0;
+
+/// Level shown as an empty bar (dBFS)
+const FLOOR_DB: f32 = -70.0;
+
+/// How much of a bar's level is left after a frame when the audio drops
+const FALLOFF: f32 = 0.8;
+
+/// Number of samples between checks whether the visualizer is shown
+const CONTROL_INTERVAL: u32 = 1024;
+
+/// How long a source may go quiet before another one takes over the frames
+const OWNER_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Mono audio from one source
+struct Chunk {
+    source: u64,
+    sample_rate: u32,
+    samples: [f32; CHUNK_SIZE],
+}
+
+struct SpectrumShared {
+    enabled: AtomicBool,
+    /// Hands out an ID to each tapped source
+    next_id: AtomicU64,
+    chunks_tx: std_mpsc::SyncSender<Chunk>,
+}
+
+/// Handle used by the player to turn analysis on and off for every source
+#[derive(Clone)]
+pub struct SpectrumControl(Arc<SpectrumShared>);
+
+impl SpectrumControl {
+    /// Start the analysis thread. Frames are sent to `frames_tx` as bar
+    /// levels from 0.0 to 1.0, lowest frequency first, and dropped while the
+    /// receiver is full.
+    pub fn new(frames_tx: mpsc::Sender<Vec<f32>>) -> Self {
+        let (chunks_tx, chunks_rx) = std_mpsc::sync_channel(CHUNK_QUEUE);
+        std::thread::Builder::new()
+            .name("spectrum".into())
+            .spawn(move || analyse(chunks_rx, frames_tx))
+            .inspect_err(|e| eprintln!("[spectrum] Failed to start analysis thread: {e}"))
+            .ok();
+        Self(Arc::new(SpectrumShared {
+            enabled: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+            chunks_tx,
+        }))
+    }
+
+    /// Start or stop analysing. Sources notice within a few milliseconds.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Analysis thread: turn chunks into frames until every sender is gone
+fn analyse(chunks_rx: std_mpsc::Receiver<Chunk>, frames_tx: mpsc::Sender<Vec<f32>>) {
+    let mut analyser: Option<Analyser> = None;
+    // Source the frames currently come from, and when it last sent audio
+    let mut owner: Option<(u64, Instant)> = None;
+    loop {
+        let chunk = match chunks_rx.recv_timeout(OWNER_TIMEOUT) {
+            Ok(chunk) => chunk,
+            Err(RecvTimeoutError::Timeout) => {
+                // Paused or hidden: start from silence next time
+                owner = None;
+                analyser = None;
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        let now = Instant::now();
+        match owner {
+            Some((id, last)) if id != chunk.source && now.duration_since(last) < OWNER_TIMEOUT => continue,
+            _ => owner = Some((chunk.source, now)),
+        }
+
+        let analyser = match &mut analyser {
+            Some(analyser) if analyser.sample_rate == chunk.sample_rate => analyser,
+            analyser => analyser.insert(Analyser::new(chunk.sample_rate)),
+        };
+        for &sample in &chunk.samples {
+            if analyser.push(sample) {
+                // Frames keep coming, so drop them while the player is busy
+                let _ = frames_tx.try_send(analyser.frame());
+            }
+        }
+    }
+}
+
+/// Window, FFT and bar layout for one sample rate
+struct Analyser {
+    sample_rate: u32,
+    fft: Arc<dyn Fft<f32>>,
+    /// Hann window coefficients
+    window: Vec<f32>,
+    /// Mono samples, oldest first from `write`
+    history: Vec<f32>,
+    write: usize,
+    /// Samples between published frames
+    hop: usize,
+    until_frame: usize,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    /// FFT bin range of each bar
+    bins: Vec<(usize, usize)>,
+    /// Levels of the last frame, for the falloff
+    levels: Vec<f32>,
+}
+
+impl Analyser {
+    fn new(sample_rate: u32) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+
+        // Log-spaced bars, each at least one bin wide
+        let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+        let max_frequency = MAX_FREQUENCY.min(sample_rate as f32 / 2.0);
+        let ratio = max_frequency / MIN_FREQUENCY;
+        let mut bins = Vec::with_capacity(BAR_COUNT);
+        let mut start = ((MIN_FREQUENCY / bin_width) as usize).max(1);
+        for bar in 1..=BAR_COUNT {
+            let edge = MIN_FREQUENCY * ratio.powf(bar as f32 / BAR_COUNT as f32);
+            let end = ((edge / bin_width) as usize).max(start + 1).min(FFT_SIZE / 2);
+            bins.push((start.min(end - 1), end));
+            start = end;
+        }
+
+        let hop = (sample_rate / FRAME_RATE).max(1) as usize;
+        Self {
+            sample_rate,
+            fft,
+            window,
+            history: vec![0.0; FFT_SIZE],
+            write: 0,
+            hop,
+            until_frame: hop,
+            buffer: vec![Complex::default(); FFT_SIZE],
+            scratch,
+            bins,
+            levels: vec![0.0; BAR_COUNT],
+        }
+    }
+
+    /// Add a sample to the window. Returns whether a frame is due.
+    fn push(&mut self, sample: f32) -> bool {
+        self.history[self.write] = sample;
+        self.write = (self.write + 1) % FFT_SIZE;
+        self.until_frame -= 1;
+        if self.until_frame == 0 {
+            self.until_frame = self.hop;
+            return true;
+        }
+        false
+    }
+
+    /// Bar levels of the current window
+    fn frame(&mut self) -> Vec<f32> {
+        let history = self.history[self.write..].iter().chain(&self.history[..self.write]);
+        for ((out, &sample), &weight) in self.buffer.iter_mut().zip(history).zip(&self.window) {
+            *out = Complex::new(sample * weight, 0.0);
+        }
+        self.fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        // A full-scale sine peaks at a quarter of the window size after the
+        // Hann window, which is 0 dB
+        let reference = FFT_SIZE as f32 / 4.0;
+        for (level, &(start, end)) in self.levels.iter_mut().zip(&self.bins) {
+            let peak = self.buffer[start..end]
+                .iter()
+                .map(|bin| bin.norm())
+                .fold(0.0f32, f32::max);
+            let db = 20.0 * (peak / reference).max(1e-6).log10();
+            let target = ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0);
+            *level = target.max(*level * FALLOFF);
+        }
+        self.levels.clone()
+    }
+}
+
+/// Source wrapper handing samples to the spectrum analysis while it's enabled
+pub struct Spectrum<S> {
+    inner: S,
+    control: SpectrumControl,
+    id: u64,
+    enabled: bool,
+    until_check: u32,
+    channels: usize,
+    channel: usize,
+    /// Sum of the current frame's samples, mixed down to mono
+    mix: f32,
+    /// Mono samples waiting to be sent
+    chunk: [f32; CHUNK_SIZE],
+    filled: usize,
+}
+
+impl<S> Spectrum<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S, control: SpectrumControl) -> Self {
+        let id = control.0.next_id.fetch_add(1, Ordering::Relaxed);
+        let channels = usize::from(inner.channels().max(1));
+        Self {
+            inner,
+            control,
+            id,
+            enabled: false,
+            until_check: 0,
+            channels,
+            channel: 0,
+            mix: 0.0,
+            chunk: [0.0; CHUNK_SIZE],
+            filled: 0,
+        }
+    }
+
+    fn check_control(&mut self) {
+        self.until_check = CONTROL_INTERVAL;
+        self.enabled = self.control.0.enabled.load(Ordering::Relaxed);
+    }
+
+    fn tap(&mut self, sample: f32) {
+        self.mix += sample;
+        self.channel += 1;
+        if self.channel < self.channels {
+            return;
+        }
+        self.channel = 0;
+        self.chunk[self.filled] = self.mix / self.channels as f32;
+        self.mix = 0.0;
+        self.filled += 1;
+
+        if self.filled == CHUNK_SIZE {
+            self.filled = 0;
+            // Never wait on the analysis thread; if it's behind, skip ahead
+            let _ = self.control.0.chunks_tx.try_send(Chunk {
+                source: self.id,
+                sample_rate: self.inner.sample_rate(),
+                samples: self.chunk,
+            });
+        }
+    }
+}
+
+impl<S> Iterator for Spectrum<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.until_check == 0 {
+            self.check_control();
+        }
+        self.until_check -= 1;
+
+        let sample = self.inner.next()?;
+        if self.enabled {
+            self.tap(sample);
+        }
+        Some(sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Spectrum<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
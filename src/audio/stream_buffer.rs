@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Shared buffer for playing audio while it is still downloading.
+//!
+//! A background task appends downloaded bytes to a [`StreamBuffer`] while the
+//! decoder reads from it through a [`StreamReader`]. Reads past the end of the
+//! downloaded data block until more bytes arrive, and mark the buffer as
+//! stalled so the player can report buffering.
+//!
+//! The bytes live in an anonymous temporary file rather than in memory, so a
+//! long mix costs disk space instead of RAM; the file is deleted once the
+//! last reader is dropped.
+
+use parking_lot::{Condvar, Mutex};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a blocked read waits before re-checking for cancellation
+const READ_WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct BufferState {
+    /// Bytes written to the file so far
+    len: u64,
+    /// All data has been downloaded (or the download was cancelled)
+    finished: bool,
+    /// Download failed - reads past the downloaded data return this error
+    error: Option<String>,
+    /// A reader is waiting for data that hasn't arrived yet
+    stalled: bool,
+}
+
+/// Growable file-backed buffer filled by a download task and read by the decoder
+pub struct StreamBuffer {
+    file: File,
+    state: Mutex<BufferState>,
+    data_available: Condvar,
+}
+
+impl StreamBuffer {
+    pub fn new() -> Result<Arc<Self>, String> {
+        let file = tempfile::tempfile().map_err(|e| format!("Failed to create buffer file: {e}"))?;
+        Ok(Arc::new(Self {
+            file,
+            state: Mutex::new(BufferState::default()),
+            data_available: Condvar::new(),
+        }))
+    }
+
+    /// Append downloaded bytes and wake any waiting reader
+    pub fn append(&self, bytes: &[u8]) {
+        let mut state = self.state.lock();
+        if state.finished {
+            return;
+        }
+        match self.file.write_all_at(bytes, state.len) {
+            Ok(()) => state.len += bytes.len() as u64,
+            Err(e) => {
+                state.error = Some(format!("Failed to write buffer file: {e}"));
+                state.finished = true;
+            }
+        }
+        state.stalled = false;
+        self.data_available.notify_all();
+    }
+
+    /// Mark the download as complete (or cancelled), releasing blocked readers
+    pub fn finish(&self) {
+        let mut state = self.state.lock();
+        state.finished = true;
+        state.stalled = false;
+        self.data_available.notify_all();
+    }
+
+    /// Mark the download as failed
+    pub fn fail(&self, error: String) {
+        let mut state = self.state.lock();
+        state.error = Some(error);
+        state.finished = true;
+        state.stalled = false;
+        self.data_available.notify_all();
+    }
+
+    /// A reader over all the data, once the download has completed successfully
+    pub fn completed_reader(self: &Arc<Self>) -> Option<StreamReader> {
+        let state = self.state.lock();
+        (state.finished && state.error.is_none()).then(|| self.reader())
+    }
+
+    /// Whether playback has caught up with the download
+    pub fn is_stalled(&self) -> bool {
+        self.state.lock().stalled
+    }
+
+    /// Create a reader positioned at the start of the buffer
+    pub fn reader(self: &Arc<Self>) -> StreamReader {
+        StreamReader {
+            buffer: Arc::clone(self),
+            position: 0,
+        }
+    }
+}
+
+/// `Read + Seek` view over a [`StreamBuffer`], suitable for `rodio::Decoder`
+pub struct StreamReader {
+    buffer: Arc<StreamBuffer>,
+    position: u64,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.buffer.state.lock();
+        let available = loop {
+            if self.position < state.len {
+                break state.len - self.position;
+            }
+
+            if state.finished {
+                return match &state.error {
+                    Some(e) => Err(io::Error::other(e.clone())),
+                    None => Ok(0),
+                };
+            }
+
+            // Playback has caught up with the download - wait for more data
+            state.stalled = true;
+            self.buffer.data_available.wait_for(&mut state, READ_WAIT);
+        };
+        drop(state);
+
+        // Written bytes never change, so they can be read without the lock
+        let count = buf.len().min(usize::try_from(available).unwrap_or(usize::MAX));
+        let count = self.buffer.file.read_at(&mut buf[..count], self.position)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for StreamReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                // The total length is only known once the download completes
+                let mut state = self.buffer.state.lock();
+                while !state.finished {
+                    self.buffer.data_available.wait_for(&mut state, READ_WAIT);
+                }
+                state.len.checked_add_signed(offset)
+            }
+        };
+
+        let target = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        self.position = target;
+        Ok(target)
+    }
+}
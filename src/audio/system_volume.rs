@@ -1,43 +1,202 @@
 // SPDX-License-Identifier: MPL-2.0
 
-//! System volume control using PulseAudio/PipeWire via pactl
+//! System volume control, talking to PulseAudio (or PipeWire's PulseAudio
+//! server) through libpulse.
+//!
+//! Used when the volume slider is set to drive the whole desktop. The
+//! default, app-only mode just sets the volume of the player's own sink.
+//!
+//! One connection is kept open on a worker thread, so the UI doesn't wait on
+//! the sound server while the slider is dragged. Volume changes queued up
+//! behind a slow request are collapsed into the latest one.
 
-use std::process::Command;
+use libpulse_binding::callbacks::ListResult;
+use libpulse_binding::context::{Context, FlagSet, State};
+use libpulse_binding::mainloop::standard::{IterateResult, Mainloop};
+use libpulse_binding::operation::{Operation, State as OperationState};
+use libpulse_binding::volume::{ChannelVolumes, Volume};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
 
-/// Get the current system volume as a value between 0.0 and 1.0
-pub fn get_volume() -> Option<f32> {
-    let output = Command::new("pactl")
-        .args(["get-sink-volume", "@DEFAULT_SINK@"])
-        .output()
-        .ok()?;
-
-    if !output.status.success() {
-        return None;
-    }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    // Output format: "Volume: front-left: 65536 / 100% / 0.00 dB,   front-right: 65536 / 100% / 0.00 dB"
-    // We need to extract the percentage
-    for part in stdout.split('/') {
-        let trimmed = part.trim();
-        if trimmed.ends_with('%') {
-            if let Ok(percent) = trimmed.trim_end_matches('%').trim().parse::<u32>() {
-                return Some((percent as f32 / 100.0).clamp(0.0, 1.0));
+/// Name the sound server resolves to the current default output
+const DEFAULT_SINK: &str = "@DEFAULT_SINK@";
+
+/// How long `get_volume` waits for the worker before giving up
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A blocking connection to the sound server
+struct Connection {
+    // Declared first so the context is dropped before its mainloop
+    context: Context,
+    mainloop: Mainloop,
+}
+
+impl Connection {
+    fn open() -> Result<Self, String> {
+        let mut mainloop = Mainloop::new().ok_or("Failed to create PulseAudio mainloop")?;
+        let mut context = Context::new(&mainloop, "cosmic-soundcloud")
+            .ok_or("Failed to create PulseAudio context")?;
+        context
+            .connect(None, FlagSet::NOFLAGS, None)
+            .map_err(|e| format!("Failed to connect to PulseAudio: {e:?}"))?;
+
+        loop {
+            iterate(&mut mainloop)?;
+            match context.get_state() {
+                State::Ready => break,
+                State::Failed | State::Terminated => {
+                    return Err("PulseAudio connection failed".into());
+                }
+                _ => {}
             }
         }
+
+        Ok(Self { context, mainloop })
+    }
+
+    /// Run the mainloop until an operation completes
+    fn wait<F: ?Sized>(&mut self, operation: &Operation<F>) -> Result<(), String> {
+        while operation.get_state() == OperationState::Running {
+            iterate(&mut self.mainloop)?;
+        }
+        Ok(())
     }
 
-    None
+    /// Per-channel volume of the default sink
+    fn default_sink_volume(&mut self) -> Result<ChannelVolumes, String> {
+        let volume = Rc::new(RefCell::new(None));
+        let result = Rc::clone(&volume);
+        let operation = self
+            .context
+            .introspect()
+            .get_sink_info_by_name(DEFAULT_SINK, move |info| {
+                if let ListResult::Item(sink) = info {
+                    *result.borrow_mut() = Some(sink.volume);
+                }
+            });
+        self.wait(&operation)?;
+        volume.take().ok_or_else(|| "No default sink".to_string())
+    }
+
+    fn set_default_sink_volume(&mut self, volume: &ChannelVolumes) -> Result<(), String> {
+        let success = Rc::new(RefCell::new(false));
+        let result = Rc::clone(&success);
+        let operation = self.context.introspect().set_sink_volume_by_name(
+            DEFAULT_SINK,
+            volume,
+            Some(Box::new(move |ok| *result.borrow_mut() = ok)),
+        );
+        self.wait(&operation)?;
+        if success.take() {
+            Ok(())
+        } else {
+            Err("PulseAudio refused the volume change".into())
+        }
+    }
 }
 
-/// Set the system volume to a value between 0.0 and 1.0
-pub fn set_volume(volume: f32) -> bool {
-    let percent = (volume.clamp(0.0, 1.0) * 100.0).round() as u32;
-    let volume_str = format!("{percent}%");
+/// Block until the mainloop has handled the next batch of events
+fn iterate(mainloop: &mut Mainloop) -> Result<(), String> {
+    match mainloop.iterate(true) {
+        IterateResult::Success(_) => Ok(()),
+        IterateResult::Quit(_) => Err("PulseAudio mainloop quit".into()),
+        IterateResult::Err(e) => Err(format!("PulseAudio mainloop failed: {e:?}")),
+    }
+}
 
-    Command::new("pactl")
-        .args(["set-sink-volume", "@DEFAULT_SINK@", &volume_str])
-        .status()
-        .is_ok_and(|s| s.success())
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.context.disconnect();
+    }
 }
 
+/// Requests handled by the worker thread
+enum Request {
+    Get(Sender<Option<f32>>),
+    Set(f32),
+}
+
+/// Channel to the worker thread, started on first use
+fn worker() -> &'static Sender<Request> {
+    static WORKER: OnceLock<Sender<Request>> = OnceLock::new();
+    WORKER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("system-volume".into())
+            .spawn(move || serve(rx))
+            .inspect_err(|e| eprintln!("[volume] Failed to start worker thread: {e}"))
+            .ok();
+        tx
+    })
+}
+
+/// Worker thread: handle requests over one connection, reconnecting after
+/// an error, until the app exits
+fn serve(requests: Receiver<Request>) {
+    let mut connection = None;
+    while let Ok(first) = requests.recv() {
+        let mut batch = vec![first];
+        batch.extend(requests.try_iter());
+        // Only the latest volume matters when several are waiting
+        let last_set = batch.iter().rposition(|request| matches!(request, Request::Set(_)));
+        for (index, request) in batch.into_iter().enumerate() {
+            match request {
+                Request::Get(reply) => {
+                    let volume = with_connection(&mut connection, Connection::default_sink_volume)
+                        .inspect_err(|e| eprintln!("[volume] {e}"))
+                        .ok()
+                        .map(|volume| {
+                            let level = volume.avg().0 as f32 / Volume::NORMAL.0 as f32;
+                            level.clamp(0.0, 1.0)
+                        });
+                    let _ = reply.send(volume);
+                }
+                Request::Set(volume) if Some(index) == last_set => {
+                    let level = Volume((volume.clamp(0.0, 1.0) * Volume::NORMAL.0 as f32).round() as u32);
+                    let _ = with_connection(&mut connection, |connection| {
+                        // Keep the sink's channel layout, setting every channel alike
+                        let mut channels = connection.default_sink_volume()?;
+                        let count = channels.len();
+                        channels.set(count, level);
+                        connection.set_default_sink_volume(&channels)
+                    })
+                    .inspect_err(|e| eprintln!("[volume] {e}"));
+                }
+                Request::Set(_) => {}
+            }
+        }
+    }
+}
+
+/// Run `f` on the open connection, connecting first if there's none. The
+/// connection is dropped if `f` fails, so the next request starts afresh.
+fn with_connection<T>(
+    connection: &mut Option<Connection>,
+    f: impl FnOnce(&mut Connection) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut open = match connection.take() {
+        Some(open) => open,
+        None => Connection::open()?,
+    };
+    let result = f(&mut open);
+    if result.is_ok() {
+        *connection = Some(open);
+    }
+    result
+}
+
+/// Get the current system volume as a value between 0.0 and 1.0
+pub fn get_volume() -> Option<f32> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    worker().send(Request::Get(reply_tx)).ok()?;
+    reply_rx.recv_timeout(REPLY_TIMEOUT).ok().flatten()
+}
+
+/// Set the system volume to a value between 0.0 and 1.0. Returns straight
+/// away; the worker applies it.
+pub fn set_volume(volume: f32) {
+    let _ = worker().send(Request::Set(volume));
+}
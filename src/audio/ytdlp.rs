@@ -4,34 +4,232 @@
 //!
 //! SoundCloud provides unencrypted streams to certain clients, which yt-dlp can extract.
 //! This bypasses the DRM-encrypted streams that the official API returns.
+//!
+//! yt-dlp runs as a child process under a timeout, and is killed when the
+//! extraction is cancelled or times out. Its `-J` output describes the format
+//! it picked, so the stream's codec, bitrate and expiry are known before
+//! playback starts.
+
+use crate::api::AudioCodec;
+use serde::Deserialize;
+use std::fmt;
+use std::io::ErrorKind;
+use std::process::Stdio;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+
+/// Binary looked up on `PATH` when no path is configured
+const DEFAULT_BINARY: &str = "yt-dlp";
+
+/// How long an extraction may take before yt-dlp is killed
+const EXTRACT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long `--version` may take
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Best audio-only format, avoiding Opus as there's no decoder for it
+const FORMAT: &str = "bestaudio[acodec!=opus]/bestaudio";
+
+/// Why yt-dlp couldn't provide a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum YtDlpError {
+    /// The binary doesn't exist or can't be executed
+    Missing(String),
+    /// yt-dlp didn't finish in time and was killed
+    TimedOut,
+    /// yt-dlp ran and failed, with its error output
+    Failed(String),
+    /// yt-dlp's output couldn't be understood
+    InvalidOutput(String),
+}
 
-use std::process::Command;
+impl fmt::Display for YtDlpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(binary) => write!(f, "yt-dlp not found ({binary})"),
+            Self::TimedOut => write!(f, "yt-dlp timed out"),
+            Self::Failed(stderr) => write!(f, "yt-dlp failed: {stderr}"),
+            Self::InvalidOutput(e) => write!(f, "unexpected yt-dlp output: {e}"),
+        }
+    }
+}
+
+/// A stream yt-dlp extracted
+#[derive(Debug, Clone)]
+pub struct Extracted {
+    pub url: String,
+    /// yt-dlp's name for the format, e.g. `hls_aac_160k`
+    pub format_id: Option<String>,
+    pub codec: Option<AudioCodec>,
+    /// Average audio bitrate in bits per second
+    pub bitrate: Option<u64>,
+    /// When the signed URL stops working, if it says
+    pub expires: Option<SystemTime>,
+}
 
-/// Extract a playable audio URL using yt-dlp
-pub fn extract_stream_url(track_url: &str) -> Result<String, String> {
-    // Check if yt-dlp is available
-    let output = Command::new("yt-dlp")
-        .args([
-            "-f", "bestaudio",
-            "--get-url",
-            "--no-warnings",
-            track_url,
-        ])
-        .output()
-        .map_err(|e| format!("Failed to run yt-dlp: {e}"))?;
+/// The yt-dlp binary to run
+#[derive(Debug, Clone)]
+pub struct YtDlp {
+    binary: String,
+}
+
+impl YtDlp {
+    /// `binary` is a path or a name on `PATH`; unset or empty means `yt-dlp`
+    pub fn new(binary: Option<&str>) -> Self {
+        let binary = binary
+            .map(str::trim)
+            .filter(|binary| !binary.is_empty())
+            .unwrap_or(DEFAULT_BINARY);
+        Self {
+            binary: binary.to_string(),
+        }
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("yt-dlp failed: {stderr}"));
+    /// Version of the installed yt-dlp, which also tells whether it runs at all
+    pub async fn version(&self) -> Result<String, YtDlpError> {
+        let stdout = self.run(&["--version"], VERSION_TIMEOUT).await?;
+        let version = String::from_utf8_lossy(&stdout)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if version.is_empty() {
+            return Err(YtDlpError::InvalidOutput("empty version".into()));
+        }
+        Ok(version)
     }
 
-    let url = String::from_utf8_lossy(&output.stdout)
-        .trim()
-        .to_string();
+    /// Extract the best audio stream from a track page
+    pub async fn extract(&self, track_url: &str) -> Result<Extracted, YtDlpError> {
+        let stdout = self
+            .run(
+                &["-J", "-f", FORMAT, "--no-playlist", "--no-warnings", track_url],
+                EXTRACT_TIMEOUT,
+            )
+            .await?;
+        let info: Info =
+            serde_json::from_slice(&stdout).map_err(|e| YtDlpError::InvalidOutput(e.to_string()))?;
+
+        // The chosen format's fields sit at the top level, unless yt-dlp
+        // picked several formats to merge
+        let format = match info.format.url {
+            Some(_) => info.format,
+            None => info
+                .requested_formats
+                .into_iter()
+                .find(|format| format.url.is_some())
+                .ok_or_else(|| YtDlpError::InvalidOutput("no stream URL".into()))?,
+        };
+        let url = format.url.unwrap_or_default();
+        Ok(Extracted {
+            codec: codec(format.acodec.as_deref(), format.ext.as_deref()),
+            bitrate: format.abr.filter(|abr| *abr > 0.0).map(|abr| (abr * 1000.0) as u64),
+            expires: expiry(&url),
+            format_id: format.format_id,
+            url,
+        })
+    }
+
+    /// Run yt-dlp with `args` and return its output. Dropping the future
+    /// kills the process.
+    async fn run(&self, args: &[&str], timeout: Duration) -> Result<Vec<u8>, YtDlpError> {
+        let output = Command::new(&self.binary)
+            .args(args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = match tokio::time::timeout(timeout, output).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied) => {
+                return Err(YtDlpError::Missing(self.binary.clone()));
+            }
+            Ok(Err(e)) => return Err(YtDlpError::Failed(e.to_string())),
+            Err(_) => return Err(YtDlpError::TimedOut),
+        };
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(YtDlpError::Failed(stderr));
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// The parts of yt-dlp's `-J` info dict we use
+#[derive(Deserialize)]
+struct Info {
+    #[serde(flatten)]
+    format: FormatInfo,
+    #[serde(default)]
+    requested_formats: Vec<FormatInfo>,
+}
+
+#[derive(Deserialize)]
+struct FormatInfo {
+    url: Option<String>,
+    format_id: Option<String>,
+    ext: Option<String>,
+    acodec: Option<String>,
+    /// Average audio bitrate in kbps
+    abr: Option<f64>,
+}
+
+/// Codec from yt-dlp's `acodec`, or the file extension when that's missing
+fn codec(acodec: Option<&str>, ext: Option<&str>) -> Option<AudioCodec> {
+    let name = acodec.filter(|codec| *codec != "none").or(ext)?;
+    match name {
+        "mp3" => Some(AudioCodec::Mp3),
+        "opus" => Some(AudioCodec::Opus),
+        "aac" | "m4a" => Some(AudioCodec::Aac),
+        name if name.starts_with("mp4a") => Some(AudioCodec::Aac),
+        _ => None,
+    }
+}
+
+/// Expiry of a signed CDN URL, from an `expires` parameter or a CloudFront
+/// policy
+fn expiry(url: &str) -> Option<SystemTime> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let mut seconds = None;
+    for (key, value) in url.query_pairs() {
+        if key.eq_ignore_ascii_case("expires") {
+            seconds = value.parse::<u64>().ok();
+        } else if key == "Policy" {
+            seconds = policy_expiry(&value);
+        }
+        if seconds.is_some() {
+            break;
+        }
+    }
+    seconds.map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+}
 
-    if url.is_empty() {
-        return Err("yt-dlp returned empty URL".into());
+/// `DateLessThan` of a CloudFront policy, which is JSON in CloudFront's
+/// URL-safe base64 (`-`, `~` and `_` for `+`, `/` and `=`)
+fn policy_expiry(policy: &str) -> Option<u64> {
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    let mut json = Vec::with_capacity(policy.len() * 3 / 4);
+    for c in policy.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'~' => 63,
+            b'=' | b'_' => break,
+            _ => return None,
+        };
+        // Only the bits of the byte being assembled are needed
+        bits = ((bits << 6) | u32::from(value)) & 0xFFFF;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            json.push((bits >> bit_count) as u8);
+        }
     }
 
-    Ok(url)
+    let policy: serde_json::Value = serde_json::from_slice(&json).ok()?;
+    policy["Statement"][0]["Condition"]["DateLessThan"]["AWS:EpochTime"].as_u64()
 }
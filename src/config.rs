@@ -1,5 +1,7 @@
 // SPDX-License-Identifier: MPL-2.0
 
+use crate::api::StreamPreference;
+use crate::audio::{BAND_COUNT, ResolverKind};
 use cosmic::cosmic_config::{self, CosmicConfigEntry, cosmic_config_derive::CosmicConfigEntry};
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +14,92 @@ pub enum RepeatMode {
     All,
 }
 
+/// What the volume slider controls
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum VolumeMode {
+    /// Only this app's output
+    #[default]
+    App,
+    /// The system's default output device, like the desktop volume
+    System,
+}
+
+impl VolumeMode {
+    pub fn all() -> &'static [VolumeMode] {
+        &[Self::App, Self::System]
+    }
+
+    pub fn all_labels() -> &'static [&'static str] {
+        &["This app", "System"]
+    }
+}
+
+/// Loudness normalization mode
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum NormalizationMode {
+    #[default]
+    Off,
+    /// Every track is brought to the same loudness
+    Track,
+    /// Albums share one gain, keeping their quiet and loud tracks intact
+    Album,
+}
+
+impl NormalizationMode {
+    pub fn all() -> &'static [NormalizationMode] {
+        &[Self::Off, Self::Track, Self::Album]
+    }
+
+    pub fn all_labels() -> &'static [&'static str] {
+        &["Off", "Track", "Album"]
+    }
+}
+
+/// Equalizer curve selection
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum EqualizerPreset {
+    #[default]
+    Flat,
+    BassBoost,
+    TrebleBoost,
+    Vocal,
+    Rock,
+    Electronic,
+    /// The user's own curve, stored in `Config::equalizer_custom`
+    Custom,
+}
+
+impl EqualizerPreset {
+    pub fn all() -> &'static [EqualizerPreset] {
+        &[
+            Self::Flat,
+            Self::BassBoost,
+            Self::TrebleBoost,
+            Self::Vocal,
+            Self::Rock,
+            Self::Electronic,
+            Self::Custom,
+        ]
+    }
+
+    pub fn all_labels() -> &'static [&'static str] {
+        &["Flat", "Bass Boost", "Treble Boost", "Vocal", "Rock", "Electronic", "Custom"]
+    }
+
+    /// Band gains (dB) of a built-in preset, or `None` for the custom curve
+    pub fn gains(self) -> Option<[f32; BAND_COUNT]> {
+        match self {
+            Self::Flat => Some([0.0; BAND_COUNT]),
+            Self::BassBoost => Some([6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+            Self::TrebleBoost => Some([0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 4.0, 5.0, 6.0]),
+            Self::Vocal => Some([-2.0, -2.0, -1.0, 0.0, 2.0, 4.0, 4.0, 2.0, 0.0, -1.0]),
+            Self::Rock => Some([4.0, 3.0, 2.0, 0.0, -1.0, -1.0, 1.0, 2.0, 3.0, 4.0]),
+            Self::Electronic => Some([5.0, 4.0, 1.0, 0.0, -2.0, 0.0, 1.0, 2.0, 4.0, 5.0]),
+            Self::Custom => None,
+        }
+    }
+}
+
 /// A recently viewed artist for quick navigation
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RecentArtist {
@@ -27,14 +115,36 @@ pub struct Config {
     /// DEPRECATED: Token is now stored in system keyring for security.
     /// This field is kept for migration from older versions.
     pub oauth_token: Option<String>,
-    /// Volume level (0.0 - 1.0)
+    /// Volume level (0.0 - 1.0) in app volume mode
     pub volume: f32,
+    /// Whether the volume slider controls this app or the whole system
+    pub volume_mode: VolumeMode,
     /// Shuffle mode enabled
     pub shuffle: bool,
     /// Repeat mode
     pub repeat_mode: RepeatMode,
     /// Recently viewed artists (max 10)
     pub recent_artists: Vec<RecentArtist>,
+    /// Crossfade duration between tracks in seconds (0 = off, max 12)
+    pub crossfade_secs: f32,
+    /// Loudness normalization mode
+    pub normalization: NormalizationMode,
+    /// Selected equalizer preset
+    pub equalizer_preset: EqualizerPreset,
+    /// Band gains (dB) of the custom equalizer curve
+    pub equalizer_custom: [f32; BAND_COUNT],
+    /// Which variant of a multi-variant HLS stream to play
+    pub stream_preference: StreamPreference,
+    /// Name of the audio output device (None = system default)
+    pub output_device: Option<String>,
+    /// Disk space for cached tracks in MiB
+    pub cache_limit_mb: u32,
+    /// Where to look for a track's audio, in order
+    pub stream_sources: Vec<ResolverKind>,
+    /// Path of the yt-dlp binary (None = `yt-dlp` on PATH)
+    pub ytdlp_path: Option<String>,
+    /// Folder exported tracks are saved to (None = the music folder)
+    pub export_dir: Option<String>,
 }
 
 impl Default for Config {
@@ -42,10 +152,28 @@ impl Default for Config {
         Self {
             oauth_token: None,
             volume: 0.8,
+            volume_mode: VolumeMode::App,
             shuffle: false,
             repeat_mode: RepeatMode::None,
             recent_artists: Vec::new(),
+            crossfade_secs: 0.0,
+            normalization: NormalizationMode::Off,
+            equalizer_preset: EqualizerPreset::Flat,
+            equalizer_custom: [0.0; BAND_COUNT],
+            stream_preference: StreamPreference::HighestQuality,
+            output_device: None,
+            cache_limit_mb: 1024,
+            stream_sources: ResolverKind::default_order(),
+            ytdlp_path: None,
+            export_dir: None,
         }
     }
 }
 
+impl Config {
+    /// Band gains (dB) of the selected equalizer curve
+    pub fn equalizer_gains(&self) -> [f32; BAND_COUNT] {
+        self.equalizer_preset.gains().unwrap_or(self.equalizer_custom)
+    }
+}
+